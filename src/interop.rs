@@ -1,5 +1,5 @@
 use mlua::prelude::*;
-use crate::{table_helpers::TableBuilder, LuaValueResult, colors, wrap_err};
+use crate::{table_helpers::TableBuilder, LuaValueResult, colors};
 
 fn interop_mlua_isint(_luau: &Lua, n: LuaValue) -> LuaValueResult {
     match n {
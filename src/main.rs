@@ -8,6 +8,13 @@ mod std_io_output;
 mod std_fs;
 mod std_fs_pathlib;
 mod std_process;
+mod std_process_child;
+mod std_process_info;
+mod std_process_pipeline;
+mod std_process_pty;
+mod std_process_shellwords;
+mod std_process_signals;
+mod std_process_supervise;
 mod std_env;
 mod std_json;
 mod std_time;
@@ -17,9 +24,25 @@ mod std_io;
 mod std_io_colors;
 mod std_io_input;
 mod std_net;
+mod std_net_dns;
+mod std_net_events;
 mod std_net_http;
+mod std_net_ip;
 mod std_net_serve;
+mod std_net_serve_lifecycle;
+mod std_net_serve_router;
+mod std_net_serve_static;
+mod std_net_serve_tls;
+mod std_net_tcp;
+mod std_net_udp;
+mod std_net_serve_request;
+mod std_net_url;
+mod std_net_websocket;
 mod std_thread;
+mod std_thread_channel;
+mod std_thread_pool;
+mod std_thread_serialize;
+mod std_thread_sync;
 mod std_serde;
 mod std_crypt;
 mod std_testing;
@@ -35,6 +58,7 @@ const TYPEDEFS_DIR: Dir = include_dir!(".typedefs");
 type LuaValueResult = LuaResult<LuaValue>;
 
 fn main() -> LuaResult<()> {
+    std_process_info::record_start();
     let args: Vec<String> = env::args().collect();
 
     if args.len() == 3 && args[2] == "--debug" {
@@ -163,6 +187,7 @@ fn main() -> LuaResult<()> {
             Ok(())
         },
         Err(err) => {
+            std_process_supervise::stop_all();
             // let replace_main_re = Regex::new(r#"\[string \"[^\"]+\"\]"#).unwrap();
             let mut err_message = error_handling::parse_traceback(err.to_string());
             let script: LuaTable = globals.get("script")?;
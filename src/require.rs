@@ -80,9 +80,15 @@ fn get_standard_library(luau: &Lua, path: String) -> LuaValueResult {
         "@std/json" => ok_table(std_json::create(luau)),
 
         "@std/net" => ok_table(std_net::create(luau)),
+        "@std/net/dns" => ok_table(std_net_dns::create(luau)),
         "@std/net/http" => ok_table(std_net_http::create(luau)),
         "@std/net/http/server" => ok_table(std_net_serve::create(luau)),
+        "@std/net/ip" => ok_table(std_net_ip::create(luau)),
         "@std/net/request" => ok_function(std_net_http::http_request, luau),
+        "@std/net/tcp" => ok_table(std_net_tcp::create(luau)),
+        "@std/net/udp" => ok_table(std_net_udp::create(luau)),
+        "@std/net/url" => ok_table(std_net_url::create(luau)),
+        "@std/net/websocket" => ok_table(std_net_websocket::create(luau)),
 
         "@std/crypt" => ok_table(std_crypt::create(luau)),
         "@std/crypt/aes" => ok_table(std_crypt::create_aes(luau)),
@@ -122,7 +128,7 @@ fn get_standard_library(luau: &Lua, path: String) -> LuaValueResult {
     }
 }
 
-fn resolve_path(luau: &Lua, path: String) -> LuaResult<String> {
+pub fn resolve_path(luau: &Lua, path: String) -> LuaResult<String> {
     let require_resolver = include_str!("./scripts/require_resolver.luau");
     let r: LuaFunction = luau.load(require_resolver).eval()?;
     match r.call::<LuaValue>(path.to_owned()) {
@@ -1,9 +1,16 @@
 use mlua::prelude::*;
 
-use crate::{std_net_http, table_helpers::TableBuilder};
+use crate::{std_net_dns, std_net_http, std_net_ip, std_net_tcp, std_net_udp, std_net_url, std_net_websocket, table_helpers::TableBuilder};
 
 pub fn create(luau: &Lua) -> LuaResult<LuaTable> {
     TableBuilder::create(luau)?
         .with_value("http", std_net_http::create(luau)?)?
+        .with_value("dns", std_net_dns::create(luau)?)?
+        .with_value("ip", std_net_ip::create(luau)?)?
+        .with_function("interfaces", std_net_ip::net_interfaces)?
+        .with_value("tcp", std_net_tcp::create(luau)?)?
+        .with_value("udp", std_net_udp::create(luau)?)?
+        .with_value("url", std_net_url::create(luau)?)?
+        .with_value("websocket", std_net_websocket::create(luau)?)?
         .build_readonly()
 }
@@ -0,0 +1,84 @@
+use crate::{colors, table_helpers::TableBuilder, LuaValueResult};
+use mlua::prelude::*;
+use std::net::IpAddr;
+
+/// `dns.lookup(host: string, family: ("ipv4" | "ipv6")?)`; goes through the system resolver, so
+/// `/etc/hosts` entries and "localhost" resolve without a network
+fn dns_lookup(luau: &Lua, mut multivalue: LuaMultiValue) -> LuaValueResult {
+    let host = match multivalue.pop_front() {
+        Some(LuaValue::String(host)) => host.to_string_lossy(),
+        other => {
+            return wrap_err!("dns.lookup(host: string, family: (\"ipv4\" | \"ipv6\")?) expected host to be a string, got: {:#?}", other);
+        }
+    };
+    let family = match multivalue.pop_front() {
+        None | Some(LuaNil) => None,
+        Some(LuaValue::String(family)) => match family.to_str()?.as_ref() {
+            "ipv4" => Some(4),
+            "ipv6" => Some(6),
+            other => {
+                return wrap_err!("dns.lookup expected family to be \"ipv4\" or \"ipv6\", got: '{}'", other);
+            }
+        },
+        Some(other) => {
+            return wrap_err!("dns.lookup expected family to be \"ipv4\", \"ipv6\", or nil, got: {:#?}", other);
+        }
+    };
+
+    let addresses = match dns_lookup::lookup_host(&host) {
+        Ok(addresses) => addresses,
+        Err(err) => {
+            return wrap_err!("dns.lookup: unable to resolve '{}': {}", host, err);
+        }
+    };
+
+    // getaddrinfo hands back one entry per socket type, so the same address shows up several times
+    let mut unique: Vec<IpAddr> = Vec::new();
+    for address in addresses {
+        let wanted = match family {
+            Some(4) => address.is_ipv4(),
+            Some(6) => address.is_ipv6(),
+            _ => true,
+        };
+        if wanted && !unique.contains(&address) {
+            unique.push(address);
+        }
+    }
+
+    let result = luau.create_table()?;
+    for address in unique {
+        result.raw_push(address.to_string())?;
+    }
+    Ok(LuaValue::Table(result))
+}
+
+/// `dns.reverse(ip: string)`, returning nil when the address has no name
+fn dns_reverse(luau: &Lua, ip: LuaValue) -> LuaValueResult {
+    let ip = match ip {
+        LuaValue::String(ip) => ip.to_string_lossy(),
+        other => {
+            return wrap_err!("dns.reverse(ip: string) expected ip to be a string, got: {:#?}", other);
+        }
+    };
+    let address: IpAddr = match ip.parse() {
+        Ok(address) => address,
+        Err(_) => {
+            return wrap_err!("dns.reverse: '{}' isn't a valid IPv4 or IPv6 address", ip);
+        }
+    };
+    match dns_lookup::lookup_addr(&address) {
+        // getnameinfo falls back to the numeric address when there's no name for it
+        Ok(name) if name.parse::<IpAddr>().is_ok() => Ok(LuaNil),
+        Ok(name) => Ok(LuaValue::String(luau.create_string(name)?)),
+        Err(err) => {
+            wrap_err!("dns.reverse: unable to look up '{}': {}", ip, err)
+        }
+    }
+}
+
+pub fn create(luau: &Lua) -> LuaResult<LuaTable> {
+    TableBuilder::create(luau)?
+        .with_function("lookup", dns_lookup)?
+        .with_function("reverse", dns_reverse)?
+        .build_readonly()
+}
@@ -0,0 +1,449 @@
+use crate::{colors, std_json, table_helpers::TableBuilder, LuaValueResult};
+use crate::std_net_serve_request::ServeRequest;
+use crate::std_net_websocket::SocketStream;
+use mlua::prelude::*;
+use std::cell::RefCell;
+use std::io::{self, BufRead, BufReader, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// how often we send a keep-alive comment on an otherwise quiet stream, so proxies don't time it out
+const DEFAULT_KEEP_ALIVE_INTERVAL: f64 = 15.0;
+/// how often we check whether the client has hung up
+const DISCONNECT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+struct EventWriter<S> {
+    stream: S,
+    last_write: Instant,
+}
+
+impl<S: Write> EventWriter<S> {
+    fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.stream.write_all(frame)?;
+        self.stream.flush()?;
+        self.last_write = Instant::now();
+        Ok(())
+    }
+}
+
+/// the server side of an event stream, shared between the script and the keep-alive thread
+struct EventStream<S> {
+    writer: Mutex<EventWriter<S>>,
+    open: AtomicBool,
+}
+
+impl<S: Write> EventStream<S> {
+    fn is_open(&self) -> bool {
+        self.open.load(Ordering::SeqCst)
+    }
+
+    fn close(&self) {
+        self.open.store(false, Ordering::SeqCst);
+    }
+
+    /// writes a frame if the client's still there, returning whether it was sent
+    fn send(&self, frame: &[u8]) -> bool {
+        if !self.is_open() {
+            return false;
+        }
+        let mut writer = self.writer.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if writer.write_frame(frame).is_err() {
+            self.close();
+            return false;
+        }
+        true
+    }
+}
+
+/// `event:` and `id:` values end at the first newline, so they can't contain one
+fn single_line_field(event: &LuaTable, field: &str) -> LuaResult<Option<String>> {
+    match event.raw_get(field)? {
+        LuaNil => Ok(None),
+        LuaValue::String(value) => {
+            let value = value.to_string_lossy();
+            if value.contains(['\n', '\r']) {
+                wrap_err!("EventStream:send: event.{} can't contain newlines, got: {:?}", field, value)
+            } else {
+                Ok(Some(value))
+            }
+        },
+        LuaValue::Integer(n) => Ok(Some(n.to_string())),
+        LuaValue::Number(n) => Ok(Some(n.to_string())),
+        other => {
+            wrap_err!("EventStream:send expected event.{} to be a string, got: {:#?}", field, other)
+        }
+    }
+}
+
+fn event_data(luau: &Lua, data: LuaValue) -> LuaResult<String> {
+    match data {
+        LuaNil => Ok(String::new()),
+        LuaValue::String(data) => Ok(data.to_string_lossy()),
+        LuaValue::Integer(n) => Ok(n.to_string()),
+        LuaValue::Number(n) => Ok(n.to_string()),
+        LuaValue::Boolean(b) => Ok(b.to_string()),
+        data @ LuaValue::Table(_) => std_json::json_encode_raw(luau, data),
+        other => {
+            wrap_err!("EventStream:send expected data to be a string, number, boolean, or json-serializable table, got: {:#?}", other)
+        }
+    }
+}
+
+/// formats an event as `event:`/`id:`/`retry:`/`data:` lines ending in a blank line; multiline data
+/// gets one `data:` line per line, which clients join back together with newlines
+fn format_event(luau: &Lua, event: LuaValue) -> LuaResult<Vec<u8>> {
+    let mut frame = String::new();
+    let data = match event {
+        LuaValue::Table(event) => {
+            if let Some(name) = single_line_field(&event, "event")? {
+                frame.push_str(&format!("event: {}\n", name));
+            }
+            if let Some(id) = single_line_field(&event, "id")? {
+                frame.push_str(&format!("id: {}\n", id));
+            }
+            match event.raw_get("retry")? {
+                LuaNil => {},
+                LuaValue::Integer(retry) if retry >= 0 => frame.push_str(&format!("retry: {}\n", retry)),
+                LuaValue::Number(retry) if retry >= 0.0 => frame.push_str(&format!("retry: {}\n", retry as u64)),
+                other => {
+                    return wrap_err!("EventStream:send expected event.retry to be a non-negative number of milliseconds, got: {:#?}", other);
+                }
+            }
+            event_data(luau, event.raw_get("data")?)?
+        },
+        other => event_data(luau, other)?,
+    };
+    for line in data.split("\r\n").flat_map(|line| line.split(['\n', '\r'])) {
+        frame.push_str(&format!("data: {}\n", line));
+    }
+    frame.push('\n');
+    Ok(frame.into_bytes())
+}
+
+fn format_comment(comment: &str) -> Vec<u8> {
+    let mut frame = String::new();
+    for line in comment.split("\r\n").flat_map(|line| line.split(['\n', '\r'])) {
+        frame.push_str(&format!(": {}\n", line));
+    }
+    frame.push('\n');
+    frame.into_bytes()
+}
+
+/// sends keep-alive comments while the stream's quiet, and notices when the client hangs up
+fn watch_stream<S: SocketStream + Send + 'static>(events: Arc<EventStream<S>>, keep_alive_interval: Duration) -> Option<thread::JoinHandle<()>> {
+    let socket = {
+        let writer = events.writer.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        writer.stream.tcp_stream()?.try_clone().ok()?
+    };
+    // we never read from the stream otherwise, so its read timeout is ours to use as a poll interval
+    socket.set_read_timeout(Some(DISCONNECT_POLL_INTERVAL)).ok()?;
+    Some(thread::spawn(move || {
+        let mut peeked = [0u8; 1];
+        while events.is_open() {
+            match socket.peek(&mut peeked) {
+                // the client closed its side of the connection
+                Ok(0) => break,
+                // clients don't send anything on an event stream, but if one does we just ignore it
+                Ok(_) => thread::sleep(DISCONNECT_POLL_INTERVAL),
+                Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {},
+                Err(_) => break,
+            }
+            let quiet_for = events.writer.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).last_write.elapsed();
+            if quiet_for >= keep_alive_interval && !events.send(&format_comment("keep-alive")) {
+                break;
+            }
+        }
+        events.close();
+    }))
+}
+
+fn create_stream_table<S: SocketStream + Send + 'static>(luau: &Lua, events: &Arc<EventStream<S>>) -> LuaResult<LuaTable> {
+    TableBuilder::create(luau)?
+        .with_function("send", {
+            let events = Arc::clone(events);
+            move |luau: &Lua, mut multivalue: LuaMultiValue| -> LuaResult<bool> {
+                let _s = multivalue.pop_front();
+                let frame = match multivalue.pop_front() {
+                    Some(event) => format_event(luau, event)?,
+                    None => {
+                        return wrap_err!("EventStream:send(event: string | ServerEvent) expected event, got nothing");
+                    }
+                };
+                Ok(events.send(&frame))
+            }
+        })?
+        .with_function("comment", {
+            let events = Arc::clone(events);
+            move |_luau: &Lua, mut multivalue: LuaMultiValue| -> LuaResult<bool> {
+                let _s = multivalue.pop_front();
+                let comment = match multivalue.pop_front() {
+                    None | Some(LuaNil) => String::new(),
+                    Some(LuaValue::String(comment)) => comment.to_string_lossy(),
+                    Some(other) => {
+                        return wrap_err!("EventStream:comment(text: string?) expected text to be a string or nil, got: {:#?}", other);
+                    }
+                };
+                Ok(events.send(&format_comment(&comment)))
+            }
+        })?
+        .with_function("is_open", {
+            let events = Arc::clone(events);
+            move |_luau: &Lua, _multivalue: LuaMultiValue| -> LuaResult<bool> {
+                Ok(events.is_open())
+            }
+        })?
+        .with_function("close", {
+            let events = Arc::clone(events);
+            move |_luau: &Lua, _multivalue: LuaMultiValue| -> LuaValueResult {
+                events.close();
+                Ok(LuaNil)
+            }
+        })?
+        .build_readonly()
+}
+
+/// writes the event stream's headers and hands the stream to the handler's callback, ending the
+/// response (and the connection) when the callback returns
+pub fn serve_events<S: SocketStream + Send + 'static>(
+    luau: &Lua,
+    reader: BufReader<S>,
+    request: &ServeRequest,
+    request_table: LuaTable,
+    serve_response: LuaTable,
+    on_stream: LuaFunction,
+) {
+    let mut head = String::from("HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nX-Accel-Buffering: no\r\nConnection: close\r\n");
+    if let Ok(LuaValue::Table(headers)) = serve_response.raw_get("headers") {
+        for (key, value) in headers.pairs::<LuaString, LuaString>().flatten() {
+            let (key, value) = (key.to_string_lossy(), value.to_string_lossy());
+            let managed = ["content-type", "content-length", "transfer-encoding", "connection", "cache-control"]
+                .iter().any(|managed| key.eq_ignore_ascii_case(managed));
+            if !managed {
+                head.push_str(&format!("{}: {}\r\n", key, value));
+            }
+        }
+    }
+    head.push_str("\r\n");
+
+    let keep_alive_interval = match serve_response.raw_get("keep_alive_interval") {
+        Ok(LuaValue::Integer(seconds)) if seconds > 0 => Duration::from_secs(seconds as u64),
+        Ok(LuaValue::Number(seconds)) if seconds > 0.0 => Duration::from_secs_f64(seconds),
+        _ => Duration::from_secs_f64(DEFAULT_KEEP_ALIVE_INTERVAL),
+    };
+
+    let mut writer = EventWriter { stream: reader.into_inner(), last_write: Instant::now() };
+    if writer.write_frame(head.as_bytes()).is_err() {
+        return;
+    }
+    let events = Arc::new(EventStream { writer: Mutex::new(writer), open: AtomicBool::new(true) });
+    if let Ok(LuaValue::Integer(retry)) = serve_response.raw_get("retry") {
+        events.send(format!("retry: {}\n\n", retry).as_bytes());
+    }
+    let watcher = watch_stream(Arc::clone(&events), keep_alive_interval);
+
+    let result = create_stream_table(luau, &events)
+        .and_then(|stream_table| on_stream.call::<LuaValue>((stream_table, request_table)));
+    if let Err(err) = result {
+        eprintln!("{}[ERR]{}{} server.serve: event stream handler failed on {}, ending the stream:\n{}{}",
+            colors::BOLD_RED, colors::RESET, colors::RED, request.path(), err, colors::RESET);
+    }
+
+    events.close();
+    if let Some(watcher) = watcher {
+        let _ = watcher.join();
+    }
+    // the stream table might still be hanging around in Luau, but it can't write once we're closed
+    events.writer.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).stream.shutdown();
+}
+
+/// `server.events(handler: (stream, request) -> (), options: EventStreamOptions?)`: a response that
+/// keeps the connection open as a `text/event-stream`
+pub fn server_events(luau: &Lua, mut multivalue: LuaMultiValue) -> LuaValueResult {
+    let on_stream = match multivalue.pop_front() {
+        Some(LuaValue::Function(on_stream)) => on_stream,
+        other => {
+            return wrap_err!("server.events(handler: (stream: EventStream, request: ServeRequest) -> (), options: EventStreamOptions?) expected handler to be a function, got: {:#?}", other);
+        }
+    };
+    let response = TableBuilder::create(luau)?
+        .with_value("status_code", "200 OK")?
+        .with_value("content_type", "text/event-stream")?
+        .with_value("body", "")?
+        .with_value("events", on_stream)?
+        .build()?;
+    match multivalue.pop_front() {
+        None | Some(LuaNil) => {},
+        Some(LuaValue::Table(options)) => {
+            match options.raw_get("keep_alive_interval")? {
+                LuaNil => {},
+                seconds @ (LuaValue::Integer(_) | LuaValue::Number(_)) => response.raw_set("keep_alive_interval", seconds)?,
+                other => {
+                    return wrap_err!("server.events expected options.keep_alive_interval to be a number of seconds, got: {:#?}", other);
+                }
+            }
+            match options.raw_get("retry")? {
+                LuaNil => {},
+                LuaValue::Integer(retry) if retry >= 0 => response.raw_set("retry", retry)?,
+                other => {
+                    return wrap_err!("server.events expected options.retry to be a non-negative integer number of milliseconds, got: {:#?}", other);
+                }
+            }
+        },
+        Some(other) => {
+            return wrap_err!("server.events expected options to be an EventStreamOptions table or nil, got: {:#?}", other);
+        }
+    }
+    Ok(LuaValue::Table(response))
+}
+
+/// parses a `text/event-stream` the way browsers' EventSource does
+struct EventParser<R> {
+    reader: R,
+    last_event_id: Option<String>,
+    started: bool,
+}
+
+struct ParsedEvent {
+    event: String,
+    data: String,
+    id: Option<String>,
+    retry: Option<u64>,
+}
+
+impl<R: BufRead> EventParser<R> {
+    fn next_event(&mut self) -> io::Result<Option<ParsedEvent>> {
+        let mut event_type = String::new();
+        let mut data = String::new();
+        let mut retry = None;
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            // an event that's cut off by the stream ending doesn't get dispatched
+            if self.reader.read_until(b'\n', &mut line)? == 0 {
+                return Ok(None);
+            }
+            if line.ends_with(b"\n") {
+                line.pop();
+            }
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+            let mut text = String::from_utf8_lossy(&line).into_owned();
+            if !self.started {
+                self.started = true;
+                if let Some(stripped) = text.strip_prefix('\u{feff}') {
+                    text = stripped.to_string();
+                }
+            }
+
+            if text.is_empty() {
+                if data.is_empty() {
+                    event_type.clear();
+                    continue;
+                }
+                data.pop();
+                let event = if event_type.is_empty() { "message".to_string() } else { event_type };
+                return Ok(Some(ParsedEvent { event, data, id: self.last_event_id.clone(), retry }));
+            }
+            if text.starts_with(':') {
+                continue;
+            }
+            let (field, value) = match text.split_once(':') {
+                Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+                None => (text.as_str(), ""),
+            };
+            match field {
+                "event" => event_type = value.to_string(),
+                "data" => {
+                    data.push_str(value);
+                    data.push('\n');
+                },
+                "id" if !value.contains('\0') => self.last_event_id = Some(value.to_string()),
+                "retry" => {
+                    if let Ok(milliseconds) = value.parse::<u64>() {
+                        retry = Some(milliseconds);
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+type EventReader = BufReader<ureq::BodyReader<'static>>;
+
+/// `http.events(url: string | { url: string, headers: { [string]: string }? })`: connects to an event
+/// stream and returns an iterator over its events, ending when the server closes the stream
+pub fn http_events(luau: &Lua, config: LuaValue) -> LuaValueResult {
+    let (url, headers) = match config {
+        LuaValue::String(url) => (url.to_string_lossy(), None),
+        LuaValue::Table(config) => {
+            let url = match config.raw_get("url")? {
+                LuaValue::String(url) => url.to_string_lossy(),
+                other => {
+                    return wrap_err!("http.events expected url to be a string, got: {:#?}", other);
+                }
+            };
+            let headers: Option<LuaTable> = config.raw_get("headers")?;
+            (url, headers)
+        },
+        other => {
+            return wrap_err!("http.events(url: string | EventsConfig) expected url or EventsConfig table, got: {:#?}", other);
+        }
+    };
+
+    let mut request = ureq::get(&url)
+        .header("Accept", "text/event-stream")
+        .header("Cache-Control", "no-cache");
+    if let Some(headers) = headers {
+        for pair in headers.pairs::<String, String>() {
+            let (key, value) = pair?;
+            request = request.header(key, value);
+        }
+    }
+    let response = match request.call() {
+        Ok(response) => response,
+        Err(err) => {
+            return wrap_err!("http.events: unable to connect to '{}': {}", url, err);
+        }
+    };
+    let content_type = response.headers().get("content-type")
+        .and_then(|content_type| content_type.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    if !content_type.starts_with("text/event-stream") {
+        return wrap_err!("http.events: expected '{}' to respond with Content-Type: text/event-stream, got: '{}'", url, content_type);
+    }
+
+    let parser: RefCell<Option<EventParser<EventReader>>> = RefCell::new(Some(EventParser {
+        reader: BufReader::new(response.into_body().into_reader()),
+        last_event_id: None,
+        started: false,
+    }));
+    Ok(LuaValue::Function(luau.create_function(move |luau: &Lua, _multivalue: LuaMultiValue| -> LuaValueResult {
+        let mut parser = parser.borrow_mut();
+        let Some(events) = parser.as_mut() else {
+            return Ok(LuaNil);
+        };
+        match events.next_event() {
+            Ok(Some(event)) => Ok(LuaValue::Table(TableBuilder::create(luau)?
+                .with_value("event", event.event)?
+                .with_value("data", event.data)?
+                .with_value("id", event.id)?
+                .with_value("retry", event.retry)?
+                .build_readonly()?
+            )),
+            Ok(None) => {
+                // drops the connection
+                parser.take();
+                Ok(LuaNil)
+            },
+            Err(err) => {
+                parser.take();
+                wrap_err!("http.events: stream from '{}' failed: {}", url, err)
+            }
+        }
+    })?))
+}
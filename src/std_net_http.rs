@@ -1,7 +1,11 @@
+use std::fs;
+
 use ureq::{self, Error as UreqError};
 use mlua::prelude::*;
+use rand::{distributions::Alphanumeric, Rng};
+use url::form_urlencoded;
 
-use crate::{std_io_colors as colors, std_json};
+use crate::{std_io_colors as colors, std_json, std_net_events, std_net_url};
 use crate::{table_helpers::TableBuilder, LuaValueResult};
 
 pub fn http_get(luau: &Lua, get_config: LuaValue) -> LuaValueResult {
@@ -62,23 +66,24 @@ pub fn http_get(luau: &Lua, get_config: LuaValue) -> LuaValueResult {
                 }
             };
 
-            if let LuaValue::Table(headers_table) = config.get("params")? {
-                for pair in headers_table.pairs::<String, String>() {
-                    let (key, value) = pair?;
+            if let LuaValue::Table(params_table) = config.get("params")? {
+                for (key, value) in std_net_url::query_pairs(params_table, "net.request: params")? {
                     get_builder = get_builder.query(key, value);
                 }
             };
 
-            let body: Option<String> = {
+            let body: Option<Vec<u8>> = {
                 match config.get("body")? {
-                    LuaValue::String(body) => Some(body.to_str()?.to_string()),
+                    LuaValue::String(body) => Some(body.as_bytes().to_vec()),
+                    LuaValue::Buffer(body) => Some(body.to_vec()),
                     LuaValue::Table(body_table) => {
-                        get_builder = get_builder.header("Content-Type", "application/json");
-                        Some(std_json::json_encode_raw(luau, LuaValue::Table(body_table))?)
+                        let (body, content_type) = encode_table_body(luau, body_table)?;
+                        get_builder = get_builder.header("Content-Type", content_type);
+                        Some(body)
                     },
                     LuaValue::Nil => None,
                     other => {
-                        return wrap_err!("net.get GetOptions.body expected table (to serialize as json) or string, got: {:?}", other)
+                        return wrap_err!("net.get GetOptions.body expected table (to serialize as json), string, buffer, or http.form/http.multipart body, got: {:?}", other)
                     }
                 }
             };
@@ -213,22 +218,23 @@ pub fn http_post(luau: &Lua, post_config: LuaValue) -> LuaValueResult {
                 }
             };
 
-            if let LuaValue::Table(headers_table) = config.get("params")? {
-                for pair in headers_table.pairs::<String, String>() {
-                    let (key, value) = pair?;
+            if let LuaValue::Table(params_table) = config.get("params")? {
+                for (key, value) in std_net_url::query_pairs(params_table, "net.request: params")? {
                     post_builder = post_builder.query(key, value);
                 }
             };
 
-            let body = {
+            let body: Vec<u8> = {
                 match config.get("body")? {
-                    LuaValue::String(body) => body.to_str()?.to_string(),
+                    LuaValue::String(body) => body.as_bytes().to_vec(),
+                    LuaValue::Buffer(body) => body.to_vec(),
                     LuaValue::Table(body_table) => {
-                        post_builder = post_builder.header("Content-Type", "application/json");
-                        std_json::json_encode_raw(luau, LuaValue::Table(body_table))?
+                        let (body, content_type) = encode_table_body(luau, body_table)?;
+                        post_builder = post_builder.header("Content-Type", content_type);
+                        body
                     },
                     other => {
-                        return wrap_err!("net.post PostOptions.body expected table (to serialize as json) or string, got: {:?}", other)
+                        return wrap_err!("net.post PostOptions.body expected table (to serialize as json), string, buffer, or http.form/http.multipart body, got: {:?}", other)
                     }
                 }
             };
@@ -350,22 +356,23 @@ fn http_put(luau: &Lua, put_config: LuaValue) -> LuaValueResult {
                 }
             };
 
-            if let LuaValue::Table(headers_table) = config.get("params")? {
-                for pair in headers_table.pairs::<String, String>() {
-                    let (key, value) = pair?;
+            if let LuaValue::Table(params_table) = config.get("params")? {
+                for (key, value) in std_net_url::query_pairs(params_table, "net.request: params")? {
                     put_builder = put_builder.query(key, value);
                 }
             };
 
-            let body = {
+            let body: Vec<u8> = {
                 match config.get("body")? {
-                    LuaValue::String(body) => body.to_str()?.to_string(),
+                    LuaValue::String(body) => body.as_bytes().to_vec(),
+                    LuaValue::Buffer(body) => body.to_vec(),
                     LuaValue::Table(body_table) => {
-                        put_builder = put_builder.header("Content-Type", "application/json");
-                        std_json::json_encode_raw(luau, LuaValue::Table(body_table))?
+                        let (body, content_type) = encode_table_body(luau, body_table)?;
+                        put_builder = put_builder.header("Content-Type", content_type);
+                        body
                     },
                     other => {
-                        return wrap_err!("net.get PutOptions.body expected table (to serialize as json) or string, got: {:?}", other)
+                        return wrap_err!("net.get PutOptions.body expected table (to serialize as json), string, buffer, or http.form/http.multipart body, got: {:?}", other)
                     }
                 }
             };
@@ -487,22 +494,23 @@ fn http_patch(luau: &Lua, patch_config: LuaValue) -> LuaValueResult {
                 }
             };
 
-            if let LuaValue::Table(headers_table) = config.get("params")? {
-                for pair in headers_table.pairs::<String, String>() {
-                    let (key, value) = pair?;
+            if let LuaValue::Table(params_table) = config.get("params")? {
+                for (key, value) in std_net_url::query_pairs(params_table, "net.request: params")? {
                     patch_builder = patch_builder.query(key, value);
                 }
             };
 
-            let body = {
+            let body: Vec<u8> = {
                 match config.get("body")? {
-                    LuaValue::String(body) => body.to_str()?.to_string(),
+                    LuaValue::String(body) => body.as_bytes().to_vec(),
+                    LuaValue::Buffer(body) => body.to_vec(),
                     LuaValue::Table(body_table) => {
-                        patch_builder = patch_builder.header("Content-Type", "application/json");
-                        std_json::json_encode_raw(luau, LuaValue::Table(body_table))?
+                        let (body, content_type) = encode_table_body(luau, body_table)?;
+                        patch_builder = patch_builder.header("Content-Type", content_type);
+                        body
                     },
                     other => {
-                        return wrap_err!("net.request: PATCH: PatchOptions.body expected to be table (to serialize as json) or string, got: {:?}", other)
+                        return wrap_err!("net.request: PATCH: PatchOptions.body expected to be table (to serialize as json), string, buffer, or http.form/http.multipart body, got: {:?}", other)
                     }
                 }
             };
@@ -621,9 +629,8 @@ fn http_delete(luau: &Lua, delete_config: LuaValue) -> LuaValueResult {
                 }
             };
 
-            if let LuaValue::Table(headers_table) = config.get("params")? {
-                for pair in headers_table.pairs::<String, String>() {
-                    let (key, value) = pair?;
+            if let LuaValue::Table(params_table) = config.get("params")? {
+                for (key, value) in std_net_url::query_pairs(params_table, "net.request: params")? {
                     delete_builder = delete_builder.query(key, value);
                 }
             };
@@ -718,6 +725,191 @@ fn http_delete(luau: &Lua, delete_config: LuaValue) -> LuaValueResult {
     }
 }
 
+/// encodes a table passed as a request body; tables made by http.form and http.multipart carry
+/// their own encoded content and content type, anything else gets serialized to json
+fn encode_table_body(luau: &Lua, body_table: LuaTable) -> LuaResult<(Vec<u8>, String)> {
+    match body_table.raw_get::<LuaValue>("type")? {
+        LuaValue::String(body_type) if body_type == "FormBody" || body_type == "MultipartBody" => {
+            let content_type: String = body_table.raw_get("content_type")?;
+            let content = match body_table.raw_get("content")? {
+                LuaValue::String(content) => content.as_bytes().to_vec(),
+                LuaValue::Buffer(content) => content.to_vec(),
+                other => {
+                    return wrap_err!("net: {}.content expected to be a string or buffer, got: {:?}", body_type.to_string_lossy(), other);
+                }
+            };
+            Ok((content, content_type))
+        },
+        _ => {
+            let json = std_json::json_encode_raw(luau, LuaValue::Table(body_table))?;
+            Ok((json.into_bytes(), String::from("application/json")))
+        }
+    }
+}
+
+fn form_field_values(field_name: &str, value: LuaValue) -> LuaResult<Vec<String>> {
+    match value {
+        LuaValue::String(value) => Ok(vec![value.to_string_lossy()]),
+        LuaValue::Integer(n) => Ok(vec![n.to_string()]),
+        LuaValue::Number(n) => Ok(vec![n.to_string()]),
+        LuaValue::Boolean(b) => Ok(vec![b.to_string()]),
+        LuaValue::Table(values) => {
+            let mut result = Vec::new();
+            for value in values.sequence_values::<LuaValue>() {
+                result.extend(form_field_values(field_name, value?)?);
+            }
+            Ok(result)
+        },
+        other => {
+            wrap_err!("http.form: field '{}' expected to be a string, number, boolean, or array of those, got: {:?}", field_name, other)
+        }
+    }
+}
+
+fn http_form(luau: &Lua, fields: LuaValue) -> LuaValueResult {
+    let fields = match fields {
+        LuaValue::Table(fields) => fields,
+        other => {
+            return wrap_err!("http.form(fields) expected fields to be a table of form fields, got: {:?}", other);
+        }
+    };
+
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+    for pair in fields.pairs::<LuaString, LuaValue>() {
+        let (key, value) = pair?;
+        let key = key.to_string_lossy();
+        for value in form_field_values(&key, value)? {
+            serializer.append_pair(&key, &value);
+        }
+    }
+
+    Ok(LuaValue::Table(
+        TableBuilder::create(luau)?
+            .with_value("type", "FormBody")?
+            .with_value("content_type", "application/x-www-form-urlencoded")?
+            .with_value("content", serializer.finish())?
+            .build_readonly()?
+    ))
+}
+
+/// quotes and newlines aren't allowed inside Content-Disposition names and filenames, so
+/// we percent-encode them the same way browsers do
+fn escape_multipart_name(name: &str) -> String {
+    name.replace('"', "%22").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+struct MultipartPart {
+    filename: Option<String>,
+    content_type: Option<String>,
+    content: Vec<u8>,
+}
+
+impl MultipartPart {
+    fn from_value(field_name: &str, value: LuaValue) -> LuaResult<Self> {
+        match value {
+            LuaValue::String(value) => Ok(MultipartPart {
+                filename: None,
+                content_type: None,
+                content: value.as_bytes().to_vec(),
+            }),
+            LuaValue::Integer(_) | LuaValue::Number(_) | LuaValue::Boolean(_) => Ok(MultipartPart {
+                filename: None,
+                content_type: None,
+                content: value.to_string()?.into_bytes(),
+            }),
+            LuaValue::Buffer(buffy) => Ok(MultipartPart {
+                filename: Some(field_name.to_string()),
+                content_type: Some(String::from("application/octet-stream")),
+                content: buffy.to_vec(),
+            }),
+            LuaValue::Table(part) => {
+                // fs.file() entries get read from disk and sent as file uploads
+                if let LuaValue::String(entry_type) = part.raw_get::<LuaValue>("type")? {
+                    if entry_type == "File" {
+                        let path: String = part.raw_get("path")?;
+                        let content = match fs::read(&path) {
+                            Ok(content) => content,
+                            Err(err) => {
+                                return wrap_err!("http.multipart: unable to read file '{}' for field '{}': {}", path, field_name, err);
+                            }
+                        };
+                        let filename: Option<String> = part.raw_get("name")?;
+                        return Ok(MultipartPart {
+                            filename: Some(filename.unwrap_or_else(|| field_name.to_string())),
+                            content_type: Some(mime_guess::from_path(&path).first_or_octet_stream().to_string()),
+                            content,
+                        });
+                    }
+                }
+                let content = match part.raw_get("content")? {
+                    LuaValue::String(content) => content.as_bytes().to_vec(),
+                    LuaValue::Buffer(content) => content.to_vec(),
+                    other => {
+                        return wrap_err!("http.multipart: MultipartPart.content for field '{}' expected to be a string or buffer, got: {:?}", field_name, other);
+                    }
+                };
+                Ok(MultipartPart {
+                    filename: part.raw_get("filename")?,
+                    content_type: part.raw_get("content_type")?,
+                    content,
+                })
+            },
+            other => {
+                wrap_err!("http.multipart: field '{}' expected to be a string, number, buffer, FileEntry, or MultipartPart, got: {:?}", field_name, other)
+            }
+        }
+    }
+}
+
+fn http_multipart(luau: &Lua, fields: LuaValue) -> LuaValueResult {
+    let fields = match fields {
+        LuaValue::Table(fields) => fields,
+        other => {
+            return wrap_err!("http.multipart(fields) expected fields to be a table of form fields, got: {:?}", other);
+        }
+    };
+
+    let boundary: String = {
+        let random_part: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(24)
+            .map(char::from)
+            .collect();
+        format!("----SealFormBoundary{}", random_part)
+    };
+
+    let mut content: Vec<u8> = Vec::new();
+    for pair in fields.pairs::<LuaString, LuaValue>() {
+        let (field_name, value) = pair?;
+        let field_name = field_name.to_string_lossy();
+        let part = MultipartPart::from_value(&field_name, value)?;
+
+        content.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+        let mut disposition = format!("Content-Disposition: form-data; name=\"{}\"", escape_multipart_name(&field_name));
+        if let Some(filename) = part.filename {
+            disposition.push_str(&format!("; filename=\"{}\"", escape_multipart_name(&filename)));
+        }
+        content.extend_from_slice(disposition.as_bytes());
+        content.extend_from_slice(b"\r\n");
+        if let Some(content_type) = part.content_type {
+            content.extend_from_slice(format!("Content-Type: {}\r\n", content_type).as_bytes());
+        }
+        content.extend_from_slice(b"\r\n");
+        content.extend_from_slice(&part.content);
+        content.extend_from_slice(b"\r\n");
+    }
+    content.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+    Ok(LuaValue::Table(
+        TableBuilder::create(luau)?
+            .with_value("type", "MultipartBody")?
+            .with_value("content_type", format!("multipart/form-data; boundary={}", boundary))?
+            .with_value("boundary", boundary)?
+            .with_value("content", luau.create_buffer(content)?)?
+            .build_readonly()?
+    ))
+}
+
 pub fn http_request(luau: &Lua, request_options: LuaValue) -> LuaValueResult {
     match request_options {
         LuaValue::Table(options) => {
@@ -760,5 +952,8 @@ pub fn create(luau: &Lua) -> LuaResult<LuaTable> {
         .with_function("get", http_get)?
         .with_function("post", http_post)?
         .with_function("request", http_request)?
+        .with_function("form", http_form)?
+        .with_function("multipart", http_multipart)?
+        .with_function("events", std_net_events::http_events)?
         .build_readonly()
 }
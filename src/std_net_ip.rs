@@ -0,0 +1,181 @@
+use crate::{colors, table_helpers::TableBuilder, LuaValueResult};
+use mlua::prelude::*;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+fn parse_address(value: LuaValue, function_name: &str) -> LuaResult<IpAddr> {
+    let ip = match value {
+        LuaValue::String(ip) => ip.to_string_lossy(),
+        other => {
+            return wrap_err!("{} expected ip to be a string, got: {:#?}", function_name, other);
+        }
+    };
+    match ip.trim().parse::<IpAddr>() {
+        Ok(address) => Ok(address),
+        Err(_) => wrap_err!("{}: '{}' isn't a valid IPv4 or IPv6 address", function_name, ip),
+    }
+}
+
+fn is_private(address: &IpAddr) -> bool {
+    match address {
+        IpAddr::V4(v4) => v4.is_private(),
+        // unique local addresses (fc00::/7) are IPv6's private ranges
+        IpAddr::V6(v6) => (v6.segments()[0] & 0xfe00) == 0xfc00,
+    }
+}
+
+fn is_link_local(address: &IpAddr) -> bool {
+    match address {
+        IpAddr::V4(v4) => v4.is_link_local(),
+        IpAddr::V6(v6) => (v6.segments()[0] & 0xffc0) == 0xfe80,
+    }
+}
+
+fn ip_parse(luau: &Lua, ip: LuaValue) -> LuaValueResult {
+    let address = parse_address(ip, "ip.parse(ip: string)")?;
+    Ok(LuaValue::Table(TableBuilder::create(luau)?
+        .with_value("address", address.to_string())?
+        .with_value("version", if address.is_ipv4() { 4 } else { 6 })?
+        .with_value("is_loopback", address.is_loopback())?
+        .with_value("is_private", is_private(&address))?
+        .with_value("is_link_local", is_link_local(&address))?
+        .with_value("is_multicast", address.is_multicast())?
+        .with_value("is_unspecified", address.is_unspecified())?
+        .build_readonly()?
+    ))
+}
+
+/// a network like 10.0.0.0/8, stored with its host bits cleared
+struct Cidr {
+    network: IpAddr,
+    prefix_length: u8,
+}
+
+impl Cidr {
+    fn parse(cidr: &str) -> Option<Cidr> {
+        let (address, prefix_length) = cidr.trim().split_once('/')?;
+        let address: IpAddr = address.parse().ok()?;
+        let prefix_length: u8 = prefix_length.parse().ok()?;
+        let network = match address {
+            IpAddr::V4(v4) if prefix_length <= 32 => IpAddr::V4(Ipv4Addr::from(u32::from(v4) & v4_mask(prefix_length))),
+            IpAddr::V6(v6) if prefix_length <= 128 => IpAddr::V6(Ipv6Addr::from(u128::from(v6) & v6_mask(prefix_length))),
+            _ => return None,
+        };
+        Some(Cidr { network, prefix_length })
+    }
+
+    fn contains(&self, address: &IpAddr) -> bool {
+        match (self.network, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                u32::from(*address) & v4_mask(self.prefix_length) == u32::from(network)
+            },
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                u128::from(*address) & v6_mask(self.prefix_length) == u128::from(network)
+            },
+            _ => false,
+        }
+    }
+
+    fn netmask(&self) -> IpAddr {
+        match self.network {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(v4_mask(self.prefix_length))),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(v6_mask(self.prefix_length))),
+        }
+    }
+}
+
+fn v4_mask(prefix_length: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix_length as u32).unwrap_or(0)
+}
+
+fn v6_mask(prefix_length: u8) -> u128 {
+    u128::MAX.checked_shl(128 - prefix_length as u32).unwrap_or(0)
+}
+
+fn parse_cidr(value: LuaValue, function_name: &str) -> LuaResult<Cidr> {
+    let cidr = match value {
+        LuaValue::String(cidr) => cidr.to_string_lossy(),
+        other => {
+            return wrap_err!("{} expected cidr to be a string like \"10.0.0.0/8\", got: {:#?}", function_name, other);
+        }
+    };
+    match Cidr::parse(&cidr) {
+        Some(cidr) => Ok(cidr),
+        None => wrap_err!("{}: '{}' isn't valid CIDR notation (like \"192.168.0.0/16\" or \"fd00::/8\")", function_name, cidr),
+    }
+}
+
+fn ip_cidr(luau: &Lua, cidr: LuaValue) -> LuaValueResult {
+    let cidr = parse_cidr(cidr, "ip.cidr(cidr: string)")?;
+    let network = cidr.network.to_string();
+    let netmask = cidr.netmask().to_string();
+    let prefix_length = cidr.prefix_length;
+    Ok(LuaValue::Table(TableBuilder::create(luau)?
+        .with_value("network", network)?
+        .with_value("netmask", netmask)?
+        .with_value("prefix_length", prefix_length)?
+        .with_value("version", if cidr.network.is_ipv4() { 4 } else { 6 })?
+        .with_function("contains", move |_luau: &Lua, mut multivalue: LuaMultiValue| -> LuaResult<bool> {
+            let _s = multivalue.pop_front();
+            let address = parse_address(multivalue.pop_front().unwrap_or(LuaNil), "Cidr:contains(ip: string)")?;
+            Ok(cidr.contains(&address))
+        })?
+        .build_readonly()?
+    ))
+}
+
+fn ip_contains(_luau: &Lua, (cidr, ip): (LuaValue, LuaValue)) -> LuaResult<bool> {
+    let cidr = parse_cidr(cidr, "ip.contains(cidr: string, ip: string)")?;
+    let address = parse_address(ip, "ip.contains(cidr: string, ip: string)")?;
+    Ok(cidr.contains(&address))
+}
+
+/// `net.interfaces()`: every network interface with its addresses, grouped by interface name
+pub fn net_interfaces(luau: &Lua, _value: LuaValue) -> LuaValueResult {
+    let interfaces = match if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces,
+        Err(err) => {
+            return wrap_err!("net.interfaces: unable to list network interfaces: {}", err);
+        }
+    };
+
+    // interfaces with several addresses show up once per address
+    let mut by_name: BTreeMap<String, (bool, bool, Vec<LuaTable>)> = BTreeMap::new();
+    for interface in interfaces {
+        let (ip, netmask, prefix_length, broadcast) = match &interface.addr {
+            if_addrs::IfAddr::V4(v4) => (IpAddr::V4(v4.ip), IpAddr::V4(v4.netmask), v4.prefixlen, v4.broadcast.map(IpAddr::V4)),
+            if_addrs::IfAddr::V6(v6) => (IpAddr::V6(v6.ip), IpAddr::V6(v6.netmask), v6.prefixlen, v6.broadcast.map(IpAddr::V6)),
+        };
+        let address = TableBuilder::create(luau)?
+            .with_value("address", ip.to_string())?
+            .with_value("netmask", netmask.to_string())?
+            .with_value("prefix_length", prefix_length)?
+            .with_value("version", if ip.is_ipv4() { 4 } else { 6 })?
+            .with_value("broadcast", broadcast.map(|broadcast| broadcast.to_string()))?
+            .build_readonly()?;
+        let entry = by_name.entry(interface.name.clone()).or_insert((false, false, Vec::new()));
+        entry.0 |= interface.is_oper_up();
+        entry.1 |= interface.is_loopback();
+        entry.2.push(address);
+    }
+
+    let result = luau.create_table()?;
+    for (name, (is_up, is_loopback, addresses)) in by_name {
+        result.raw_push(TableBuilder::create(luau)?
+            .with_value("name", name)?
+            .with_value("is_up", is_up)?
+            .with_value("is_loopback", is_loopback)?
+            .with_value("addresses", luau.create_sequence_from(addresses)?)?
+            .build_readonly()?
+        )?;
+    }
+    Ok(LuaValue::Table(result))
+}
+
+pub fn create(luau: &Lua) -> LuaResult<LuaTable> {
+    TableBuilder::create(luau)?
+        .with_function("parse", ip_parse)?
+        .with_function("cidr", ip_cidr)?
+        .with_function("contains", ip_contains)?
+        .build_readonly()
+}
@@ -1,301 +1,841 @@
 #[allow(unused_imports)]
-use crate::{colors, std_json, table_helpers::TableBuilder, LuaValueResult};
+use crate::{colors, globals, require, std_json, std_net_url, table_helpers::TableBuilder, LuaValueResult};
+use crate::std_net_serve_router::Router;
+use crate::{std_net_events, std_net_serve_lifecycle, std_net_serve_static, std_net_serve_tls, std_net_websocket};
+use crate::std_net_serve_lifecycle::{ServerLifecycle, TrackedConnection};
+use crate::std_net_websocket::SocketStream;
+use crate::std_net_serve_request::{self, RequestError, RequestLimits, ServeRequest};
 use mlua::prelude::*;
-use regex::Regex;
 use std::net::{TcpListener, TcpStream};
-use std::io::{prelude::*, BufReader, Write};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{fs, thread};
 
-fn server_serve(luau: &Lua, serve_config: LuaValue) -> LuaValueResult {
-    let config = match serve_config {
-        LuaValue::Table(config) => config,
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
+
+/// how long an idle keep-alive connection is held open before we close it
+const DEFAULT_KEEP_ALIVE_TIMEOUT: f64 = 5.0;
+/// how often a worker holding an idle keep-alive connection checks whether other connections are waiting on it
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// per-connection settings shared by every worker
+#[derive(Clone)]
+struct ConnectionOptions {
+    keep_alive_timeout: Option<Duration>,
+    limits: RequestLimits,
+    tls: Option<Arc<rustls::ServerConfig>>,
+    lifecycle: Arc<ServerLifecycle>,
+    /// connections waiting for a worker (None without workers); a worker hangs up on an idle keep-alive
+    /// connection when anyone's waiting, so a few chatty clients can't hold every worker
+    queue: Option<Receiver<TcpStream>>,
+}
+
+fn connection_header(keep_alive: bool) -> &'static str {
+    if keep_alive { "keep-alive" } else { "close" }
+}
+
+/// a plain text response for when we can't (or shouldn't) ask the handler for one
+fn simple_response(status_code: &str, body: &str, keep_alive: bool) -> Vec<u8> {
+    format!("HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nConnection: {}\r\nContent-Length: {}\r\n\r\n{}",
+        status_code, connection_header(keep_alive), body.len(), body).into_bytes()
+}
+
+enum ResponseBody {
+    Bytes(Vec<u8>),
+    /// called until it returns nil, sending each string or buffer chunk as soon as we get it
+    Stream(LuaFunction),
+    /// a FileEntry, copied straight from disk
+    File(fs::File),
+}
+
+struct PreparedResponse {
+    head: Vec<u8>,
+    body: Option<ResponseBody>,
+    /// streams without a known length are sent with chunked encoding (or until we close the connection for HTTP/1.0)
+    chunked: bool,
+    content_length: Option<u64>,
+    keep_alive: bool,
+}
+
+fn parse_content_type(content_type: LuaValue) -> LuaResult<String> {
+    if let LuaValue::String(response) = content_type {
+        let response = response.to_string_lossy();
+        Ok(match response.to_lowercase().as_str() {
+            "text" => "text/plain; charset=utf-8".to_string(),
+            "html" => "text/html; charset=utf-8".to_string(),
+            "json" => "application/json".to_string(),
+            "xml"  => "application/xml".to_string(),
+            "css"  => "text/css".to_string(),
+            "binary" => "application/octet-stream".to_string(),
+            _other => response,
+        })
+    } else {
+        wrap_err!("ServeResponse expected content_type to be a string, got: {:#?}", content_type)
+    }
+}
+
+fn open_file_entry(file_entry: &LuaTable) -> LuaResult<fs::File> {
+    let path: String = match file_entry.raw_get("path")? {
+        LuaValue::String(path) => path.to_string_lossy(),
         other => {
-            return wrap_err!("server.serve expected ServeConfig table (with fields address, port, handler, etc.), got: {:#?}", other);
+            return wrap_err!("ServeResponse expected body FileEntry to have a path, got: {:#?}", other);
         }
     };
+    match fs::File::open(&path) {
+        Ok(file) => Ok(file),
+        Err(err) => wrap_err!("ServeResponse: unable to open body FileEntry '{}': {}", path, err),
+    }
+}
 
-    let address: String = match config.raw_get("address") {
-        Ok(address) => address,
-        Err(err) => {
-            return wrap_err!("server.serve expected an address (string), got an error: {}", err);
-        }
+/// responses to HEAD requests keep the Content-Length of the body we would have sent but leave out the body itself
+fn build_response(serve_response: LuaTable, keep_alive: bool, request: &ServeRequest) -> LuaResult<PreparedResponse> {
+    let status_code: String = match serve_response.raw_get("status_code") {
+        Ok(status) => status,
+        Err(err) => return wrap_err!("ServeResponse table missing 'status_code': {}", err),
     };
 
-    let port: String = match config.raw_get("port") {
-        Ok(port) => match port {
-            LuaValue::String(port) => port.to_string_lossy(),
-            LuaValue::Integer(port) => port.to_string(),
-            other => {
-                return wrap_err!("server.serve expected a port, got: {:#?}", other);
-            }
+    let content_type: String = match serve_response.raw_get("content_type") {
+        Ok(content_type) => parse_content_type(content_type)?,
+        Err(err) => return wrap_err!("ServeResponse table missing 'content_type': {}", err),
+    };
+
+    let body = match serve_response.raw_get("body") {
+        Ok(LuaValue::String(body)) => ResponseBody::Bytes(body.as_bytes().to_vec()),
+        Ok(LuaValue::Buffer(buff)) => ResponseBody::Bytes(buff.to_vec()),
+        Ok(LuaValue::Function(chunks)) => ResponseBody::Stream(chunks),
+        Ok(LuaValue::Table(file_entry)) if file_entry.raw_get::<LuaValue>("type")?.to_string()? == "File" => {
+            ResponseBody::File(open_file_entry(&file_entry)?)
         },
-        Err(err) => {
-            return wrap_err!("server.serve expected a port (string), got an error: {}", err);
+        Ok(other) => {
+            return wrap_err!("Expected body to be a string, buffer, iterator function, or FileEntry, got: {:#?}", other);
         }
+        Err(err) => return wrap_err!("ServeResponse table missing 'body': {}", err),
     };
 
-    let handler_function = match config.raw_get("handler") {
-        Ok(LuaValue::Function(f)) => f,
-        Ok(LuaValue::Table(_handles)) => todo!(),
-        Ok(other) => {
-            return wrap_err!("server.serve expected handler to be a function, got: {:#?}", other);
+    let headers: Option<LuaTable> = serve_response.raw_get("headers").ok();
+    let cookies: Option<LuaTable> = serve_response.raw_get("cookies").ok();
+    let http_version: Option<String> = serve_response.raw_get("http_version").ok();
+    let reason_phrase: Option<String> = serve_response.raw_get("reason_phrase").ok();
+    let redirect_url: Option<String> = serve_response.raw_get("redirect_url").ok();
+
+    let http_version = http_version.unwrap_or_else(|| "HTTP/1.1".to_string());
+    // status_code is usually "404 Not Found", but a custom reason_phrase replaces the one it came with
+    let code = status_code.split_whitespace().next().unwrap_or_default().to_string();
+    let status_line = match reason_phrase {
+        Some(reason_phrase) => format!("{} {} {}", http_version, code, reason_phrase),
+        None => format!("{} {}", http_version, status_code),
+    };
+
+    // Construct headers and cookies
+    let mut additional_headers = String::new();
+    let mut declared_length: Option<u64> = None;
+    if let Some(headers_table) = headers {
+        for pair in headers_table.pairs::<LuaString, LuaString>().flatten() {
+            let (key, value) = pair;
+            let (key, value) = (key.to_str()?, value.to_str()?);
+            // framing headers are ours to manage, except that a streamed body can declare its length up front
+            if key.eq_ignore_ascii_case("content-length") {
+                declared_length = match value.trim().parse::<u64>() {
+                    Ok(length) => Some(length),
+                    Err(_) => return wrap_err!("ServeResponse has an invalid Content-Length header: {}", value.to_string()),
+                };
+                continue;
+            }
+            if key.eq_ignore_ascii_case("transfer-encoding") || key.eq_ignore_ascii_case("connection") {
+                continue;
+            }
+            additional_headers.push_str(&format!("{}: {}\r\n", key, value));
         }
-        Err(err) => {
-            return wrap_err!("server.serve expected some handler, got an error: {}", err);
+    }
+    if let Some(cookies_table) = cookies {
+        for pair in cookies_table.pairs::<LuaString, LuaString>().flatten() {
+            let (key, value) = pair;
+            additional_headers.push_str(&format!("Set-Cookie: {}={}\r\n", key.to_str()?, value.to_str()?));
         }
+    }
+    if let Some(url) = redirect_url {
+        additional_headers.push_str(&format!("Location: {}\r\n", url));
+    }
+
+    let content_length = match &body {
+        ResponseBody::Bytes(bytes) => Some(bytes.len() as u64),
+        ResponseBody::File(file) => Some(file.metadata()?.len()),
+        ResponseBody::Stream(_) => declared_length,
     };
+    // 1xx, 204 and 304 responses never have a body
+    let bodiless_status = code.starts_with('1') || code == "204" || code == "304";
+    let chunked = content_length.is_none() && !bodiless_status && request.http_version == "HTTP/1.1";
+    // without a length or chunked encoding, the end of the connection marks the end of the body
+    let keep_alive = keep_alive && (content_length.is_some() || chunked || bodiless_status);
 
-    let address_port = format!("{}:{}", address, port);
-    let listener = match TcpListener::bind(&address_port) {
-        Ok(listener) => listener,
-        Err(err) => {
-            return wrap_err!("server.serve: failed to bind to {} with error: {}", address_port, err);
-        }
+    let framing = if bodiless_status {
+        String::new()
+    } else if let Some(length) = content_length {
+        format!("Content-Length: {}\r\n", length)
+    } else if chunked {
+        String::from("Transfer-Encoding: chunked\r\n")
+    } else {
+        String::new()
     };
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                match handle_client(stream, handler_function.clone(), luau) {
-                    Ok(_client) => {}
-                    Err(err) => {
-                        return wrap_err!("server.serve: failed to handle client: {}", err);
+    let head = format!("{}\r\nContent-Type: {}\r\n{}Connection: {}\r\n{}\r\n",
+        status_line, content_type, additional_headers, connection_header(keep_alive), framing
+    ).into_bytes();
+
+    Ok(PreparedResponse {
+        head,
+        body: if bodiless_status || request.method == "HEAD" { None } else { Some(body) },
+        chunked,
+        content_length,
+        keep_alive,
+    })
+}
+
+fn chunk_bytes(chunk: LuaValue) -> LuaResult<Option<Vec<u8>>> {
+    match chunk {
+        LuaValue::Nil => Ok(None),
+        LuaValue::String(chunk) => Ok(Some(chunk.as_bytes().to_vec())),
+        LuaValue::Buffer(chunk) => Ok(Some(chunk.to_vec())),
+        other => {
+            wrap_err!("streamed body iterator should return a string or buffer chunk (or nil when done), got: {:#?}", other)
+        }
+    }
+}
+
+/// writes the response, calling the body's iterator as we go; errors after the head is sent
+/// can't become a 500 anymore, so the caller should just log them and drop the connection
+fn write_response<W: Write>(writer: &mut W, response: PreparedResponse) -> LuaResult<()> {
+    writer.write_all(&response.head)?;
+    match response.body {
+        None => {},
+        Some(ResponseBody::Bytes(bytes)) => writer.write_all(&bytes)?,
+        Some(ResponseBody::File(file)) => {
+            io::copy(&mut file.take(response.content_length.unwrap_or_default()), writer)?;
+        },
+        Some(ResponseBody::Stream(chunks)) => {
+            let mut written: u64 = 0;
+            while let Some(chunk) = chunk_bytes(chunks.call::<LuaValue>(())?)? {
+                if chunk.is_empty() {
+                    // an empty chunk would end a chunked body early
+                    continue;
+                }
+                written += chunk.len() as u64;
+                if response.content_length.is_some_and(|length| written > length) {
+                    return wrap_err!("streamed body is longer than its Content-Length header ({} bytes)", response.content_length.unwrap_or_default());
+                }
+                if response.chunked {
+                    let mut framed = format!("{:x}\r\n", chunk.len()).into_bytes();
+                    framed.extend_from_slice(&chunk);
+                    framed.extend_from_slice(b"\r\n");
+                    writer.write_all(&framed)?;
+                } else {
+                    writer.write_all(&chunk)?;
+                }
+                writer.flush()?;
+            }
+            if response.chunked {
+                writer.write_all(b"0\r\n\r\n")?;
+            } else if response.content_length.is_some_and(|length| written != length) {
+                return wrap_err!("streamed body ended after {} bytes but its Content-Length header promised {}", written, response.content_length.unwrap_or_default());
+            }
+        },
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+enum ServeHandler {
+    Function(LuaFunction),
+    Router(Router),
+}
+
+/// middleware that run on every request; `before` can short-circuit by returning a response,
+/// `after` can replace the response, and `on_error` turns handler errors into responses
+struct Middleware {
+    before: Vec<LuaFunction>,
+    after: Vec<LuaFunction>,
+    on_error: Option<LuaFunction>,
+}
+
+impl Middleware {
+    fn from_table(table: &LuaTable) -> LuaResult<Self> {
+        Ok(Middleware {
+            before: middleware_functions(table, "before")?,
+            after: middleware_functions(table, "after")?,
+            on_error: match table.raw_get("on_error")? {
+                LuaValue::Function(on_error) => Some(on_error),
+                LuaValue::Nil => None,
+                other => {
+                    return wrap_err!("server.serve expected on_error to be a function or nil, got: {:#?}", other);
+                }
+            },
+        })
+    }
+}
+
+fn middleware_functions(table: &LuaTable, field: &str) -> LuaResult<Vec<LuaFunction>> {
+    match table.raw_get(field)? {
+        LuaValue::Function(f) => Ok(vec![f]),
+        LuaValue::Table(functions) => {
+            let mut result = Vec::new();
+            for f in functions.sequence_values::<LuaValue>() {
+                match f? {
+                    LuaValue::Function(f) => result.push(f),
+                    other => {
+                        return wrap_err!("server.serve expected {} to be a function or array of functions, got an array containing: {:#?}", field, other);
                     }
                 }
             }
-            Err(e) => {
-                println!("Connection failed: {}", e);
+            Ok(result)
+        },
+        LuaValue::Nil => Ok(Vec::new()),
+        other => {
+            wrap_err!("server.serve expected {} to be a function or array of functions, got: {:#?}", field, other)
+        }
+    }
+}
+
+struct ServeApp {
+    handler: ServeHandler,
+    middleware: Middleware,
+}
+
+impl ServeApp {
+    /// `middleware` is the ServeConfig (or module) table that `before`, `after`, and `on_error` are read from
+    fn new(handler: LuaValue, middleware: &LuaTable) -> LuaResult<Self> {
+        let handler = match handler {
+            LuaValue::Function(f) => ServeHandler::Function(f),
+            LuaValue::Table(routes) => ServeHandler::Router(Router::from_table(routes)?),
+            other => {
+                return wrap_err!("server.serve expected handler to be a function or table of routes, got: {:#?}", other);
+            }
+        };
+        Ok(ServeApp {
+            handler,
+            middleware: Middleware::from_table(middleware)?,
+        })
+    }
+
+    fn respond(&self, luau: &Lua, request: &ServeRequest, request_table: LuaTable) -> LuaResult<LuaTable> {
+        let mut response: Option<LuaTable> = None;
+        for before in &self.middleware.before {
+            match before.call::<LuaValue>(request_table.clone())? {
+                LuaValue::Table(early_response) => {
+                    response = Some(early_response);
+                    break;
+                },
+                LuaValue::Nil => {},
+                other => {
+                    return wrap_err!("before middleware should return a ServeResponse or nil, got: {:#?}", other);
+                }
             }
         }
+
+        let mut response = match response {
+            Some(response) => response,
+            None => {
+                let result = match &self.handler {
+                    ServeHandler::Function(handler_function) => handler_function.call::<LuaValue>(request_table.clone())?,
+                    ServeHandler::Router(router) => router.dispatch(luau, &request.method, request.path(), request_table.clone())?,
+                };
+                match result {
+                    LuaValue::Table(response) => response,
+                    other => {
+                        return wrap_err!("handler_function should return a table, got: {:#?}", other);
+                    }
+                }
+            }
+        };
+
+        for after in &self.middleware.after {
+            match after.call::<LuaValue>((request_table.clone(), response.clone()))? {
+                LuaValue::Table(new_response) => response = new_response,
+                LuaValue::Nil => {},
+                other => {
+                    return wrap_err!("after middleware should return a ServeResponse or nil, got: {:#?}", other);
+                }
+            }
+        }
+
+        Ok(response)
     }
 
-    Ok(LuaValue::Nil)
+    fn handle(&self, luau: &Lua, request: &ServeRequest, request_table: LuaTable) -> LuaResult<LuaTable> {
+        match self.respond(luau, request, request_table.clone()) {
+            Ok(response) => Ok(response),
+            Err(err) => match &self.middleware.on_error {
+                Some(on_error) => match on_error.call::<LuaValue>((request_table, err.to_string()))? {
+                    LuaValue::Table(response) => Ok(response),
+                    other => {
+                        wrap_err!("on_error should return a ServeResponse, got: {:#?}\noriginal error: {}", other, err)
+                    }
+                },
+                None => Err(err),
+            }
+        }
+    }
 }
 
-fn handle_client(mut stream: TcpStream, handler_function: LuaFunction, luau: &Lua) -> LuaValueResult {
-    let mut invalid_request = false;
+fn log_handler_error(request: &ServeRequest, err: impl std::fmt::Display) {
+    eprintln!("{}[ERR]{}{} server.serve: handler failed on {} {}, responding with 500 Internal Server Error:\n{}{}",
+        colors::BOLD_RED, colors::RESET, colors::RED, request.method, request.path(), err, colors::RESET);
+}
+
+fn log_stream_error(request: &ServeRequest, err: impl std::fmt::Display) {
+    eprintln!("{}[ERR]{}{} server.serve: streaming the response to {} {} failed, closing the connection:\n{}{}",
+        colors::BOLD_RED, colors::RESET, colors::RED, request.method, request.path(), err, colors::RESET);
+}
 
+/// serves requests on a single connection until the client closes it, stops keeping it alive,
+/// or idles past the keep-alive timeout; handler errors get logged and turned into 500s
+fn handle_connection(luau: &Lua, app: &ServeApp, stream: TcpStream, options: &ConnectionOptions) {
     let peer_address = match stream.peer_addr() {
         Ok(address) => address.to_string(),
         Err(err) => format!("Unknown ({})", err),
     };
 
-    let mut body: String = String::from("");
-
-    let lines: Vec<String> = {
-        let mut buf_reader = BufReader::new(&stream);
-        let mut content_length: Option<usize> = None;
-        let mut lines: Vec<String> = Vec::new();
-        
-        // handle header and break upon hitting \r\n\r\n aka time to start body
-        for header in buf_reader.by_ref().lines() {
-            match header {
-                Ok(header_line) => {
-                    if header_line.to_lowercase().contains("content-length") {
-                        if let Some(content_length_str) = header_line.split_once(':').map(|x| x.1) {
-                            if let Ok(length) = content_length_str.trim().parse::<usize>() {
-                                content_length = Some(length);
-                            } else {
-                                eprintln!("Failed to parse Content-Length");
-                            }
-                        } else {
-                            eprintln!("Content-Length header is malformed");
-                        }
-                    }
-                    // handle body here by reading exact content length and append it to lines vec
-                    if header_line.is_empty() {
-                        if let Some(length) = content_length {
-                            let mut body_buff = vec![0; length];
-                            let body_string = match buf_reader.read_exact(&mut body_buff) {
-                                Ok(_) => String::from_utf8_lossy(&body_buff).to_string(),
-                                Err(err) => err.to_string(),
-                            };
-                            body = body_string.clone();
-                            lines.push(body_string);
-                        }
-                        break;
-                    }
-                    lines.push(header_line);
-                },
-                Err(err) => {
-                    invalid_request = true;
-                    lines.push(format!("Error reading line: {}", err));
+    if stream.set_read_timeout(options.keep_alive_timeout).is_err() {
+        return;
+    }
+    // connections that come in while we're stopping just get closed
+    let Some(connection) = options.lifecycle.track(&stream) else {
+        return;
+    };
+    match &options.tls {
+        Some(tls_config) => {
+            let tls_connection = match rustls::ServerConnection::new(Arc::clone(tls_config)) {
+                Ok(tls_connection) => tls_connection,
+                Err(_) => return,
+            };
+            // the handshake happens on the first read, so a failed one just looks like a broken connection
+            if let Some(mut tls_stream) = serve_stream(luau, app, rustls::StreamOwned::new(tls_connection, stream), &peer_address, &connection, options) {
+                tls_stream.conn.send_close_notify();
+                let _ = tls_stream.flush();
+            }
+        },
+        None => {
+            serve_stream(luau, app, stream, &peer_address, &connection, options);
+        }
+    }
+}
+
+/// serves requests off a plain or TLS stream, handing the stream back when the connection's done
+/// (or None if it got upgraded to a websocket or event stream)
+fn serve_stream<S: SocketStream + Send + 'static>(luau: &Lua, app: &ServeApp, stream: S, peer_address: &str, connection: &TrackedConnection, options: &ConnectionOptions) -> Option<S> {
+    let mut reader = BufReader::new(stream);
+    let mut kept_alive = false;
+
+    loop {
+        if !connection.wait_for_request() {
+            break;
+        }
+        // once the next request starts arriving, stopping the server lets it finish
+        if !await_request(&mut reader, kept_alive, options) {
+            break;
+        }
+        connection.begin_request();
+        kept_alive = true;
+        let request = match std_net_serve_request::read_request(&mut reader, &options.limits) {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(err) => {
+                // we can't tell where the next request would start, so respond and hang up;
+                // ignore write errors since the client may already be gone (ctrl c mid request)
+                if let Some(status) = err.status() {
+                    let body = match &err {
+                        RequestError::BadRequest(message) => format!("{}: {}", status, message),
+                        _ => status.to_string(),
+                    };
+                    let _ = reader.get_mut().write_all(&simple_response(status, &body, false));
+                }
+                break;
+            }
+        };
+        let keep_alive = options.keep_alive_timeout.is_some() && request.wants_keep_alive() && !connection.is_stopping();
+
+        let handled = request.to_table(luau, peer_address)
+            .and_then(|request_table| Ok((app.handle(luau, &request, request_table.clone())?, request_table)));
+        if let Ok((serve_response, request_table)) = &handled {
+            if let (Ok(LuaValue::Function(on_socket)), true) = (serve_response.raw_get("websocket"), request.is_websocket_upgrade()) {
+                serve_websocket(luau, reader, &request, request_table.clone(), on_socket);
+                return None;
+            }
+            if let Ok(LuaValue::Function(on_stream)) = serve_response.raw_get("events") {
+                std_net_events::serve_events(luau, reader, &request, request_table.clone(), serve_response.clone(), on_stream);
+                return None;
+            }
+        }
+
+        let writer = reader.get_mut();
+        let serve_response = handled
+            .and_then(|(serve_response, _request_table)| build_response(serve_response, keep_alive, &request));
+        let response = match serve_response {
+            Ok(response) => response,
+            Err(err) => {
+                log_handler_error(&request, err);
+                let response = simple_response("500 Internal Server Error", "500 Internal Server Error", keep_alive);
+                if writer.write_all(&response).and_then(|_| writer.flush()).is_err() || !keep_alive {
                     break;
                 }
+                continue;
+            }
+        };
+
+        let keep_alive = response.keep_alive;
+        if let Err(err) = write_response(writer, response) {
+            if !matches!(err, LuaError::ExternalError(_)) {
+                log_stream_error(&request, err);
             }
+            break;
         }
-        lines
+        if !keep_alive {
+            break;
+        }
+    }
+    Some(reader.into_inner())
+}
+
+/// waits for a request to start arriving; false if the client hung up or idled past the keep-alive timeout,
+/// or if this connection's being kept alive between requests and other connections are waiting for a worker
+fn await_request<S: SocketStream>(reader: &mut BufReader<S>, kept_alive: bool, options: &ConnectionOptions) -> bool {
+    let (true, Some(queue), Some(keep_alive_timeout)) = (kept_alive, &options.queue, options.keep_alive_timeout) else {
+        return matches!(reader.fill_buf(), Ok(buffered) if !buffered.is_empty());
     };
+    // wait in slices so we notice anyone queueing up behind us
+    let deadline = Instant::now() + keep_alive_timeout;
+    let arrived = loop {
+        let slice = IDLE_POLL_INTERVAL.min(deadline.saturating_duration_since(Instant::now()));
+        if slice.is_zero() || reader.get_ref().set_read_timeout(Some(slice)).is_err() {
+            break false;
+        }
+        match reader.fill_buf() {
+            Ok(buffered) => break !buffered.is_empty(),
+            Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted) => {
+                if !queue.is_empty() {
+                    break false;
+                }
+            },
+            Err(_) => break false,
+        }
+    };
+    // the rest of the request gets the usual timeout
+    arrived && reader.get_ref().set_read_timeout(Some(keep_alive_timeout)).is_ok()
+}
+
+/// finishes the websocket handshake and hands the socket to the route's callback, closing it
+/// once the callback returns (if it didn't already)
+fn serve_websocket<S: SocketStream + 'static>(luau: &Lua, mut reader: BufReader<S>, request: &ServeRequest, request_table: LuaTable, on_socket: LuaFunction) {
+    let key = request.header("Sec-WebSocket-Key").unwrap_or_default();
+    let handshake = format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        tungstenite::handshake::derive_accept_key(key.as_bytes()));
+    let writer = reader.get_mut();
+    if writer.write_all(handshake.as_bytes()).and_then(|_| writer.flush()).is_err() {
+        return;
+    }
+
+    // anything the client sent right after the handshake is already sitting in our buffer
+    let already_read = reader.buffer().to_vec();
+    let stream = reader.into_inner();
+    // sockets wait on the script, not the keep-alive timeout
+    let _ = stream.set_read_timeout(None);
+    let socket = tungstenite::WebSocket::from_partially_read(stream, already_read, tungstenite::protocol::Role::Server, None);
 
-    if invalid_request {
-        // commented all of this out because it gets garbled when returned through https
-        
-        // let response = r#"{"ok": false, "err": "invalid utf-8 request"}"#;
-        // let response_headers = format!(
-        //     "HTTP/1.1 400 Bad Request\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
-        //     response.len()
-        // );
-
-        // if stream.write_all(response_headers.as_bytes()).is_err() {
-        //     return Ok(LuaNil); // prevent server from crashing if pipe break (ctrl c) in mid invalid request
-        // }
-        // if stream.write_all(response.as_bytes()).is_err() {
-        //     return Ok(LuaNil);
-        // }
-        return Ok(LuaNil);
-    }  
-
-    let request_text = lines.join("\n");
-    
-    let first_line_re = Regex::new(r"^(?P<method>\w+)\s+(?P<path>\S+)\s+HTTP/1\.[01]$").unwrap();
-    let captures = match first_line_re.captures(&lines[0]) {
-        Some(captures) => captures,
-        None => return wrap_err!("server.serve: Failed to parse request line: {}", lines[0]),
+    let socket = match std_net_websocket::create_socket(luau, socket) {
+        Ok((socket_table, socket)) => {
+            if let Err(err) = on_socket.call::<LuaValue>((socket_table, request_table)) {
+                eprintln!("{}[ERR]{}{} server.serve: websocket handler failed on {}, closing the socket:\n{}{}",
+                    colors::BOLD_RED, colors::RESET, colors::RED, request.path(), err, colors::RESET);
+            }
+            socket
+        },
+        Err(_) => return,
     };
+    let _ = std_net_websocket::close_socket(&mut socket.borrow_mut(), None);
+}
 
-    let method = match captures.name("method") {
-        Some(method) => method.as_str(),
-        None => return wrap_err!("server.serve: Failed to extract HTTP method: {}", lines[0]),
+/// `server.websocket(handler: (socket, request) -> ())`: a route handler that accepts websocket upgrades
+/// (and responds 426 Upgrade Required to anything else)
+fn server_websocket(luau: &Lua, on_socket: LuaValue) -> LuaResult<LuaFunction> {
+    let on_socket = match on_socket {
+        LuaValue::Function(on_socket) => on_socket,
+        other => {
+            return wrap_err!("server.websocket(handler: (socket: WebSocket, request: ServeRequest) -> ()) expected handler to be a function, got: {:#?}", other);
+        }
     };
-    let path = match captures.name("path") {
-        Some(path) => path.as_str(),
-        None => return wrap_err!("server.serve: Failed to extract HTTP request path: {}", lines[0]),
+    luau.create_function(move |luau, request: LuaTable| -> LuaResult<LuaTable> {
+        let headers: LuaTable = request.raw_get("headers")?;
+        let upgrade: Option<String> = headers.raw_get("upgrade")?;
+        if upgrade.is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket")) {
+            // the connection handler checks the rest of the handshake before taking over
+            TableBuilder::create(luau)?
+                .with_value("status_code", "101 Switching Protocols")?
+                .with_value("content_type", "text")?
+                .with_value("body", "")?
+                .with_value("websocket", on_socket.clone())?
+                .build()
+        } else {
+            TableBuilder::create(luau)?
+                .with_value("status_code", "426 Upgrade Required")?
+                .with_value("content_type", "text")?
+                .with_value("body", "426 Upgrade Required: this route only accepts websocket connections")?
+                .with_value("headers", TableBuilder::create(luau)?
+                    .with_value("Upgrade", "websocket")?
+                    .with_value("Connection", "Upgrade")?
+                    .build()?
+                )?
+                .build()
+        }
+    })
+}
+
+/// sets up a fresh Luau VM for a server worker and evaluates the handler module in it, much like
+/// thread.spawn does for threads; the module can return a handler function, a table of routes,
+/// or a table with `handler` and any of `before`, `after`, and `on_error`
+fn load_handler_module(luau: &Lua, handler_path: &str) -> LuaResult<ServeApp> {
+    let handler_src = match fs::read_to_string(handler_path) {
+        Ok(src) => src,
+        Err(err) => {
+            return wrap_err!("server.serve: unable to read handler module '{}': {}", handler_path, err);
+        }
     };
 
-    // Collect headers
-    let headers_table = luau.create_table()?;
-    let mut i = 1;
-    while i < lines.len() && !lines[i].is_empty() {
-        let mut header_line = lines[i].to_string();
-        while i + 1 < lines.len() && lines[i + 1].starts_with(' ') {
-            i += 1;
-            header_line.push_str(&format!("\r\n{}", lines[i]));
+    globals::set_globals(luau)?;
+    luau.globals().raw_set("script", TableBuilder::create(luau)?
+        .with_value("entry_path", handler_path)?
+        .with_value("src", handler_src.to_owned())?
+        .with_function("path", globals::get_script_path)?
+        .with_function("parent", globals::get_script_parent)?
+        .build()?
+    )?;
+
+    let no_middleware = luau.create_table()?;
+    match luau.load(handler_src).set_name(handler_path).eval::<LuaValue>()? {
+        LuaValue::Table(module) if module.contains_key("handler")? => {
+            ServeApp::new(module.raw_get("handler")?, &module)
+        },
+        handler @ (LuaValue::Function(_) | LuaValue::Table(_)) => ServeApp::new(handler, &no_middleware),
+        other => {
+            wrap_err!("server.serve: handler module '{}' should return a handler function or table of routes, got: {:#?}", handler_path, other)
         }
+    }
+}
 
-        if let Some((key, value)) = header_line.split_once(": ") {
-            headers_table.raw_set(key, value)?;
-        } else {
-            // sometimes we get fails to parse header line and IDK they look like half cut off words and stuff
-            // by that time we usually got some headers so let's just break out of it
-            // if we continue instead of break we basically hit a spinlock of Failed to parse header line stuff
-            break;
-            // return wrap_err!("server.serve: Failed to parse header line: {}", header_line);
+/// starts worker threads that each load the handler module into their own Luau VM, returning the
+/// channel to hand them connections on once they've all loaded it
+fn start_workers(handler_path: String, workers: usize, options: &ConnectionOptions) -> LuaResult<Sender<TcpStream>> {
+    let (connection_sender, connection_receiver) = unbounded::<TcpStream>();
+    let (ready_sender, ready_receiver) = bounded::<Result<(), String>>(workers);
+
+    for _ in 0..workers {
+        let connection_receiver = connection_receiver.clone();
+        let ready_sender = ready_sender.clone();
+        let handler_path = handler_path.clone();
+        let options = ConnectionOptions { queue: Some(connection_receiver.clone()), ..options.clone() };
+        thread::spawn(move || {
+            let luau = Lua::new();
+            let app = match load_handler_module(&luau, &handler_path) {
+                Ok(app) => {
+                    let _ = ready_sender.send(Ok(()));
+                    app
+                },
+                Err(err) => {
+                    let _ = ready_sender.send(Err(err.to_string()));
+                    return;
+                }
+            };
+            drop(ready_sender);
+            for stream in connection_receiver.iter() {
+                handle_connection(&luau, &app, stream, &options);
+            }
+        });
+    }
+    drop(ready_sender);
+
+    // make sure every worker managed to load the handler before we start accepting connections
+    for _ in 0..workers {
+        match ready_receiver.recv() {
+            Ok(Ok(())) => {},
+            Ok(Err(err)) => {
+                return wrap_err!("server.serve: worker failed to start: {}", err);
+            },
+            Err(_) => {
+                return wrap_err!("server.serve: worker exited before it could start");
+            }
         }
-        i += 1;
     }
 
-    let serve_request_info = TableBuilder::create(luau)?
-        .with_value("peer_address", peer_address)?
-        .with_value("method", method)?
-        .with_value("path", path)?
-        .with_value("headers", headers_table)?
-        .with_value("body", body)?
-        .with_value("raw_text", request_text.clone())?
-        .build_readonly()?;
-
-    let serve_response: LuaTable = match handler_function.call(serve_request_info) {
-        Ok(res) => match res {
-            LuaValue::Table(table) => table,
-            other => return wrap_err!("server.serve: handler_function should return a table, got: {:#?}", other),
-        },
-        Err(err) => return wrap_err!("server.serve: handler_function call failed with error: {}", err),
+    Ok(connection_sender)
+}
+
+fn serve_with_workers(luau: &Lua, listener: TcpListener, handler_path: String, workers: usize, options: ConnectionOptions) -> LuaValueResult {
+    let connection_sender = start_workers(handler_path, workers, &options)?;
+
+    std_net_serve_lifecycle::accept_in_foreground(luau, listener, |stream| {
+        match connection_sender.send(stream) {
+            Ok(()) => Ok(()),
+            Err(_) => {
+                wrap_err!("server.serve: all workers have exited")
+            }
+        }
+    })?;
+    Ok(LuaValue::Nil)
+}
+
+/// serves on worker threads without blocking the script, returning a handle to stop the server with
+fn serve_in_background(luau: &Lua, listener: TcpListener, handler_path: String, workers: usize, options: ConnectionOptions) -> LuaValueResult {
+    let address = match listener.local_addr() {
+        Ok(address) => address,
+        Err(err) => {
+            return wrap_err!("server.serve: unable to get the server's address: {}", err);
+        }
     };
+    let lifecycle = Arc::clone(&options.lifecycle);
+    let connection_sender = start_workers(handler_path, workers, &options)?;
+    let done = match std_net_serve_lifecycle::accept_in_background(listener, connection_sender, Arc::clone(&lifecycle)) {
+        Ok(done) => done,
+        Err(err) => {
+            return wrap_err!("server.serve: unable to start accepting connections in the background: {}", err);
+        }
+    };
+    Ok(LuaValue::Table(std_net_serve_lifecycle::create_handle(luau, address, lifecycle, done)?))
+}
 
-    let status_code: String = match serve_response.raw_get("status_code") {
-        Ok(status) => status,
-        Err(err) => return wrap_err!("ServeResponse table missing 'status_code': {}", err),
+fn size_limit(config: &LuaTable, field: &str, default: usize) -> LuaResult<usize> {
+    match config.raw_get(field)? {
+        LuaValue::Nil => Ok(default),
+        LuaValue::Integer(bytes) if bytes > 0 => Ok(bytes as usize),
+        LuaValue::Number(bytes) if bytes > 0.0 && bytes.fract() == 0.0 => Ok(bytes as usize),
+        other => {
+            wrap_err!("server.serve expected {} to be a positive number of bytes, got: {:#?}", field, other)
+        }
+    }
+}
+
+fn server_serve(luau: &Lua, serve_config: LuaValue) -> LuaValueResult {
+    let config = match serve_config {
+        LuaValue::Table(config) => config,
+        other => {
+            return wrap_err!("server.serve expected ServeConfig table (with fields address, port, handler, etc.), got: {:#?}", other);
+        }
     };
 
-    let content_type: String = match serve_response.raw_get("content_type") {
-        Ok(content_type) => {
-            if let LuaValue::String(response) = content_type {
-                let response = response.to_string_lossy();
-                match response.to_lowercase().as_str() {
-                    "text" => "text/plain; charset=utf-8".to_string(),
-                    "html" => "text/html; charset=utf-8".to_string(),
-                    "json" => "application/json".to_string(),
-                    "xml"  => "application/xml".to_string(),
-                    "css"  => "text/css".to_string(),
-                    "binary" => "application/octet-stream".to_string(),
-                    other => other.to_string()
-                }
-            } else {
-                return wrap_err!("ServeResponse expected content_type to be a string, got: {:#?}", content_type);
+    let address: String = match config.raw_get("address") {
+        Ok(address) => address,
+        Err(err) => {
+            return wrap_err!("server.serve expected an address (string), got an error: {}", err);
+        }
+    };
+
+    let port: String = match config.raw_get("port") {
+        Ok(port) => match port {
+            LuaValue::String(port) => port.to_string_lossy(),
+            LuaValue::Integer(port) => port.to_string(),
+            other => {
+                return wrap_err!("server.serve expected a port, got: {:#?}", other);
             }
         },
-        Err(err) => return wrap_err!("ServeResponse table missing 'content_type': {}", err),
+        Err(err) => {
+            return wrap_err!("server.serve expected a port (string), got an error: {}", err);
+        }
     };
 
-    let mut buffer_body: Option<Vec<u8>> = None;
-    let body: String = match serve_response.raw_get("body") {
-        Ok(LuaValue::String(body)) => body.to_string_lossy(),
-        Ok(LuaValue::Buffer(buff)) => {
-            let bytes = buff.to_vec();
-            buffer_body = Some(bytes);
-            String::from("")
-        },
+    let handler = match config.raw_get("handler") {
+        Ok(handler @ (LuaValue::Function(_) | LuaValue::Table(_) | LuaValue::String(_))) => handler,
         Ok(other) => {
-            return wrap_err!("Expected body to be a string (or buffer), got: {:#?}", other);
+            return wrap_err!("server.serve expected handler to be a function, table of routes, or a path to a handler module, got: {:#?}", other);
+        }
+        Err(err) => {
+            return wrap_err!("server.serve expected some handler, got an error: {}", err);
         }
-        Err(err) => return wrap_err!("ServeResponse table missing 'body': {}", err),
     };
 
-    let headers: Option<LuaTable> = serve_response.raw_get("headers").ok();
-    let cookies: Option<LuaTable> = serve_response.raw_get("cookies").ok();
-    let http_version: Option<String> = serve_response.raw_get("http_version").ok();
-    let reason_phrase: Option<String> = serve_response.raw_get("reason_phrase").ok();
-    let redirect_url: Option<String> = serve_response.raw_get("redirect_url").ok();
+    let keep_alive_timeout: Option<Duration> = match config.raw_get("keep_alive_timeout")? {
+        // a single-threaded server can't accept anyone else while it holds an idle connection open,
+        // so keep-alive is on by default only when serving with workers
+        LuaValue::Nil if matches!(handler, LuaValue::String(_)) => Some(Duration::from_secs_f64(DEFAULT_KEEP_ALIVE_TIMEOUT)),
+        LuaValue::Nil => None,
+        LuaValue::Integer(0) => None,
+        LuaValue::Integer(seconds) if seconds > 0 => Some(Duration::from_secs(seconds as u64)),
+        LuaValue::Number(0.0) => None,
+        LuaValue::Number(seconds) if seconds > 0.0 => Some(Duration::from_secs_f64(seconds)),
+        other => {
+            return wrap_err!("server.serve expected keep_alive_timeout to be a positive number of seconds (or 0 to disable keep-alive), got: {:#?}", other);
+        }
+    };
 
-    let http_version = http_version.unwrap_or_else(|| "HTTP/1.1".to_string());
-    let reason_phrase = reason_phrase.unwrap_or_else(|| "OK".to_string());
+    let default_limits = RequestLimits::default();
+    let limits = RequestLimits {
+        max_header_size: size_limit(&config, "max_header_size", default_limits.max_header_size)?,
+        max_body_size: size_limit(&config, "max_body_size", default_limits.max_body_size)?,
+    };
+    let tls = std_net_serve_tls::server_config(config.raw_get("tls")?, &address)?;
+    let options = ConnectionOptions { keep_alive_timeout, limits, tls, lifecycle: Arc::default(), queue: None };
 
-    // Construct headers and cookies
-    let mut additional_headers = String::new();
-    if let Some(headers_table) = headers {
-        for pair in headers_table.pairs::<LuaString, LuaString>().flatten() {
-            let (key, value) = pair;
-            additional_headers.push_str(&format!("{}: {}\r\n", key.to_str()?, value.to_str()?));
+    let background = match config.raw_get("background")? {
+        LuaValue::Nil => false,
+        LuaValue::Boolean(background) => background,
+        other => {
+            return wrap_err!("server.serve expected background to be a boolean or nil, got: {:#?}", other);
         }
-    }
-    if let Some(cookies_table) = cookies {
-        for pair in cookies_table.pairs::<LuaString, LuaString>().flatten() {
-            let (key, value) = pair;
-            additional_headers.push_str(&format!("Set-Cookie: {}={}\r\n", key.to_str()?, value.to_str()?));
+    };
+
+    let workers: Option<usize> = match config.raw_get("workers")? {
+        LuaValue::Nil => None,
+        LuaValue::Integer(workers) if workers > 0 => Some(workers as usize),
+        other => {
+            return wrap_err!("server.serve expected workers to be a positive integer or nil, got: {:#?}", other);
         }
-    }
-    if let Some(url) = redirect_url {
-        additional_headers.push_str(&format!("Location: {}\r\n", url));
-    }
+    };
 
-    // Respond with the specified content
-    let response = format!("{} {} {}\r\nContent-Type: {}\r\n{}Content-Length: {}\r\n\r\n{}", 
-        http_version, status_code, reason_phrase, content_type, additional_headers, body.len(), body);
-    
-    // now we actually send and write to stream
-    // if someone passed in body = somebuffer then we have to write the buffer to the stream separately
-    match buffer_body {
-        Some(bytes) => {
-            match stream.write_all(response.as_bytes()).and_then(|_| stream.write_all(&bytes) ) {
-                Ok(_) => match stream.flush() {
-                    Ok(_) => Ok(LuaValue::Nil),
-                    Err(err) => wrap_err!("Failed to flush stream: {}", err),
-                },
-                Err(err) => wrap_err!("Failed to write response: {}", err),
-            }
+    let address_port = format!("{}:{}", address, port);
+    let listener = match TcpListener::bind(&address_port) {
+        Ok(listener) => listener,
+        Err(err) => {
+            return wrap_err!("server.serve: failed to bind to {} with error: {}", address_port, err);
         }
-        None => {
-            match stream.write_all(response.as_bytes()) {
-                Ok(_) => match stream.flush() {
-                    Ok(_) => Ok(LuaValue::Nil),
-                    Err(err) => wrap_err!("Failed to flush stream: {}", err),
-                },
-                Err(err) => wrap_err!("Failed to write response: {}", err),
+    };
+
+    match handler {
+        LuaValue::String(handler_path) => {
+            // worker Luau VMs can't share our functions, so they each load the handler module themselves
+            for field in ["before", "after", "on_error"] {
+                if !config.raw_get::<LuaValue>(field)?.is_nil() {
+                    return wrap_err!("server.serve: when handler is a module path, {} must be returned by the module (as `return {{ handler = ..., {} = ... }}`) instead of passed to serve", field, field);
+                }
             }
-        }
+            let handler_path = require::resolve_path(luau, handler_path.to_string_lossy())?;
+            let workers = workers.unwrap_or_else(|| {
+                thread::available_parallelism().map(|n| n.get()).unwrap_or(4)
+            });
+            if background {
+                serve_in_background(luau, listener, handler_path, workers, options)
+            } else {
+                serve_with_workers(luau, listener, handler_path, workers, options)
+            }
+        },
+        _handler if background => {
+            wrap_err!("server.serve: ServeConfig.background requires handler to be a path to a handler module (like \"./handler.luau\"), since the script keeps running while the server handles requests on other threads")
+        },
+        handler => {
+            if workers.is_some() {
+                return wrap_err!("server.serve: ServeConfig.workers requires handler to be a path to a handler module (like \"./handler.luau\"), since functions can't be shared between worker threads");
+            }
+            let app = ServeApp::new(handler, &config)?;
+            std_net_serve_lifecycle::accept_in_foreground(luau, listener, |stream| {
+                handle_connection(luau, &app, stream, &options);
+                Ok(())
+            })?;
+            Ok(LuaValue::Nil)
+        },
     }
 }
 
 pub fn create(luau: &Lua) -> LuaResult<LuaTable> {
     TableBuilder::create(luau)?
         .with_function("serve", server_serve)?
+        .with_function("static", std_net_serve_static::server_static)?
+        .with_function("websocket", server_websocket)?
+        .with_function("events", std_net_events::server_events)?
         .build_readonly()
 }
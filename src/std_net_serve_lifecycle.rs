@@ -0,0 +1,228 @@
+use crate::{colors, std_net_tcp, std_process_signals, table_helpers::TableBuilder, LuaValueResult};
+use mlua::prelude::*;
+use std::collections::HashMap;
+use std::io;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+
+/// how long `stop` waits for in-flight requests by default before hanging up on them
+const DEFAULT_GRACEFUL_TIMEOUT: f64 = 5.0;
+/// how often a server checks for new connections (or for being stopped, or for signals in the foreground)
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// how often `stop` checks whether in-flight connections have finished
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// how often `wait` checks for signals the script's handling
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+struct Connection {
+    stream: TcpStream,
+    /// waiting on the client for its next request, so it's safe to hang up on
+    idle: bool,
+}
+
+/// keeps track of a server's open connections so it can be stopped without cutting off requests mid-response
+#[derive(Default)]
+pub struct ServerLifecycle {
+    stopping: AtomicBool,
+    next_id: AtomicU64,
+    connections: Mutex<HashMap<u64, Connection>>,
+}
+
+impl ServerLifecycle {
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    /// starts tracking a connection until the returned guard is dropped; returns None if we're
+    /// already stopping, in which case the connection should just be closed
+    pub fn track(self: &Arc<Self>, stream: &TcpStream) -> Option<TrackedConnection> {
+        let stream = stream.try_clone().ok()?;
+        let mut connections = self.connections.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if self.is_stopping() {
+            return None;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        connections.insert(id, Connection { stream, idle: true });
+        Some(TrackedConnection { id, lifecycle: Arc::clone(self) })
+    }
+
+    /// stops new requests from being read and hangs up on idle keep-alive connections
+    fn begin_stop(&self) {
+        let connections = self.connections.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        self.stopping.store(true, Ordering::SeqCst);
+        for connection in connections.values().filter(|connection| connection.idle) {
+            let _ = connection.stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    fn open_connections(&self) -> usize {
+        self.connections.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).len()
+    }
+
+    fn close_all(&self) {
+        let connections = self.connections.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for connection in connections.values() {
+            let _ = connection.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+pub struct TrackedConnection {
+    id: u64,
+    lifecycle: Arc<ServerLifecycle>,
+}
+
+impl TrackedConnection {
+    /// marks the connection as waiting for its next request; returns false if the server's stopping
+    /// and the connection should close instead
+    pub fn wait_for_request(&self) -> bool {
+        self.set_idle(true)
+    }
+
+    /// marks the connection as busy with a request, which `stop` lets finish
+    pub fn begin_request(&self) {
+        self.set_idle(false);
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.lifecycle.is_stopping()
+    }
+
+    fn set_idle(&self, idle: bool) -> bool {
+        let mut connections = self.lifecycle.connections.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(connection) = connections.get_mut(&self.id) {
+            connection.idle = idle;
+        }
+        !self.lifecycle.is_stopping()
+    }
+}
+
+impl Drop for TrackedConnection {
+    fn drop(&mut self) {
+        let mut connections = self.lifecycle.connections.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        connections.remove(&self.id);
+    }
+}
+
+/// accepts connections on a background thread, handing them to workers until the server's stopped
+/// or every worker has exited; the returned receiver disconnects once the accept loop is done
+pub fn accept_in_background(listener: TcpListener, connection_sender: Sender<TcpStream>, lifecycle: Arc<ServerLifecycle>) -> io::Result<Receiver<()>> {
+    // std has no way to interrupt a blocking accept, so poll so we notice being stopped
+    listener.set_nonblocking(true)?;
+    let (done_sender, done_receiver) = crossbeam_channel::bounded::<()>(0);
+    thread::spawn(move || {
+        let _done_sender = done_sender;
+        while !lifecycle.is_stopping() {
+            match listener.accept() {
+                Ok((stream, _address)) => {
+                    // some platforms hand back accepted streams that inherited nonblocking mode
+                    if stream.set_nonblocking(false).is_err() {
+                        continue;
+                    }
+                    if connection_sender.send(stream).is_err() {
+                        eprintln!("{}[ERR]{}{} server.serve: all workers have exited, stopping the server{}",
+                            colors::BOLD_RED, colors::RESET, colors::RED, colors::RESET);
+                        break;
+                    }
+                },
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL_INTERVAL),
+                Err(err) => println!("Connection failed: {}", err),
+            }
+        }
+    });
+    Ok(done_receiver)
+}
+
+/// accepts connections on the script's own thread, handing each to `on_stream`; polls like `accept_in_background`
+/// so signal handlers the script's registered run while we're waiting for the next connection
+pub fn accept_in_foreground(luau: &Lua, listener: TcpListener, mut on_stream: impl FnMut(TcpStream) -> LuaResult<()>) -> LuaResult<()> {
+    if let Err(err) = listener.set_nonblocking(true) {
+        return wrap_err!("server.serve: unable to start accepting connections: {}", err);
+    }
+    loop {
+        std_process_signals::dispatch_pending(luau)?;
+        match listener.accept() {
+            Ok((stream, _address)) => {
+                // some platforms hand back accepted streams that inherited nonblocking mode
+                if stream.set_nonblocking(false).is_err() {
+                    continue;
+                }
+                on_stream(stream)?;
+            },
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL_INTERVAL),
+            Err(err) => println!("Connection failed: {}", err),
+        }
+    }
+}
+
+fn handle_stop(lifecycle: &ServerLifecycle, done: &Receiver<()>, mut multivalue: LuaMultiValue) -> LuaValueResult {
+    let _s = multivalue.pop_front();
+    let graceful_timeout = match multivalue.pop_front() {
+        None | Some(LuaNil) => DEFAULT_GRACEFUL_TIMEOUT,
+        Some(LuaValue::Table(options)) => match options.raw_get("graceful_timeout")? {
+            LuaNil => DEFAULT_GRACEFUL_TIMEOUT,
+            LuaValue::Integer(seconds) if seconds >= 0 => seconds as f64,
+            LuaValue::Number(seconds) if seconds >= 0.0 => seconds,
+            other => {
+                return wrap_err!("Server:stop expected graceful_timeout to be a non-negative number of seconds, got: {:#?}", other);
+            }
+        },
+        Some(other) => {
+            return wrap_err!("Server:stop(options: {{ graceful_timeout: number? }}?) expected options to be a table or nil, got: {:#?}", other);
+        }
+    };
+
+    lifecycle.begin_stop();
+    let deadline = Instant::now() + Duration::from_secs_f64(graceful_timeout);
+    while lifecycle.open_connections() > 0 && Instant::now() < deadline {
+        thread::sleep(DRAIN_POLL_INTERVAL);
+    }
+    // anyone still mid-request (or holding a websocket open) past the deadline gets cut off
+    lifecycle.close_all();
+    // the accept loop notices we're stopping within a poll interval
+    let _ = done.recv();
+    Ok(LuaNil)
+}
+
+fn handle_wait(luau: &Lua, done: &Receiver<()>, mut multivalue: LuaMultiValue) -> LuaResult<bool> {
+    let _s = multivalue.pop_front();
+    let timeout = std_net_tcp::timeout_from_value(multivalue.pop_front(), "Server:wait(timeout: number?)")?;
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        // waiting in slices so a SIGINT handler can stop the server while we're waiting on it
+        std_process_signals::dispatch_pending(luau)?;
+        let slice = match deadline {
+            Some(deadline) => WAIT_POLL_INTERVAL.min(deadline.saturating_duration_since(Instant::now())),
+            None => WAIT_POLL_INTERVAL,
+        };
+        match done.recv_timeout(slice) {
+            Err(RecvTimeoutError::Timeout) => {
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    return Ok(false);
+                }
+            },
+            // nothing's ever sent, so the channel disconnecting means the accept loop has finished
+            _ => return Ok(true),
+        }
+    }
+}
+
+/// the handle `server.serve` returns when serving in the background
+pub fn create_handle(luau: &Lua, address: SocketAddr, lifecycle: Arc<ServerLifecycle>, done: Receiver<()>) -> LuaResult<LuaTable> {
+    TableBuilder::create(luau)?
+        .with_function("address", move |_luau: &Lua, _multivalue: LuaMultiValue| -> LuaResult<String> {
+            Ok(address.to_string())
+        })?
+        .with_function("stop", {
+            let lifecycle = Arc::clone(&lifecycle);
+            let done = done.clone();
+            move |_luau: &Lua, multivalue: LuaMultiValue| handle_stop(&lifecycle, &done, multivalue)
+        })?
+        .with_function("wait", move |luau: &Lua, multivalue: LuaMultiValue| handle_wait(luau, &done, multivalue))?
+        .build_readonly()
+}
@@ -0,0 +1,414 @@
+use crate::{std_net_url, table_helpers::TableBuilder};
+use mlua::prelude::*;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+/// default cap on the request line + headers (and chunked trailers), past which we respond 431
+pub const DEFAULT_MAX_HEADER_SIZE: usize = 64 * 1024;
+/// default cap on the (decoded) request body, past which we respond 413
+pub const DEFAULT_MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+const MAX_HEADER_COUNT: usize = 128;
+const MAX_CHUNK_SIZE_LINE: usize = 1024;
+
+#[derive(Clone, Copy)]
+pub struct RequestLimits {
+    pub max_header_size: usize,
+    pub max_body_size: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        RequestLimits {
+            max_header_size: DEFAULT_MAX_HEADER_SIZE,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+}
+
+/// why we couldn't read a request; each maps to the response we send before closing the connection
+pub enum RequestError {
+    BadRequest(String),
+    HeadersTooLarge,
+    PayloadTooLarge,
+    ExpectationFailed,
+    UnsupportedVersion,
+    /// the client went away or the socket errored, nothing left to respond to
+    Io(io::Error),
+}
+
+impl RequestError {
+    pub fn status(&self) -> Option<&'static str> {
+        match self {
+            RequestError::BadRequest(_) => Some("400 Bad Request"),
+            RequestError::HeadersTooLarge => Some("431 Request Header Fields Too Large"),
+            RequestError::PayloadTooLarge => Some("413 Payload Too Large"),
+            RequestError::ExpectationFailed => Some("417 Expectation Failed"),
+            RequestError::UnsupportedVersion => Some("505 HTTP Version Not Supported"),
+            RequestError::Io(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for RequestError {
+    fn from(err: io::Error) -> Self {
+        RequestError::Io(err)
+    }
+}
+
+fn bad_request<T>(message: impl Into<String>) -> Result<T, RequestError> {
+    Err(RequestError::BadRequest(message.into()))
+}
+
+pub struct ServeRequest {
+    pub method: String,
+    /// the request target as sent, including any query string
+    pub target: String,
+    pub http_version: String,
+    /// in the order received, with their original casing
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    head: String,
+}
+
+impl ServeRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _value)| key.eq_ignore_ascii_case(name))
+            .map(|(_key, value)| value.as_str())
+    }
+
+    fn header_values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.headers.iter()
+            .filter(move |(key, _value)| key.eq_ignore_ascii_case(name))
+            .map(|(_key, value)| value.as_str())
+    }
+
+    /// HTTP/1.1 connections stay open unless the client asks us to close them, HTTP/1.0 connections
+    /// only stay open if the client explicitly asks for keep-alive
+    pub fn wants_keep_alive(&self) -> bool {
+        let connection = self.header("Connection").map(|c| c.to_ascii_lowercase());
+        if self.http_version == "HTTP/1.0" {
+            connection.is_some_and(|c| c.contains("keep-alive"))
+        } else {
+            !connection.is_some_and(|c| c.contains("close"))
+        }
+    }
+
+    /// a websocket handshake per RFC 6455 section 4.2.1
+    pub fn is_websocket_upgrade(&self) -> bool {
+        let has_token = |header: &str, token: &str| {
+            self.header_values(header)
+                .flat_map(|value| value.split(','))
+                .any(|value| value.trim().eq_ignore_ascii_case(token))
+        };
+        self.method == "GET"
+            && has_token("Upgrade", "websocket")
+            && has_token("Connection", "upgrade")
+            && self.header("Sec-WebSocket-Version") == Some("13")
+            && self.header("Sec-WebSocket-Key").is_some()
+    }
+
+    /// the request target without its query string
+    pub fn path(&self) -> &str {
+        match self.target.split_once('?') {
+            Some((path, _query)) => path,
+            None => &self.target,
+        }
+    }
+
+    fn query_string(&self) -> &str {
+        match self.target.split_once('?') {
+            Some((_path, query)) => query,
+            None => "",
+        }
+    }
+
+    /// `headers` is keyed by lowercased header name, with repeated headers joined by ", " (or "; " for Cookie),
+    /// and looks up names case-insensitively; `header_list` keeps every header as sent.
+    /// not readonly so middleware can attach extra info (like an authenticated user) for handlers
+    pub fn to_table(&self, luau: &Lua, peer_address: &str) -> LuaResult<LuaTable> {
+        let headers_table = luau.create_table()?;
+        let header_list = luau.create_table()?;
+        for (key, _value) in &self.headers {
+            let name = key.to_ascii_lowercase();
+            if headers_table.contains_key(name.as_str())? {
+                continue;
+            }
+            let separator = if name == "cookie" { "; " } else { ", " };
+            let joined = self.header_values(&name).collect::<Vec<&str>>().join(separator);
+            headers_table.raw_set(name, joined)?;
+        }
+        for (key, value) in &self.headers {
+            header_list.raw_push(TableBuilder::create(luau)?
+                .with_value("name", key.as_str())?
+                .with_value("value", value.as_str())?
+                .build_readonly()?
+            )?;
+        }
+        headers_table.set_metatable(Some(case_insensitive_metatable(luau)?));
+
+        let mut raw_text = self.head.clone();
+        if !self.body.is_empty() {
+            raw_text.push('\n');
+            raw_text.push_str(&String::from_utf8_lossy(&self.body));
+        }
+
+        TableBuilder::create(luau)?
+            .with_value("peer_address", peer_address)?
+            .with_value("method", self.method.as_str())?
+            .with_value("path", self.path())?
+            .with_value("http_version", self.http_version.as_str())?
+            .with_value("query", std_net_url::parse_query_first(luau, self.query_string())?)?
+            .with_value("query_all", std_net_url::parse_query(luau, self.query_string())?)?
+            .with_value("params", luau.create_table()?)?
+            .with_value("headers", headers_table)?
+            .with_value("header_list", header_list)?
+            // Luau strings hold any bytes, so `body` is always a string; `body_buffer` is there for binary bodies
+            .with_value("body", luau.create_string(&self.body)?)?
+            .with_value("body_buffer", luau.create_buffer(&self.body)?)?
+            .with_value("raw_text", raw_text)?
+            .build()
+    }
+}
+
+/// lets `request.headers["Content-Type"]` find the lowercased `content-type` key
+fn case_insensitive_metatable(luau: &Lua) -> LuaResult<LuaTable> {
+    TableBuilder::create(luau)?
+        .with_function("__index", |_luau: &Lua, (headers, key): (LuaTable, LuaValue)| -> LuaResult<LuaValue> {
+            match key {
+                LuaValue::String(key) => {
+                    let lowercased = key.to_string_lossy().to_ascii_lowercase();
+                    headers.raw_get(lowercased)
+                },
+                _ => Ok(LuaValue::Nil),
+            }
+        })?
+        .build_readonly()
+}
+
+/// reads up to and including the next `\n`, failing with `too_long` once `limit` bytes have gone by;
+/// returns Ok(false) if the stream ended before anything was read
+fn read_limited_line<R: BufRead>(reader: &mut R, line: &mut Vec<u8>, limit: usize, too_long: fn() -> RequestError) -> Result<bool, RequestError> {
+    line.clear();
+    loop {
+        let available = reader.fill_buf()?;
+        if available.is_empty() {
+            if line.is_empty() {
+                return Ok(false);
+            }
+            return bad_request("connection closed in the middle of a request");
+        }
+        let (consumed, done) = match available.iter().position(|b| *b == b'\n') {
+            Some(index) => (index + 1, true),
+            None => (available.len(), false),
+        };
+        if line.len() + consumed > limit {
+            return Err(too_long());
+        }
+        line.extend_from_slice(&available[..consumed]);
+        reader.consume(consumed);
+        if done {
+            while matches!(line.last(), Some(b'\n' | b'\r')) {
+                line.pop();
+            }
+            return Ok(true);
+        }
+    }
+}
+
+fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// reads headers (or chunked trailers) until the blank line ending them
+fn read_header_fields<R: BufRead>(reader: &mut R, headers: &mut Vec<(String, String)>, head: &mut String, header_budget: &mut usize) -> Result<(), RequestError> {
+    let mut line = Vec::new();
+    loop {
+        if !read_limited_line(reader, &mut line, *header_budget, || RequestError::HeadersTooLarge)? {
+            return bad_request("connection closed in the middle of the request headers");
+        }
+        *header_budget = header_budget.saturating_sub(line.len() + 2);
+        if line.is_empty() {
+            return Ok(());
+        }
+        if headers.len() >= MAX_HEADER_COUNT {
+            return Err(RequestError::HeadersTooLarge);
+        }
+        if matches!(line[0], b' ' | b'\t') {
+            return bad_request("obsolete line folding in headers isn't supported");
+        }
+        let line = match std::str::from_utf8(&line) {
+            Ok(line) => line,
+            Err(_) => return bad_request("header isn't valid UTF-8"),
+        };
+        let Some((name, value)) = line.split_once(':') else {
+            return bad_request(format!("malformed header line: {}", line));
+        };
+        if !is_token(name) {
+            return bad_request(format!("invalid header name: {:?}", name));
+        }
+        head.push('\n');
+        head.push_str(line);
+        headers.push((name.to_string(), value.trim().to_string()));
+    }
+}
+
+enum BodyLength {
+    None,
+    Fixed(usize),
+    Chunked,
+}
+
+fn body_length(request: &ServeRequest) -> Result<BodyLength, RequestError> {
+    let transfer_encodings: Vec<&str> = request.header_values("Transfer-Encoding").collect();
+    let content_lengths: Vec<&str> = request.header_values("Content-Length").collect();
+
+    if !transfer_encodings.is_empty() {
+        // a request with both is a classic request smuggling vector, so refuse it outright
+        if !content_lengths.is_empty() {
+            return bad_request("request has both Transfer-Encoding and Content-Length");
+        }
+        let last_coding = transfer_encodings.join(",");
+        let last_coding = last_coding.rsplit(',').next().unwrap_or_default().trim();
+        return if last_coding.eq_ignore_ascii_case("chunked") {
+            Ok(BodyLength::Chunked)
+        } else {
+            bad_request(format!("unsupported Transfer-Encoding: {}", last_coding))
+        };
+    }
+
+    let mut length: Option<usize> = None;
+    for value in content_lengths.iter().flat_map(|v| v.split(',')) {
+        let value = value.trim();
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return bad_request(format!("invalid Content-Length: {}", value));
+        }
+        let parsed = value.parse::<usize>().unwrap_or(usize::MAX);
+        match length {
+            Some(existing) if existing != parsed => {
+                return bad_request("conflicting Content-Length headers");
+            },
+            _ => length = Some(parsed),
+        }
+    }
+    Ok(match length {
+        Some(0) | None => BodyLength::None,
+        Some(length) => BodyLength::Fixed(length),
+    })
+}
+
+fn read_chunked_body<R: BufRead>(reader: &mut R, limits: &RequestLimits, request: &mut ServeRequest, header_budget: &mut usize) -> Result<(), RequestError> {
+    let mut line = Vec::new();
+    loop {
+        if !read_limited_line(reader, &mut line, MAX_CHUNK_SIZE_LINE, || RequestError::BadRequest(String::from("chunk size line too long")))? {
+            return bad_request("connection closed in the middle of a chunked body");
+        }
+        let size_line = String::from_utf8_lossy(&line);
+        // ignore chunk extensions
+        let size_str = size_line.split(';').next().unwrap_or_default().trim();
+        let size = match usize::from_str_radix(size_str, 16) {
+            Ok(size) => size,
+            Err(_) => return bad_request(format!("invalid chunk size: {:?}", size_str)),
+        };
+        if size == 0 {
+            break;
+        }
+        if request.body.len().saturating_add(size) > limits.max_body_size {
+            return Err(RequestError::PayloadTooLarge);
+        }
+        let start = request.body.len();
+        request.body.resize(start + size, 0);
+        reader.read_exact(&mut request.body[start..])?;
+        let mut crlf = [0u8; 2];
+        reader.read_exact(&mut crlf)?;
+        if &crlf != b"\r\n" {
+            return bad_request("chunk data not followed by CRLF");
+        }
+    }
+    // trailers count against the header limit like any other header
+    let mut trailers = Vec::new();
+    read_header_fields(reader, &mut trailers, &mut request.head, header_budget)?;
+    request.headers.extend(trailers);
+    Ok(())
+}
+
+/// reads one request off a (possibly kept-alive) connection, returning Ok(None) if the client closed
+/// the connection (or let it idle past the read timeout) before sending anything.
+/// the underlying stream is only written to when sending `100 Continue` to clients that ask for it
+pub fn read_request<S: Read + Write>(reader: &mut BufReader<S>, limits: &RequestLimits) -> Result<Option<ServeRequest>, RequestError> {
+    let mut header_budget = limits.max_header_size;
+    let mut line = Vec::new();
+
+    // tolerate stray newlines between kept-alive requests
+    loop {
+        match read_limited_line(reader, &mut line, header_budget, || RequestError::HeadersTooLarge) {
+            Ok(false) => return Ok(None),
+            Ok(true) if line.is_empty() => continue,
+            Ok(true) => break,
+            Err(RequestError::Io(err)) if line.is_empty() && matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                return Ok(None);
+            },
+            Err(err) => return Err(err),
+        }
+    }
+    header_budget = header_budget.saturating_sub(line.len() + 2);
+
+    let request_line = match std::str::from_utf8(&line) {
+        Ok(request_line) => request_line.to_string(),
+        Err(_) => return bad_request("request line isn't valid UTF-8"),
+    };
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target), Some(http_version), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+        return bad_request(format!("malformed request line: {}", request_line));
+    };
+    if !is_token(method) || target.is_empty() {
+        return bad_request(format!("malformed request line: {}", request_line));
+    }
+    match http_version {
+        "HTTP/1.1" | "HTTP/1.0" => {},
+        version if version.starts_with("HTTP/") => return Err(RequestError::UnsupportedVersion),
+        _ => return bad_request(format!("malformed request line: {}", request_line)),
+    }
+
+    let mut request = ServeRequest {
+        method: method.to_string(),
+        target: target.to_string(),
+        http_version: http_version.to_string(),
+        headers: Vec::new(),
+        body: Vec::new(),
+        head: request_line.clone(),
+    };
+    read_header_fields(reader, &mut request.headers, &mut request.head, &mut header_budget)?;
+
+    if request.http_version == "HTTP/1.1" && request.header_values("Host").count() != 1 {
+        return bad_request("HTTP/1.1 requests must have exactly one Host header");
+    }
+
+    let body_length = body_length(&request)?;
+    if let BodyLength::Fixed(length) = body_length {
+        if length > limits.max_body_size {
+            return Err(RequestError::PayloadTooLarge);
+        }
+    }
+
+    if let Some(expect) = request.header("Expect") {
+        if !expect.eq_ignore_ascii_case("100-continue") {
+            return Err(RequestError::ExpectationFailed);
+        }
+        if request.http_version == "HTTP/1.1" && !matches!(body_length, BodyLength::None) {
+            let writer = reader.get_mut();
+            writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+            writer.flush()?;
+        }
+    }
+
+    match body_length {
+        BodyLength::None => {},
+        BodyLength::Fixed(length) => {
+            request.body = vec![0; length];
+            reader.read_exact(&mut request.body)?;
+        },
+        BodyLength::Chunked => read_chunked_body(reader, limits, &mut request, &mut header_budget)?,
+    }
+
+    Ok(Some(request))
+}
@@ -0,0 +1,198 @@
+use mlua::prelude::*;
+
+use crate::{colors, std_net_url, table_helpers::TableBuilder};
+
+enum RouteSegment {
+    Literal(String),
+    Param(String),
+    Wildcard,
+}
+
+impl RouteSegment {
+    /// literal segments are more specific than params, which are more specific than wildcards
+    fn rank(&self) -> u8 {
+        match self {
+            RouteSegment::Literal(_) => 0,
+            RouteSegment::Param(_) => 1,
+            RouteSegment::Wildcard => 2,
+        }
+    }
+}
+
+struct Route {
+    method: Option<String>,
+    segments: Vec<RouteSegment>,
+    handler: LuaFunction,
+}
+
+impl Route {
+    /// parses route keys like `"GET /users/:id"`, `"POST /upload"`, or `"/static/*"` (any method)
+    fn parse(route_key: &str, handler: LuaFunction) -> LuaResult<Self> {
+        let route_key = route_key.trim();
+        let (method, pattern) = match route_key.split_once(' ') {
+            Some((method, pattern)) => (Some(method.trim().to_ascii_uppercase()), pattern.trim()),
+            None => (None, route_key),
+        };
+
+        if let Some(method) = &method {
+            if method.is_empty() || !method.chars().all(|c| c.is_ascii_alphabetic()) {
+                return wrap_err!("server.serve: invalid HTTP method in route '{}'", route_key);
+            }
+        }
+        if !pattern.starts_with('/') {
+            return wrap_err!("server.serve: route '{}' should look like \"GET /path\" or \"/path\"", route_key);
+        }
+
+        let raw_segments: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+        let mut segments = Vec::with_capacity(raw_segments.len());
+        for (index, segment) in raw_segments.iter().enumerate() {
+            if *segment == "*" {
+                if index != raw_segments.len() - 1 {
+                    return wrap_err!("server.serve: wildcard '*' must be the last segment of route '{}'", route_key);
+                }
+                segments.push(RouteSegment::Wildcard);
+            } else if let Some(param_name) = segment.strip_prefix(':') {
+                if param_name.is_empty() {
+                    return wrap_err!("server.serve: route '{}' has a path param without a name", route_key);
+                }
+                segments.push(RouteSegment::Param(param_name.to_string()));
+            } else {
+                segments.push(RouteSegment::Literal(segment.to_string()));
+            }
+        }
+
+        Ok(Route { method, segments, handler })
+    }
+
+    /// returns the captured path params if the path matches this route's pattern
+    fn match_path(&self, path_segments: &[&str]) -> Option<RouteParams> {
+        let mut params = Vec::new();
+        for (index, segment) in self.segments.iter().enumerate() {
+            match segment {
+                RouteSegment::Wildcard => {
+                    let rest = path_segments.get(index..).unwrap_or_default();
+                    let rest: Vec<String> = rest.iter().map(|s| std_net_url::decode_component(s)).collect();
+                    params.push((String::from("*"), rest.join("/")));
+                    return Some(params);
+                },
+                RouteSegment::Literal(literal) => {
+                    match path_segments.get(index) {
+                        Some(path_segment) if std_net_url::decode_component(path_segment) == *literal => {},
+                        _ => return None,
+                    }
+                },
+                RouteSegment::Param(name) => {
+                    match path_segments.get(index) {
+                        Some(path_segment) => params.push((name.to_owned(), std_net_url::decode_component(path_segment))),
+                        None => return None,
+                    }
+                },
+            }
+        }
+        if path_segments.len() == self.segments.len() {
+            Some(params)
+        } else {
+            None
+        }
+    }
+}
+
+/// `(name, value)` for each `:param` (and `*`) a path matched
+type RouteParams = Vec<(String, String)>;
+
+pub struct Router {
+    /// sorted from most to least specific so the first match wins
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn from_table(routes_table: LuaTable) -> LuaResult<Self> {
+        let mut routes = Vec::new();
+        for pair in routes_table.pairs::<LuaValue, LuaValue>() {
+            let (route_key, handler) = pair?;
+            let route_key = match route_key {
+                LuaValue::String(route_key) => route_key.to_string_lossy(),
+                other => {
+                    return wrap_err!("server.serve: route keys should be strings like \"GET /users/:id\", got: {:#?}", other);
+                }
+            };
+            let handler = match handler {
+                LuaValue::Function(handler) => handler,
+                other => {
+                    return wrap_err!("server.serve: route '{}' expected a handler function, got: {:#?}", route_key, other);
+                }
+            };
+            routes.push(Route::parse(&route_key, handler)?);
+        }
+
+        routes.sort_by(|a, b| {
+            let a_ranks = a.segments.iter().map(RouteSegment::rank);
+            let b_ranks = b.segments.iter().map(RouteSegment::rank);
+            a_ranks.cmp(b_ranks).then_with(|| b.method.is_some().cmp(&a.method.is_some()))
+        });
+
+        Ok(Router { routes })
+    }
+
+    /// the best route for `method` and the path's params, or the methods the path does accept
+    fn find_route(&self, method: &str, path_segments: &[&str]) -> Result<(&Route, RouteParams), Vec<&str>> {
+        let mut allowed_methods: Vec<&str> = Vec::new();
+        for route in &self.routes {
+            let Some(params) = route.match_path(path_segments) else {
+                continue;
+            };
+            match &route.method {
+                Some(route_method) if route_method != method => {
+                    if !allowed_methods.contains(&route_method.as_str()) {
+                        allowed_methods.push(route_method);
+                    }
+                },
+                _ => return Ok((route, params)),
+            }
+        }
+        Err(allowed_methods)
+    }
+
+    /// calls the best matching route with `request.params` filled in, or builds a 404 or 405 response
+    pub fn dispatch(&self, luau: &Lua, method: &str, path: &str, request: LuaTable) -> LuaResult<LuaValue> {
+        let path_segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+        // HEAD falls back to the GET route, since build_response leaves the body out of HEAD responses anyway
+        let found = match self.find_route(method, &path_segments) {
+            Err(_) if method == "HEAD" => self.find_route("GET", &path_segments),
+            found => found,
+        };
+        match found {
+            Ok((route, params)) => {
+                let params_table = luau.create_table()?;
+                for (name, value) in params {
+                    params_table.raw_set(name, value)?;
+                }
+                request.raw_set("params", params_table)?;
+                route.handler.call::<LuaValue>(request)
+            },
+            Err(allowed_methods) if allowed_methods.is_empty() => {
+                Ok(LuaValue::Table(automatic_response(luau, "404 Not Found", None)?))
+            },
+            Err(mut allowed_methods) => {
+                if allowed_methods.contains(&"GET") && !allowed_methods.contains(&"HEAD") {
+                    allowed_methods.push("HEAD");
+                }
+                Ok(LuaValue::Table(automatic_response(luau, "405 Method Not Allowed", Some(allowed_methods.join(", ")))?))
+            },
+        }
+    }
+}
+
+fn automatic_response(luau: &Lua, status_code: &str, allow: Option<String>) -> LuaResult<LuaTable> {
+    let headers = luau.create_table()?;
+    if let Some(allow) = allow {
+        headers.raw_set("Allow", allow)?;
+    }
+    TableBuilder::create(luau)?
+        .with_value("status_code", status_code)?
+        .with_value("content_type", "text")?
+        .with_value("body", status_code)?
+        .with_value("headers", headers)?
+        .build()
+}
@@ -0,0 +1,242 @@
+use crate::{colors, std_net_url, table_helpers::TableBuilder};
+use mlua::prelude::*;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+struct StaticOptions {
+    root: PathBuf,
+    index: Option<String>,
+    cache_control: Option<String>,
+}
+
+enum ByteRange {
+    Full,
+    Partial { start: u64, end: u64 },
+    Unsatisfiable,
+}
+
+/// parses a single `bytes=start-end`, `bytes=start-`, or `bytes=-suffix` range; anything fancier
+/// (like multiple ranges) is allowed to fall back to the full file
+fn parse_range(range_header: &str, file_length: u64) -> ByteRange {
+    let Some(spec) = range_header.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+    let (start, end) = (start.trim(), end.trim());
+    let range = if start.is_empty() {
+        match end.parse::<u64>() {
+            Ok(0) => return ByteRange::Unsatisfiable,
+            Ok(suffix) => (file_length.saturating_sub(suffix), file_length.saturating_sub(1)),
+            Err(_) => return ByteRange::Full,
+        }
+    } else {
+        let Ok(start) = start.parse::<u64>() else {
+            return ByteRange::Full;
+        };
+        let end = if end.is_empty() {
+            file_length.saturating_sub(1)
+        } else {
+            match end.parse::<u64>() {
+                Ok(end) if end >= start => end.min(file_length.saturating_sub(1)),
+                _ => return ByteRange::Full,
+            }
+        };
+        (start, end)
+    };
+    if file_length == 0 || range.0 >= file_length {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Partial { start: range.0, end: range.1 }
+    }
+}
+
+/// the ETag changes whenever the file's size or modification time does
+fn etag(metadata: &fs::Metadata) -> String {
+    let modified = metadata.modified().ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since_epoch| since_epoch.as_nanos())
+        .unwrap_or_default();
+    format!("\"{:x}-{:x}\"", metadata.len(), modified)
+}
+
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.split(',')
+        .map(|candidate| candidate.trim())
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+/// maps the request path onto a file under root, refusing `..` segments and anything that
+/// resolves (through symlinks or otherwise) to somewhere outside root
+fn resolve_file(options: &StaticOptions, request_path: &str) -> Option<PathBuf> {
+    let mut path = options.root.clone();
+    for segment in request_path.split('/') {
+        let segment = std_net_url::decode_component(segment);
+        match segment.as_str() {
+            "" | "." => continue,
+            ".." => return None,
+            segment if segment.contains(['/', '\\', '\0']) || (cfg!(windows) && segment.contains(':')) => return None,
+            segment => path.push(segment),
+        }
+    }
+    if path.is_dir() {
+        path.push(options.index.as_ref()?);
+    }
+    let path = path.canonicalize().ok()?;
+    if path.starts_with(&options.root) && path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+fn text_response(luau: &Lua, status_code: &str, headers: LuaTable) -> LuaResult<LuaTable> {
+    TableBuilder::create(luau)?
+        .with_value("status_code", status_code)?
+        .with_value("content_type", "text")?
+        .with_value("body", status_code)?
+        .with_value("headers", headers)?
+        .build()
+}
+
+/// an iterator over `length` bytes of the file starting at `start`, for streaming the response body
+fn file_chunks(luau: &Lua, mut file: fs::File, start: u64, length: u64) -> LuaResult<LuaFunction> {
+    file.seek(SeekFrom::Start(start))?;
+    let mut remaining = length;
+    luau.create_function_mut(move |luau, _: LuaMultiValue| -> LuaResult<LuaValue> {
+        if remaining == 0 {
+            return Ok(LuaNil);
+        }
+        let mut chunk = vec![0; STREAM_CHUNK_SIZE.min(remaining as usize)];
+        file.read_exact(&mut chunk)?;
+        remaining -= chunk.len() as u64;
+        Ok(LuaValue::Buffer(luau.create_buffer(chunk)?))
+    })
+}
+
+fn serve_static_file(luau: &Lua, options: &StaticOptions, request: LuaTable) -> LuaResult<LuaTable> {
+    let method: String = request.raw_get("method")?;
+    let request_headers: LuaTable = request.raw_get("headers")?;
+    let headers = luau.create_table()?;
+
+    if method != "GET" && method != "HEAD" {
+        headers.raw_set("Allow", "GET, HEAD")?;
+        return text_response(luau, "405 Method Not Allowed", headers);
+    }
+
+    // when mounted on a wildcard route like "/static/*", serve the part the wildcard matched
+    let params: Option<LuaTable> = request.raw_get("params")?;
+    let request_path: String = match params.map(|params| params.raw_get::<Option<String>>("*")) {
+        Some(Ok(Some(wildcard))) => wildcard,
+        _ => request.raw_get("path")?,
+    };
+
+    let Some(path) = resolve_file(options, &request_path) else {
+        return text_response(luau, "404 Not Found", headers);
+    };
+    let file = fs::File::open(&path)?;
+    let metadata = file.metadata()?;
+    let file_length = metadata.len();
+    let etag = etag(&metadata);
+
+    headers.raw_set("ETag", etag.as_str())?;
+    headers.raw_set("Accept-Ranges", "bytes")?;
+    if let Some(cache_control) = &options.cache_control {
+        headers.raw_set("Cache-Control", cache_control.as_str())?;
+    }
+
+    if let Some(if_none_match) = request_headers.raw_get::<Option<String>>("if-none-match")? {
+        if etag_matches(&if_none_match, &etag) {
+            return text_response(luau, "304 Not Modified", headers);
+        }
+    }
+
+    let range = match request_headers.raw_get::<Option<String>>("range")? {
+        Some(range_header) => parse_range(&range_header, file_length),
+        None => ByteRange::Full,
+    };
+    let (status_code, start, length) = match range {
+        ByteRange::Full => ("200 OK", 0, file_length),
+        ByteRange::Partial { start, end } => {
+            headers.raw_set("Content-Range", format!("bytes {}-{}/{}", start, end, file_length))?;
+            ("206 Partial Content", start, end - start + 1)
+        },
+        ByteRange::Unsatisfiable => {
+            headers.raw_set("Content-Range", format!("bytes */{}", file_length))?;
+            return text_response(luau, "416 Range Not Satisfiable", headers);
+        }
+    };
+    headers.raw_set("Content-Length", length)?;
+
+    let content_type = mime_guess::from_path(&path).first_or_octet_stream().to_string();
+    TableBuilder::create(luau)?
+        .with_value("status_code", status_code)?
+        .with_value("content_type", content_type)?
+        .with_value("body", file_chunks(luau, file, start, length)?)?
+        .with_value("headers", headers)?
+        .build()
+}
+
+/// `server.static(dir: string, options: StaticOptions?) -> (ServeRequest) -> ServeResponse`
+pub fn server_static(luau: &Lua, mut multivalue: LuaMultiValue) -> LuaResult<LuaFunction> {
+    let dir = match multivalue.pop_front() {
+        Some(LuaValue::String(dir)) => dir.to_string_lossy(),
+        Some(other) => {
+            return wrap_err!("server.static(dir: string, options: StaticOptions?) expected dir to be a string, got: {:#?}", other);
+        },
+        None => {
+            return wrap_err!("server.static(dir: string, options: StaticOptions?) expected dir, got nothing");
+        }
+    };
+
+    let root = match Path::new(&dir).canonicalize() {
+        Ok(root) if root.is_dir() => root,
+        Ok(_) => {
+            return wrap_err!("server.static: '{}' isn't a directory", dir);
+        },
+        Err(err) => {
+            return wrap_err!("server.static: unable to find directory '{}': {}", dir, err);
+        }
+    };
+
+    let mut options = StaticOptions {
+        root,
+        index: Some(String::from("index.html")),
+        cache_control: None,
+    };
+    match multivalue.pop_front() {
+        Some(LuaValue::Table(options_table)) => {
+            options.index = match options_table.raw_get("index")? {
+                LuaValue::Nil => options.index,
+                LuaValue::Boolean(false) => None,
+                LuaValue::String(index) => Some(index.to_string_lossy()),
+                other => {
+                    return wrap_err!("server.static expected StaticOptions.index to be a filename string (or false to disable), got: {:#?}", other);
+                }
+            };
+            options.cache_control = match options_table.raw_get("cache_control")? {
+                LuaValue::Nil => None,
+                LuaValue::String(cache_control) => Some(cache_control.to_string_lossy()),
+                other => {
+                    return wrap_err!("server.static expected StaticOptions.cache_control to be a Cache-Control header string, got: {:#?}", other);
+                }
+            };
+        },
+        Some(LuaNil) | None => {},
+        Some(other) => {
+            return wrap_err!("server.static expected StaticOptions table or nil, got: {:#?}", other);
+        }
+    }
+
+    luau.create_function(move |luau, request: LuaTable| -> LuaResult<LuaTable> {
+        serve_static_file(luau, &options, request)
+    })
+}
@@ -0,0 +1,106 @@
+use crate::colors;
+use mlua::prelude::*;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::ServerConfig;
+use std::fs;
+use std::io::BufReader;
+use std::sync::Arc;
+
+fn read_certs(cert_path: &str) -> LuaResult<Vec<CertificateDer<'static>>> {
+    let cert_file = match fs::File::open(cert_path) {
+        Ok(file) => file,
+        Err(err) => {
+            return wrap_err!("server.serve: unable to open TLS certificate '{}': {}", cert_path, err);
+        }
+    };
+    let certs = match rustls_pemfile::certs(&mut BufReader::new(cert_file)).collect::<Result<Vec<_>, _>>() {
+        Ok(certs) => certs,
+        Err(err) => {
+            return wrap_err!("server.serve: unable to parse TLS certificate '{}': {}", cert_path, err);
+        }
+    };
+    if certs.is_empty() {
+        return wrap_err!("server.serve: no PEM certificates found in '{}'", cert_path);
+    }
+    Ok(certs)
+}
+
+fn read_private_key(key_path: &str) -> LuaResult<PrivateKeyDer<'static>> {
+    let key_file = match fs::File::open(key_path) {
+        Ok(file) => file,
+        Err(err) => {
+            return wrap_err!("server.serve: unable to open TLS private key '{}': {}", key_path, err);
+        }
+    };
+    match rustls_pemfile::private_key(&mut BufReader::new(key_file)) {
+        Ok(Some(key)) => Ok(key),
+        Ok(None) => {
+            wrap_err!("server.serve: no PEM private key (PKCS#1, PKCS#8, or SEC1) found in '{}'", key_path)
+        },
+        Err(err) => {
+            wrap_err!("server.serve: unable to parse TLS private key '{}': {}", key_path, err)
+        }
+    }
+}
+
+/// a throwaway certificate for local development, valid for localhost and the address we're serving on
+fn self_signed(address: &str) -> LuaResult<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let mut hostnames = vec![String::from("localhost"), String::from("127.0.0.1"), String::from("::1")];
+    if !hostnames.iter().any(|hostname| hostname == address) {
+        hostnames.push(address.to_string());
+    }
+    let certified = match rcgen::generate_simple_self_signed(hostnames) {
+        Ok(certified) => certified,
+        Err(err) => {
+            return wrap_err!("server.serve: unable to generate a self-signed certificate: {}", err);
+        }
+    };
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der()));
+    Ok((vec![certified.cert.der().clone()], key))
+}
+
+/// builds the rustls config from `ServeConfig.tls`, which is either `{ cert = path, key = path }`
+/// or `{ self_signed = true }`
+pub fn server_config(tls: LuaValue, address: &str) -> LuaResult<Option<Arc<ServerConfig>>> {
+    let tls = match tls {
+        LuaValue::Nil => return Ok(None),
+        LuaValue::Table(tls) => tls,
+        other => {
+            return wrap_err!("server.serve expected tls to be a table like {{ cert = \"cert.pem\", key = \"key.pem\" }} or {{ self_signed = true }}, got: {:#?}", other);
+        }
+    };
+
+    let cert: Option<String> = tls.raw_get("cert")?;
+    let key: Option<String> = tls.raw_get("key")?;
+    let self_signed_requested = match tls.raw_get("self_signed")? {
+        LuaValue::Nil | LuaValue::Boolean(false) => false,
+        LuaValue::Boolean(true) => true,
+        other => {
+            return wrap_err!("server.serve expected tls.self_signed to be a boolean, got: {:#?}", other);
+        }
+    };
+
+    let (certs, private_key) = match (cert, key, self_signed_requested) {
+        (Some(cert), Some(key), false) => (read_certs(&cert)?, read_private_key(&key)?),
+        (None, None, true) => self_signed(address)?,
+        (Some(_), Some(_), true) => {
+            return wrap_err!("server.serve: tls takes either cert and key paths or self_signed = true, not both");
+        },
+        _ => {
+            return wrap_err!("server.serve expected tls to have both cert and key paths (or self_signed = true)");
+        }
+    };
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, private_key));
+    let mut config = match config {
+        Ok(config) => config,
+        Err(err) => {
+            return wrap_err!("server.serve: invalid TLS configuration: {}", err);
+        }
+    };
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Some(Arc::new(config)))
+}
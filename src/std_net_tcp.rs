@@ -0,0 +1,441 @@
+use crate::{colors, table_helpers::TableBuilder, LuaValueResult};
+use mlua::prelude::*;
+use std::cell::RefCell;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DEFAULT_READ_SIZE: usize = 8192;
+/// how often a listener with an accept timeout checks for new connections
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// a buffered stream that can hand back bytes we've already taken out of its buffer
+struct BufferedStream {
+    reader: BufReader<TcpStream>,
+    /// the start of a line `read_line` timed out partway through; every read gets these bytes first,
+    /// so a timeout doesn't lose them
+    unread: Vec<u8>,
+}
+
+impl BufferedStream {
+    fn get_ref(&self) -> &TcpStream {
+        self.reader.get_ref()
+    }
+
+    fn get_mut(&mut self) -> &mut TcpStream {
+        self.reader.get_mut()
+    }
+}
+
+impl Read for BufferedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.unread.is_empty() {
+            return self.reader.read(buf);
+        }
+        let read = self.unread.len().min(buf.len());
+        buf[..read].copy_from_slice(&self.unread[..read]);
+        self.unread.drain(..read);
+        Ok(read)
+    }
+}
+
+impl BufRead for BufferedStream {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if !self.unread.is_empty() {
+            return Ok(&self.unread);
+        }
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        if self.unread.is_empty() {
+            self.reader.consume(amount);
+        } else {
+            self.unread.drain(..amount);
+        }
+    }
+}
+
+/// `None` once the stream has been closed
+type SharedStream = Rc<RefCell<Option<BufferedStream>>>;
+
+/// parses an optional timeout in seconds from Luau, where nil means no timeout
+pub fn timeout_from_value(value: Option<LuaValue>, function_name: &str) -> LuaResult<Option<Duration>> {
+    match value {
+        None | Some(LuaNil) => Ok(None),
+        Some(LuaValue::Integer(seconds)) if seconds > 0 => Ok(Some(Duration::from_secs(seconds as u64))),
+        Some(LuaValue::Number(seconds)) if seconds > 0.0 => Ok(Some(Duration::from_secs_f64(seconds))),
+        Some(other) => {
+            wrap_err!("{} expected timeout to be a positive number of seconds or nil, got: {:#?}", function_name, other)
+        }
+    }
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+/// turns io errors into Luau errors, calling out timeouts for the calls that can't just return nil
+/// (like writes, or `read_exact` once it's read part of what it needs)
+pub fn io_err<T>(function_name: &str, err: io::Error) -> LuaResult<T> {
+    if is_timeout(&err) {
+        wrap_err!("{}: timed out", function_name)
+    } else {
+        wrap_err!("{}: {}", function_name, err)
+    }
+}
+
+/// string or buffer contents to send over a socket
+pub fn bytes_from_value(value: Option<LuaValue>, function_name: &str) -> LuaResult<Vec<u8>> {
+    match value {
+        Some(LuaValue::String(data)) => Ok(data.as_bytes().to_vec()),
+        Some(LuaValue::Buffer(data)) => Ok(data.to_vec()),
+        Some(other) => {
+            wrap_err!("{} expected data to be a string or buffer, got: {:#?}", function_name, other)
+        },
+        None => {
+            wrap_err!("{} expected data (string or buffer), got nothing", function_name)
+        }
+    }
+}
+
+fn read_size(value: Option<LuaValue>, function_name: &str) -> LuaResult<usize> {
+    match value {
+        None | Some(LuaNil) => Ok(DEFAULT_READ_SIZE),
+        Some(LuaValue::Integer(size)) if size > 0 => Ok(size as usize),
+        Some(other) => {
+            wrap_err!("{} expected size to be a positive integer or nil, got: {:#?}", function_name, other)
+        }
+    }
+}
+
+fn with_stream<T>(stream: &SharedStream, function_name: &str, f: impl FnOnce(&mut BufferedStream) -> LuaResult<T>) -> LuaResult<T> {
+    match stream.borrow_mut().as_mut() {
+        Some(stream) => f(stream),
+        None => wrap_err!("{}: stream is closed", function_name),
+    }
+}
+
+enum ReadResult {
+    Data(Vec<u8>),
+    End,
+    TimedOut,
+}
+
+/// nil on timeout like `TcpListener:accept` and `UdpSocket:recv_from`, with "timed out" after it so
+/// scripts can tell a timeout apart from the end of the stream
+fn read_values(luau: &Lua, read: ReadResult, to_value: impl FnOnce(Vec<u8>) -> LuaValueResult) -> LuaResult<LuaMultiValue> {
+    match read {
+        ReadResult::Data(data) => to_value(data)?.into_lua_multi(luau),
+        ReadResult::End => LuaNil.into_lua_multi(luau),
+        ReadResult::TimedOut => (LuaNil, "timed out").into_lua_multi(luau),
+    }
+}
+
+/// reads whatever's available (up to size bytes)
+fn read_some(stream: &SharedStream, size: usize, function_name: &str) -> LuaResult<ReadResult> {
+    with_stream(stream, function_name, |stream| {
+        let mut data = vec![0; size];
+        match stream.read(&mut data) {
+            Ok(0) => Ok(ReadResult::End),
+            Ok(read) => {
+                data.truncate(read);
+                Ok(ReadResult::Data(data))
+            },
+            Err(err) if is_timeout(&err) => Ok(ReadResult::TimedOut),
+            Err(err) => io_err(function_name, err),
+        }
+    })
+}
+
+fn stream_read(luau: &Lua, stream: &SharedStream, mut multivalue: LuaMultiValue) -> LuaResult<LuaMultiValue> {
+    let _s = multivalue.pop_front();
+    let size = read_size(multivalue.pop_front(), "TcpStream:read(size: number?)")?;
+    let read = read_some(stream, size, "TcpStream:read")?;
+    read_values(luau, read, |data| Ok(LuaValue::String(luau.create_string(data)?)))
+}
+
+fn stream_read_buffer(luau: &Lua, stream: &SharedStream, mut multivalue: LuaMultiValue) -> LuaResult<LuaMultiValue> {
+    let _s = multivalue.pop_front();
+    let size = read_size(multivalue.pop_front(), "TcpStream:read_buffer(size: number?)")?;
+    let read = read_some(stream, size, "TcpStream:read_buffer")?;
+    read_values(luau, read, |data| Ok(LuaValue::Buffer(luau.create_buffer(data)?)))
+}
+
+fn stream_read_exact(luau: &Lua, stream: &SharedStream, mut multivalue: LuaMultiValue) -> LuaValueResult {
+    let _s = multivalue.pop_front();
+    let size = match multivalue.pop_front() {
+        Some(LuaValue::Integer(size)) if size >= 0 => size as usize,
+        other => {
+            return wrap_err!("TcpStream:read_exact(size: number) expected size to be a non-negative integer, got: {:#?}", other);
+        }
+    };
+    with_stream(stream, "TcpStream:read_exact", |stream| {
+        let mut data = vec![0; size];
+        match stream.read_exact(&mut data) {
+            Ok(()) => Ok(LuaValue::String(luau.create_string(data)?)),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                wrap_err!("TcpStream:read_exact: stream ended before {} bytes could be read", size)
+            },
+            Err(err) => io_err("TcpStream:read_exact", err),
+        }
+    })
+}
+
+fn stream_read_line(luau: &Lua, stream: &SharedStream, _multivalue: LuaMultiValue) -> LuaResult<LuaMultiValue> {
+    let read = with_stream(stream, "TcpStream:read_line", |stream| {
+        let mut line = Vec::new();
+        match stream.read_until(b'\n', &mut line) {
+            Ok(0) => Ok(ReadResult::End),
+            Ok(_) => {
+                if line.ends_with(b"\n") {
+                    line.pop();
+                    if line.ends_with(b"\r") {
+                        line.pop();
+                    }
+                }
+                Ok(ReadResult::Data(line))
+            },
+            Err(err) if is_timeout(&err) => {
+                // read_until has already taken these out of the buffer, so keep them for the next read
+                stream.unread = line;
+                Ok(ReadResult::TimedOut)
+            },
+            Err(err) => io_err("TcpStream:read_line", err),
+        }
+    })?;
+    read_values(luau, read, |line| Ok(LuaValue::String(luau.create_string(line)?)))
+}
+
+fn stream_write(stream: &SharedStream, mut multivalue: LuaMultiValue) -> LuaValueResult {
+    let _s = multivalue.pop_front();
+    let data = bytes_from_value(multivalue.pop_front(), "TcpStream:write(data: string | buffer)")?;
+    with_stream(stream, "TcpStream:write", |stream| {
+        let stream = stream.get_mut();
+        match stream.write_all(&data).and_then(|_| stream.flush()) {
+            Ok(()) => Ok(LuaNil),
+            Err(err) => io_err("TcpStream:write", err),
+        }
+    })
+}
+
+fn stream_shutdown(stream: &SharedStream, mut multivalue: LuaMultiValue) -> LuaValueResult {
+    let _s = multivalue.pop_front();
+    let how = match multivalue.pop_front() {
+        None | Some(LuaNil) => Shutdown::Both,
+        Some(LuaValue::String(how)) => match how.to_str()?.as_ref() {
+            "read" => Shutdown::Read,
+            "write" => Shutdown::Write,
+            "both" => Shutdown::Both,
+            other => {
+                return wrap_err!("TcpStream:shutdown(how: \"read\" | \"write\" | \"both\"?) got unexpected '{}'", other);
+            }
+        },
+        Some(other) => {
+            return wrap_err!("TcpStream:shutdown(how: \"read\" | \"write\" | \"both\"?) expected how to be a string, got: {:#?}", other);
+        }
+    };
+    with_stream(stream, "TcpStream:shutdown", |stream| {
+        match stream.get_ref().shutdown(how) {
+            Ok(()) => Ok(LuaNil),
+            // the other side already hung up
+            Err(err) if err.kind() == io::ErrorKind::NotConnected => Ok(LuaNil),
+            Err(err) => io_err("TcpStream:shutdown", err),
+        }
+    })
+}
+
+fn stream_set_timeout(stream: &SharedStream, mut multivalue: LuaMultiValue) -> LuaValueResult {
+    let _s = multivalue.pop_front();
+    let timeout = timeout_from_value(multivalue.pop_front(), "TcpStream:set_timeout(timeout: number?)")?;
+    with_stream(stream, "TcpStream:set_timeout", |stream| {
+        let stream = stream.get_ref();
+        match stream.set_read_timeout(timeout).and_then(|_| stream.set_write_timeout(timeout)) {
+            Ok(()) => Ok(LuaNil),
+            Err(err) => io_err("TcpStream:set_timeout", err),
+        }
+    })
+}
+
+fn stream_table(luau: &Lua, stream: TcpStream) -> LuaResult<LuaTable> {
+    let local_address = stream.local_addr().map(|address| address.to_string()).unwrap_or_default();
+    let peer_address = stream.peer_addr().map(|address| address.to_string()).unwrap_or_default();
+    let stream: SharedStream = Rc::new(RefCell::new(Some(BufferedStream { reader: BufReader::new(stream), unread: Vec::new() })));
+    TableBuilder::create(luau)?
+        .with_value("local_address", local_address)?
+        .with_value("peer_address", peer_address)?
+        .with_function("read", {
+            let stream = Rc::clone(&stream);
+            move |luau: &Lua, multivalue: LuaMultiValue| stream_read(luau, &stream, multivalue)
+        })?
+        .with_function("read_buffer", {
+            let stream = Rc::clone(&stream);
+            move |luau: &Lua, multivalue: LuaMultiValue| stream_read_buffer(luau, &stream, multivalue)
+        })?
+        .with_function("read_exact", {
+            let stream = Rc::clone(&stream);
+            move |luau: &Lua, multivalue: LuaMultiValue| stream_read_exact(luau, &stream, multivalue)
+        })?
+        .with_function("read_line", {
+            let stream = Rc::clone(&stream);
+            move |luau: &Lua, multivalue: LuaMultiValue| stream_read_line(luau, &stream, multivalue)
+        })?
+        .with_function("write", {
+            let stream = Rc::clone(&stream);
+            move |_luau: &Lua, multivalue: LuaMultiValue| stream_write(&stream, multivalue)
+        })?
+        .with_function("shutdown", {
+            let stream = Rc::clone(&stream);
+            move |_luau: &Lua, multivalue: LuaMultiValue| stream_shutdown(&stream, multivalue)
+        })?
+        .with_function("set_timeout", {
+            let stream = Rc::clone(&stream);
+            move |_luau: &Lua, multivalue: LuaMultiValue| stream_set_timeout(&stream, multivalue)
+        })?
+        .with_function("close", {
+            let stream = Rc::clone(&stream);
+            move |_luau: &Lua, _multivalue: LuaMultiValue| -> LuaValueResult {
+                stream.borrow_mut().take();
+                Ok(LuaNil)
+            }
+        })?
+        .build_readonly()
+}
+
+/// resolves host and port to every address they could mean, for trying each in turn
+pub fn resolve_addresses(host: &str, port: u16, function_name: &str) -> LuaResult<Vec<SocketAddr>> {
+    match (host, port).to_socket_addrs() {
+        Ok(addresses) => {
+            let addresses: Vec<SocketAddr> = addresses.collect();
+            if addresses.is_empty() {
+                wrap_err!("{}: '{}' didn't resolve to any addresses", function_name, host)
+            } else {
+                Ok(addresses)
+            }
+        },
+        Err(err) => wrap_err!("{}: unable to resolve '{}': {}", function_name, host, err),
+    }
+}
+
+pub fn port_from_value(value: Option<LuaValue>, function_name: &str) -> LuaResult<u16> {
+    match value {
+        Some(LuaValue::Integer(port)) if (0..=65535).contains(&port) => Ok(port as u16),
+        other => {
+            wrap_err!("{} expected port to be an integer between 0 and 65535, got: {:#?}", function_name, other)
+        }
+    }
+}
+
+fn tcp_connect(luau: &Lua, mut multivalue: LuaMultiValue) -> LuaValueResult {
+    let function_name = "tcp.connect(host: string, port: number, options: TcpConnectOptions?)";
+    let host = match multivalue.pop_front() {
+        Some(LuaValue::String(host)) => host.to_string_lossy(),
+        other => {
+            return wrap_err!("{} expected host to be a string, got: {:#?}", function_name, other);
+        }
+    };
+    let port = port_from_value(multivalue.pop_front(), function_name)?;
+    let timeout = match multivalue.pop_front() {
+        Some(LuaValue::Table(options)) => timeout_from_value(Some(options.raw_get("timeout")?), function_name)?,
+        None | Some(LuaNil) => None,
+        Some(other) => {
+            return wrap_err!("{} expected options to be a TcpConnectOptions table or nil, got: {:#?}", function_name, other);
+        }
+    };
+
+    let mut last_err: Option<io::Error> = None;
+    for address in resolve_addresses(&host, port, "tcp.connect")? {
+        let connected = match timeout {
+            Some(timeout) => TcpStream::connect_timeout(&address, timeout),
+            None => TcpStream::connect(address),
+        };
+        match connected {
+            Ok(stream) => return Ok(LuaValue::Table(stream_table(luau, stream)?)),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    match last_err {
+        Some(err) if is_timeout(&err) => wrap_err!("tcp.connect: connecting to {}:{} timed out", host, port),
+        Some(err) => wrap_err!("tcp.connect: unable to connect to {}:{}: {}", host, port, err),
+        None => wrap_err!("tcp.connect: unable to connect to {}:{}", host, port),
+    }
+}
+
+fn listener_accept(luau: &Lua, listener: &Rc<RefCell<Option<TcpListener>>>, mut multivalue: LuaMultiValue) -> LuaValueResult {
+    let _s = multivalue.pop_front();
+    let timeout = timeout_from_value(multivalue.pop_front(), "TcpListener:accept(timeout: number?)")?;
+    let listener = listener.borrow();
+    let Some(listener) = listener.as_ref() else {
+        return wrap_err!("TcpListener:accept: listener is closed");
+    };
+
+    let accepted = match timeout {
+        None => listener.accept(),
+        // std has no accept timeout, so poll a nonblocking listener until the deadline
+        Some(timeout) => {
+            if let Err(err) = listener.set_nonblocking(true) {
+                return io_err("TcpListener:accept", err);
+            }
+            let deadline = Instant::now() + timeout;
+            let accepted = loop {
+                match listener.accept() {
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock && Instant::now() < deadline => {
+                        thread::sleep(ACCEPT_POLL_INTERVAL);
+                    },
+                    result => break result,
+                }
+            };
+            // back to blocking whatever happened, so a later accept without a timeout waits like it should
+            let _ = listener.set_nonblocking(false);
+            match accepted {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(LuaNil),
+                accepted => accepted.and_then(|(stream, address)| stream.set_nonblocking(false).map(|_| (stream, address))),
+            }
+        }
+    };
+    match accepted {
+        Ok((stream, _address)) => Ok(LuaValue::Table(stream_table(luau, stream)?)),
+        Err(err) => io_err("TcpListener:accept", err),
+    }
+}
+
+fn tcp_listen(luau: &Lua, address: LuaValue) -> LuaValueResult {
+    let address = match address {
+        LuaValue::String(address) => address.to_string_lossy(),
+        other => {
+            return wrap_err!("tcp.listen(address: string) expected address like \"127.0.0.1:8080\" (port 0 picks a free port), got: {:#?}", other);
+        }
+    };
+    let listener = match TcpListener::bind(&address) {
+        Ok(listener) => listener,
+        Err(err) => {
+            return wrap_err!("tcp.listen: unable to bind to {}: {}", address, err);
+        }
+    };
+    let local_address = listener.local_addr().map(|address| address.to_string()).unwrap_or_default();
+    let listener = Rc::new(RefCell::new(Some(listener)));
+    Ok(LuaValue::Table(TableBuilder::create(luau)?
+        .with_value("address", local_address)?
+        .with_function("accept", {
+            let listener = Rc::clone(&listener);
+            move |luau: &Lua, multivalue: LuaMultiValue| listener_accept(luau, &listener, multivalue)
+        })?
+        .with_function("close", {
+            let listener = Rc::clone(&listener);
+            move |_luau: &Lua, _multivalue: LuaMultiValue| -> LuaValueResult {
+                listener.borrow_mut().take();
+                Ok(LuaNil)
+            }
+        })?
+        .build_readonly()?
+    ))
+}
+
+pub fn create(luau: &Lua) -> LuaResult<LuaTable> {
+    TableBuilder::create(luau)?
+        .with_function("connect", tcp_connect)?
+        .with_function("listen", tcp_listen)?
+        .build_readonly()
+}
@@ -0,0 +1,137 @@
+use crate::{colors, std_net_tcp, table_helpers::TableBuilder, LuaValueResult};
+use mlua::prelude::*;
+use std::cell::RefCell;
+use std::net::{SocketAddr, UdpSocket};
+use std::rc::Rc;
+
+/// big enough for any UDP datagram
+const MAX_DATAGRAM_SIZE: usize = 65536;
+
+type SharedSocket = Rc<RefCell<Option<UdpSocket>>>;
+
+fn with_socket<T>(socket: &SharedSocket, function_name: &str, f: impl FnOnce(&UdpSocket) -> LuaResult<T>) -> LuaResult<T> {
+    match socket.borrow().as_ref() {
+        Some(socket) => f(socket),
+        None => wrap_err!("{}: socket is closed", function_name),
+    }
+}
+
+fn socket_send_to(socket: &SharedSocket, mut multivalue: LuaMultiValue) -> LuaValueResult {
+    let function_name = "UdpSocket:send_to(data: string | buffer, host: string, port: number)";
+    let _s = multivalue.pop_front();
+    let data = std_net_tcp::bytes_from_value(multivalue.pop_front(), function_name)?;
+    let host = match multivalue.pop_front() {
+        Some(LuaValue::String(host)) => host.to_string_lossy(),
+        other => {
+            return wrap_err!("{} expected host to be a string, got: {:#?}", function_name, other);
+        }
+    };
+    let port = std_net_tcp::port_from_value(multivalue.pop_front(), function_name)?;
+    let addresses = std_net_tcp::resolve_addresses(&host, port, "UdpSocket:send_to")?;
+
+    with_socket(socket, "UdpSocket:send_to", |socket| {
+        // send to the first resolved address in the same family as our socket, so "localhost" works
+        // whether we bound to 127.0.0.1 or ::1
+        let is_ipv4 = socket.local_addr().map(|address| address.is_ipv4()).unwrap_or(true);
+        let address = addresses.iter()
+            .find(|address| address.is_ipv4() == is_ipv4)
+            .unwrap_or(&addresses[0]);
+        match socket.send_to(&data, address) {
+            Ok(sent) => Ok(LuaValue::Integer(sent as i32)),
+            Err(err) => std_net_tcp::io_err("UdpSocket:send_to", err),
+        }
+    })
+}
+
+fn receive_from(socket: &SharedSocket, function_name: &str) -> LuaResult<Option<(Vec<u8>, SocketAddr)>> {
+    with_socket(socket, function_name, |socket| {
+        let mut data = vec![0; MAX_DATAGRAM_SIZE];
+        match socket.recv_from(&mut data) {
+            Ok((received, address)) => {
+                data.truncate(received);
+                Ok(Some((data, address)))
+            },
+            Err(err) if matches!(err.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => Ok(None),
+            Err(err) => std_net_tcp::io_err(function_name, err),
+        }
+    })
+}
+
+fn socket_recv_from(luau: &Lua, socket: &SharedSocket, _multivalue: LuaMultiValue) -> LuaResult<LuaMultiValue> {
+    match receive_from(socket, "UdpSocket:recv_from")? {
+        Some((data, address)) => {
+            (luau.create_string(data)?, address.to_string()).into_lua_multi(luau)
+        },
+        None => LuaNil.into_lua_multi(luau),
+    }
+}
+
+fn socket_recv_buffer_from(luau: &Lua, socket: &SharedSocket, _multivalue: LuaMultiValue) -> LuaResult<LuaMultiValue> {
+    match receive_from(socket, "UdpSocket:recv_buffer_from")? {
+        Some((data, address)) => {
+            (luau.create_buffer(data)?, address.to_string()).into_lua_multi(luau)
+        },
+        None => LuaNil.into_lua_multi(luau),
+    }
+}
+
+fn socket_set_timeout(socket: &SharedSocket, mut multivalue: LuaMultiValue) -> LuaValueResult {
+    let _s = multivalue.pop_front();
+    let timeout = std_net_tcp::timeout_from_value(multivalue.pop_front(), "UdpSocket:set_timeout(timeout: number?)")?;
+    with_socket(socket, "UdpSocket:set_timeout", |socket| {
+        match socket.set_read_timeout(timeout).and_then(|_| socket.set_write_timeout(timeout)) {
+            Ok(()) => Ok(LuaNil),
+            Err(err) => std_net_tcp::io_err("UdpSocket:set_timeout", err),
+        }
+    })
+}
+
+fn udp_bind(luau: &Lua, address: LuaValue) -> LuaValueResult {
+    let address = match address {
+        LuaValue::String(address) => address.to_string_lossy(),
+        other => {
+            return wrap_err!("udp.bind(address: string) expected address like \"127.0.0.1:5353\" (port 0 picks a free port), got: {:#?}", other);
+        }
+    };
+    let socket = match UdpSocket::bind(&address) {
+        Ok(socket) => socket,
+        Err(err) => {
+            return wrap_err!("udp.bind: unable to bind to {}: {}", address, err);
+        }
+    };
+    let local_address = socket.local_addr().map(|address| address.to_string()).unwrap_or_default();
+    let socket: SharedSocket = Rc::new(RefCell::new(Some(socket)));
+    Ok(LuaValue::Table(TableBuilder::create(luau)?
+        .with_value("address", local_address)?
+        .with_function("send_to", {
+            let socket = Rc::clone(&socket);
+            move |_luau: &Lua, multivalue: LuaMultiValue| socket_send_to(&socket, multivalue)
+        })?
+        .with_function("recv_from", {
+            let socket = Rc::clone(&socket);
+            move |luau: &Lua, multivalue: LuaMultiValue| socket_recv_from(luau, &socket, multivalue)
+        })?
+        .with_function("recv_buffer_from", {
+            let socket = Rc::clone(&socket);
+            move |luau: &Lua, multivalue: LuaMultiValue| socket_recv_buffer_from(luau, &socket, multivalue)
+        })?
+        .with_function("set_timeout", {
+            let socket = Rc::clone(&socket);
+            move |_luau: &Lua, multivalue: LuaMultiValue| socket_set_timeout(&socket, multivalue)
+        })?
+        .with_function("close", {
+            let socket = Rc::clone(&socket);
+            move |_luau: &Lua, _multivalue: LuaMultiValue| -> LuaValueResult {
+                socket.borrow_mut().take();
+                Ok(LuaNil)
+            }
+        })?
+        .build_readonly()?
+    ))
+}
+
+pub fn create(luau: &Lua) -> LuaResult<LuaTable> {
+    TableBuilder::create(luau)?
+        .with_function("bind", udp_bind)?
+        .build_readonly()
+}
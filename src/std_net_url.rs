@@ -0,0 +1,249 @@
+use mlua::prelude::*;
+use percent_encoding::{percent_decode_str, percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use url::{form_urlencoded, Url};
+
+use crate::{table_helpers::TableBuilder, LuaValueResult, colors};
+
+/// everything except the RFC 3986 unreserved characters gets percent-encoded
+const COMPONENT_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// parses a query string into a table of `{ [key]: { values } }`, since keys can repeat
+pub fn parse_query(luau: &Lua, query: &str) -> LuaResult<LuaTable> {
+    let query_table = luau.create_table()?;
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        let values = match query_table.raw_get::<Option<LuaTable>>(key.as_ref())? {
+            Some(values) => values,
+            None => {
+                let values = luau.create_table()?;
+                query_table.raw_set(key.as_ref(), values.clone())?;
+                values
+            }
+        };
+        values.raw_push(value.as_ref())?;
+    }
+    Ok(query_table)
+}
+
+/// like `parse_query`, but only keeps the first value of each key; used for `request.query` in the http server
+/// (`request.query_all` has every value)
+pub fn parse_query_first(luau: &Lua, query: &str) -> LuaResult<LuaTable> {
+    let query_table = luau.create_table()?;
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        if query_table.raw_get::<LuaValue>(key.as_ref())?.is_nil() {
+            query_table.raw_set(key.as_ref(), value.as_ref())?;
+        }
+    }
+    Ok(query_table)
+}
+
+/// percent-decodes a url component into a (lossy) utf-8 string; used by the http server for path segments
+pub fn decode_component(component: &str) -> String {
+    percent_decode_str(component).decode_utf8_lossy().to_string()
+}
+
+/// flattens a table of query params where each value is a string (or number) or an array of them;
+/// used for `url.build` and the `params` field of http requests
+pub fn query_pairs(params: LuaTable, function_name: &str) -> LuaResult<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+    for pair in params.pairs::<LuaString, LuaValue>() {
+        let (key, value) = pair?;
+        let key = key.to_string_lossy();
+        match value {
+            LuaValue::String(value) => pairs.push((key, value.to_string_lossy())),
+            LuaValue::Integer(_) | LuaValue::Number(_) | LuaValue::Boolean(_) => {
+                pairs.push((key, value.to_string()?))
+            },
+            LuaValue::Table(values) => {
+                for value in values.sequence_values::<LuaValue>() {
+                    match value? {
+                        LuaValue::String(value) => pairs.push((key.to_owned(), value.to_string_lossy())),
+                        LuaValue::Integer(n) => pairs.push((key.to_owned(), n.to_string())),
+                        LuaValue::Number(n) => pairs.push((key.to_owned(), n.to_string())),
+                        other => {
+                            return wrap_err!("{}: query param '{}' expected an array of strings, got an array containing: {:?}", function_name, key, other);
+                        }
+                    }
+                }
+            },
+            other => {
+                return wrap_err!("{}: query param '{}' expected to be a string or array of strings, got: {:?}", function_name, key, other);
+            }
+        }
+    }
+    Ok(pairs)
+}
+
+fn url_to_table(luau: &Lua, url: &Url) -> LuaResult<LuaTable> {
+    let user = if url.username().is_empty() { None } else { Some(url.username()) };
+    TableBuilder::create(luau)?
+        .with_value("href", url.as_str())?
+        .with_value("scheme", url.scheme())?
+        .with_value("user", user)?
+        .with_value("password", url.password())?
+        .with_value("host", url.host_str())?
+        .with_value("port", url.port_or_known_default())?
+        .with_value("path", url.path())?
+        .with_value("query", parse_query(luau, url.query().unwrap_or(""))?)?
+        .with_value("query_string", url.query())?
+        .with_value("fragment", url.fragment())?
+        .build()
+}
+
+fn url_parse(luau: &Lua, url: LuaValue) -> LuaValueResult {
+    let url = match url {
+        LuaValue::String(url) => url.to_string_lossy(),
+        other => {
+            return wrap_err!("url.parse(url: string) expected url to be a string, got: {:?}", other);
+        }
+    };
+    match Url::parse(&url) {
+        Ok(parsed) => Ok(LuaValue::Table(url_to_table(luau, &parsed)?)),
+        Err(err) => {
+            wrap_err!("url.parse: unable to parse '{}': {}", url, err)
+        }
+    }
+}
+
+fn url_build(luau: &Lua, parts: LuaValue) -> LuaValueResult {
+    let parts = match parts {
+        LuaValue::Table(parts) => parts,
+        other => {
+            return wrap_err!("url.build(parts: UrlParts) expected parts to be a table, got: {:?}", other);
+        }
+    };
+
+    let scheme: String = match parts.raw_get("scheme")? {
+        LuaValue::String(scheme) => scheme.to_string_lossy(),
+        LuaValue::Nil => String::from("https"),
+        other => {
+            return wrap_err!("url.build: UrlParts.scheme expected to be a string or nil, got: {:?}", other);
+        }
+    };
+    let host: String = match parts.raw_get("host")? {
+        LuaValue::String(host) => host.to_string_lossy(),
+        other => {
+            return wrap_err!("url.build: UrlParts.host expected to be a string, got: {:?}", other);
+        }
+    };
+
+    let mut url = match Url::parse(&format!("{}://{}", scheme, host)) {
+        Ok(url) => url,
+        Err(err) => {
+            return wrap_err!("url.build: invalid scheme or host ({}://{}): {}", scheme, host, err);
+        }
+    };
+
+    if let Some(user) = parts.raw_get::<Option<String>>("user")? {
+        if url.set_username(&user).is_err() {
+            return wrap_err!("url.build: cannot set user on a {} url", scheme);
+        }
+    }
+    if let Some(password) = parts.raw_get::<Option<String>>("password")? {
+        if url.set_password(Some(&password)).is_err() {
+            return wrap_err!("url.build: cannot set password on a {} url", scheme);
+        }
+    }
+    match parts.raw_get("port")? {
+        LuaValue::Integer(port) if (0..=65535).contains(&port) => {
+            if url.set_port(Some(port as u16)).is_err() {
+                return wrap_err!("url.build: cannot set port on a {} url", scheme);
+            }
+        },
+        LuaValue::Nil => {},
+        other => {
+            return wrap_err!("url.build: UrlParts.port expected to be an integer between 0 and 65535, got: {:?}", other);
+        }
+    }
+    if let Some(path) = parts.raw_get::<Option<String>>("path")? {
+        url.set_path(&path);
+    }
+    match parts.raw_get("query")? {
+        LuaValue::Table(query) => {
+            let pairs = query_pairs(query, "url.build")?;
+            if !pairs.is_empty() {
+                url.query_pairs_mut().extend_pairs(pairs);
+            }
+        },
+        LuaValue::String(query) => {
+            url.set_query(Some(&query.to_string_lossy()));
+        },
+        LuaValue::Nil => {},
+        other => {
+            return wrap_err!("url.build: UrlParts.query expected to be a table or string, got: {:?}", other);
+        }
+    }
+    if let Some(fragment) = parts.raw_get::<Option<String>>("fragment")? {
+        url.set_fragment(Some(&fragment));
+    }
+
+    Ok(LuaValue::String(luau.create_string(url.as_str())?))
+}
+
+fn url_encode(luau: &Lua, component: LuaValue) -> LuaValueResult {
+    let component = match component {
+        LuaValue::String(component) => component,
+        other => {
+            return wrap_err!("url.encode(component: string) expected component to be a string, got: {:?}", other);
+        }
+    };
+    let encoded: String = percent_encode(&component.as_bytes(), COMPONENT_ENCODE_SET).collect();
+    Ok(LuaValue::String(luau.create_string(encoded)?))
+}
+
+fn url_decode(luau: &Lua, component: LuaValue) -> LuaValueResult {
+    let component = match component {
+        LuaValue::String(component) => component.to_string_lossy(),
+        other => {
+            return wrap_err!("url.decode(component: string) expected component to be a string, got: {:?}", other);
+        }
+    };
+    let decoded: Vec<u8> = percent_decode_str(&component).collect();
+    Ok(LuaValue::String(luau.create_string(decoded)?))
+}
+
+fn url_join(luau: &Lua, mut multivalue: LuaMultiValue) -> LuaValueResult {
+    let base = match multivalue.pop_front() {
+        Some(LuaValue::String(base)) => base.to_string_lossy(),
+        Some(other) => {
+            return wrap_err!("url.join(base: string, relative: string) expected base to be a string, got: {:?}", other);
+        },
+        None => {
+            return wrap_err!("url.join(base: string, relative: string) called without arguments");
+        }
+    };
+    let relative = match multivalue.pop_front() {
+        Some(LuaValue::String(relative)) => relative.to_string_lossy(),
+        Some(other) => {
+            return wrap_err!("url.join(base: string, relative: string) expected relative to be a string, got: {:?}", other);
+        },
+        None => {
+            return wrap_err!("url.join(base: string, relative: string) missing relative");
+        }
+    };
+    let base_url = match Url::parse(&base) {
+        Ok(url) => url,
+        Err(err) => {
+            return wrap_err!("url.join: unable to parse base url '{}': {}", base, err);
+        }
+    };
+    match base_url.join(&relative) {
+        Ok(joined) => Ok(LuaValue::String(luau.create_string(joined.as_str())?)),
+        Err(err) => {
+            wrap_err!("url.join: unable to join '{}' onto '{}': {}", relative, base, err)
+        }
+    }
+}
+
+pub fn create(luau: &Lua) -> LuaResult<LuaTable> {
+    TableBuilder::create(luau)?
+        .with_function("parse", url_parse)?
+        .with_function("build", url_build)?
+        .with_function("encode", url_encode)?
+        .with_function("decode", url_decode)?
+        .with_function("join", url_join)?
+        .build_readonly()
+}
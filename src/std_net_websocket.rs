@@ -0,0 +1,262 @@
+use crate::{colors, table_helpers::TableBuilder, LuaValueResult};
+use mlua::prelude::*;
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::rc::Rc;
+use std::time::Duration;
+use tungstenite::client::IntoClientRequest;
+use tungstenite::http::{HeaderName, HeaderValue};
+use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::protocol::CloseFrame;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket};
+
+/// how long close() waits for the other side to acknowledge before giving up on it
+const CLOSE_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// streams a websocket can run over; we need to reach the underlying TcpStream for receive timeouts,
+/// and to hang up once the socket's closed since Luau may hold on to the socket table for a while
+pub trait SocketStream: Read + Write {
+    fn tcp_stream(&self) -> Option<&TcpStream>;
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self.tcp_stream() {
+            Some(stream) => stream.set_read_timeout(timeout),
+            None => Ok(()),
+        }
+    }
+
+    fn shutdown(&self) {
+        if let Some(stream) = self.tcp_stream() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl SocketStream for TcpStream {
+    fn tcp_stream(&self) -> Option<&TcpStream> {
+        Some(self)
+    }
+}
+
+impl SocketStream for rustls::StreamOwned<rustls::ServerConnection, TcpStream> {
+    fn tcp_stream(&self) -> Option<&TcpStream> {
+        Some(&self.sock)
+    }
+}
+
+impl SocketStream for MaybeTlsStream<TcpStream> {
+    fn tcp_stream(&self) -> Option<&TcpStream> {
+        match self {
+            MaybeTlsStream::Plain(stream) => Some(stream),
+            MaybeTlsStream::Rustls(stream) => Some(&stream.sock),
+            _ => None,
+        }
+    }
+}
+
+pub type SharedSocket<S> = Rc<RefCell<WebSocket<S>>>;
+
+fn is_timeout(err: &tungstenite::Error) -> bool {
+    matches!(err, tungstenite::Error::Io(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut))
+}
+
+/// sends a close frame (if we haven't already), waits briefly for the other side's, then hangs up
+pub fn close_socket<S: SocketStream>(socket: &mut WebSocket<S>, frame: Option<CloseFrame<'static>>) -> LuaResult<()> {
+    let result = match socket.close(frame) {
+        Ok(()) => {
+            let _ = socket.get_ref().set_read_timeout(Some(CLOSE_HANDSHAKE_TIMEOUT));
+            loop {
+                match socket.read() {
+                    Ok(_message) => continue,
+                    Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => break Ok(()),
+                    Err(err) if is_timeout(&err) => break Ok(()),
+                    Err(err) => break wrap_err!("WebSocket:close: {}", err),
+                }
+            }
+        },
+        Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => Ok(()),
+        Err(err) => wrap_err!("WebSocket:close: {}", err),
+    };
+    socket.get_ref().shutdown();
+    result
+}
+
+fn socket_send<S: SocketStream>(socket: &SharedSocket<S>, mut multivalue: LuaMultiValue) -> LuaValueResult {
+    let _s = multivalue.pop_front();
+    let message = match multivalue.pop_front() {
+        Some(LuaValue::String(text)) => match text.to_str() {
+            Ok(text) => Message::Text(text.to_string()),
+            // websocket text frames have to be valid UTF-8, so send anything else as binary
+            Err(_) => Message::Binary(text.as_bytes().to_vec()),
+        },
+        Some(LuaValue::Buffer(buffy)) => Message::Binary(buffy.to_vec()),
+        Some(other) => {
+            return wrap_err!("WebSocket:send(message: string | buffer) expected message to be a string or buffer, got: {:#?}", other);
+        },
+        None => {
+            return wrap_err!("WebSocket:send(message: string | buffer) expected message, got nothing");
+        }
+    };
+    match socket.borrow_mut().send(message) {
+        Ok(()) => Ok(LuaNil),
+        Err(err) => wrap_err!("WebSocket:send: {}", err),
+    }
+}
+
+fn socket_receive<S: SocketStream>(luau: &Lua, socket: &SharedSocket<S>, mut multivalue: LuaMultiValue) -> LuaValueResult {
+    let _s = multivalue.pop_front();
+    let timeout = match multivalue.pop_front() {
+        None | Some(LuaNil) => None,
+        Some(LuaValue::Integer(seconds)) if seconds > 0 => Some(Duration::from_secs(seconds as u64)),
+        Some(LuaValue::Number(seconds)) if seconds > 0.0 => Some(Duration::from_secs_f64(seconds)),
+        Some(other) => {
+            return wrap_err!("WebSocket:receive(timeout: number?) expected timeout to be a positive number of seconds, got: {:#?}", other);
+        }
+    };
+
+    let mut socket = socket.borrow_mut();
+    if let Err(err) = socket.get_ref().set_read_timeout(timeout) {
+        return wrap_err!("WebSocket:receive: unable to set timeout: {}", err);
+    }
+    loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => return Ok(LuaValue::String(luau.create_string(text)?)),
+            Ok(Message::Binary(bytes)) => return Ok(LuaValue::Buffer(luau.create_buffer(bytes)?)),
+            // pings get answered for us on the next read or write
+            Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_)) => continue,
+            // keep reading so tungstenite can finish the close handshake
+            Ok(Message::Close(_)) => continue,
+            Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => {
+                socket.get_ref().shutdown();
+                return Ok(LuaNil);
+            },
+            Err(err) if is_timeout(&err) => return Ok(LuaNil),
+            Err(err) => {
+                return wrap_err!("WebSocket:receive: {}", err);
+            }
+        }
+    }
+}
+
+fn socket_ping<S: SocketStream>(socket: &SharedSocket<S>, mut multivalue: LuaMultiValue) -> LuaValueResult {
+    let _s = multivalue.pop_front();
+    let payload = match multivalue.pop_front() {
+        None | Some(LuaNil) => Vec::new(),
+        Some(LuaValue::String(payload)) => payload.as_bytes().to_vec(),
+        Some(other) => {
+            return wrap_err!("WebSocket:ping(payload: string?) expected payload to be a string or nil, got: {:#?}", other);
+        }
+    };
+    if payload.len() > 125 {
+        return wrap_err!("WebSocket:ping: payload can be at most 125 bytes, got {}", payload.len());
+    }
+    match socket.borrow_mut().send(Message::Ping(payload)) {
+        Ok(()) => Ok(LuaNil),
+        Err(err) => wrap_err!("WebSocket:ping: {}", err),
+    }
+}
+
+fn socket_close<S: SocketStream>(socket: &SharedSocket<S>, mut multivalue: LuaMultiValue) -> LuaValueResult {
+    let _s = multivalue.pop_front();
+    let code = match multivalue.pop_front() {
+        None | Some(LuaNil) => 1000,
+        Some(LuaValue::Integer(code)) if (1000..5000).contains(&code) => code as u16,
+        Some(other) => {
+            return wrap_err!("WebSocket:close(code: number?, reason: string?) expected code to be a close code between 1000 and 4999, got: {:#?}", other);
+        }
+    };
+    let reason = match multivalue.pop_front() {
+        None | Some(LuaNil) => String::new(),
+        Some(LuaValue::String(reason)) => reason.to_string_lossy(),
+        Some(other) => {
+            return wrap_err!("WebSocket:close(code: number?, reason: string?) expected reason to be a string, got: {:#?}", other);
+        }
+    };
+    let frame = CloseFrame { code: CloseCode::from(code), reason: reason.into() };
+    close_socket(&mut socket.borrow_mut(), Some(frame))?;
+    Ok(LuaNil)
+}
+
+/// wraps a connected websocket (client or server side) in the table Luau scripts use to talk over it
+pub fn create_socket<S: SocketStream + 'static>(luau: &Lua, socket: WebSocket<S>) -> LuaResult<(LuaTable, SharedSocket<S>)> {
+    let socket = Rc::new(RefCell::new(socket));
+    let socket_table = TableBuilder::create(luau)?
+        .with_function("send", {
+            let socket = Rc::clone(&socket);
+            move |_luau: &Lua, multivalue: LuaMultiValue| socket_send(&socket, multivalue)
+        })?
+        .with_function("receive", {
+            let socket = Rc::clone(&socket);
+            move |luau: &Lua, multivalue: LuaMultiValue| socket_receive(luau, &socket, multivalue)
+        })?
+        .with_function("ping", {
+            let socket = Rc::clone(&socket);
+            move |_luau: &Lua, multivalue: LuaMultiValue| socket_ping(&socket, multivalue)
+        })?
+        .with_function("close", {
+            let socket = Rc::clone(&socket);
+            move |_luau: &Lua, multivalue: LuaMultiValue| socket_close(&socket, multivalue)
+        })?
+        .with_function("is_open", {
+            let socket = Rc::clone(&socket);
+            move |_luau: &Lua, _multivalue: LuaMultiValue| -> LuaResult<bool> {
+                Ok(socket.borrow().can_write())
+            }
+        })?
+        .build_readonly()?;
+    Ok((socket_table, socket))
+}
+
+fn websocket_connect(luau: &Lua, mut multivalue: LuaMultiValue) -> LuaValueResult {
+    let url = match multivalue.pop_front() {
+        Some(LuaValue::String(url)) => url.to_string_lossy(),
+        Some(other) => {
+            return wrap_err!("websocket.connect(url: string, options: WebSocketOptions?) expected url to be a string, got: {:#?}", other);
+        },
+        None => {
+            return wrap_err!("websocket.connect(url: string, options: WebSocketOptions?) expected url, got nothing");
+        }
+    };
+
+    let mut request = match url.as_str().into_client_request() {
+        Ok(request) => request,
+        Err(err) => {
+            return wrap_err!("websocket.connect: invalid url '{}': {}", url, err);
+        }
+    };
+
+    match multivalue.pop_front() {
+        Some(LuaValue::Table(options)) => {
+            if let LuaValue::Table(headers) = options.raw_get("headers")? {
+                for pair in headers.pairs::<String, String>() {
+                    let (key, value) = pair?;
+                    let (Ok(name), Ok(value)) = (HeaderName::from_bytes(key.as_bytes()), HeaderValue::from_str(&value)) else {
+                        return wrap_err!("websocket.connect: invalid header '{}: {}'", key, value);
+                    };
+                    request.headers_mut().insert(name, value);
+                }
+            }
+        },
+        None | Some(LuaNil) => {},
+        Some(other) => {
+            return wrap_err!("websocket.connect expected WebSocketOptions table or nil, got: {:#?}", other);
+        }
+    }
+
+    let socket = match tungstenite::connect(request) {
+        Ok((socket, _response)) => socket,
+        Err(err) => {
+            return wrap_err!("websocket.connect: unable to connect to '{}': {}", url, err);
+        }
+    };
+    let (socket_table, _socket) = create_socket(luau, socket)?;
+    Ok(LuaValue::Table(socket_table))
+}
+
+pub fn create(luau: &Lua) -> LuaResult<LuaTable> {
+    TableBuilder::create(luau)?
+        .with_function("connect", websocket_connect)?
+        .build_readonly()
+}
@@ -1,15 +1,105 @@
-use std::io::{BufRead, BufReader, Read, Write};
-use std::process::{self, Command, Stdio};
-// use std::thread;
-use std::sync::{Arc, Mutex};
+use std::io::{self, Write};
+use std::process::{self, Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
+use std::{env, fs, thread};
 
 use mlua::prelude::*;
-use crate::{std_env, colors, table_helpers::TableBuilder, wrap_err, LuaValueResult};
+use crate::{std_env, std_process_child, std_process_info, std_process_pipeline, std_process_pty, std_process_shellwords, std_process_signals, std_process_supervise, colors, table_helpers::TableBuilder, wrap_err, LuaValueResult};
+use crate::std_process_child::{exit_signal, OutputPipe, SpawnedChild, WAIT_POLL_INTERVAL};
 
-struct RunOptions {
-    program: String,
-    args: Vec<String>,
-    shell: Option<String>,
+/// how long we keep reading a timed out process' output after killing it
+const TIMED_OUT_OUTPUT_GRACE: Duration = Duration::from_millis(100);
+
+/// whether captured output comes back as strings or buffers
+#[derive(Clone, Copy)]
+pub enum CaptureAs {
+    String,
+    Buffer,
+}
+
+impl CaptureAs {
+    pub fn from_value(value: LuaValue, field: &str) -> LuaResult<Self> {
+        match value {
+            LuaValue::Nil => Ok(CaptureAs::String),
+            LuaValue::String(capture) => match capture.to_string_lossy().as_str() {
+                "string" => Ok(CaptureAs::String),
+                "buffer" => Ok(CaptureAs::Buffer),
+                other => wrap_err!("{}.capture expected \"string\" or \"buffer\", got: {:?}", field, other),
+            },
+            other => {
+                wrap_err!("{}.capture expected \"string\" or \"buffer\", got: {:#?}", field, other)
+            }
+        }
+    }
+
+    /// output's passed through byte for byte either way; Luau strings don't have to be valid utf-8
+    pub fn to_value(self, luau: &Lua, output: Vec<u8>) -> LuaValueResult {
+        match self {
+            CaptureAs::String => Ok(LuaValue::String(luau.create_string(output)?)),
+            CaptureAs::Buffer => Ok(LuaValue::Buffer(luau.create_buffer(output)?)),
+        }
+    }
+}
+
+/// where a child process' stdout or stderr goes
+pub enum OutputMode {
+    /// captured, so `run` can return it and `spawn` can read it
+    Pipe,
+    Inherit,
+    Null,
+    /// written to a file, replacing its contents
+    File(String),
+}
+
+impl OutputMode {
+    fn from_value(value: LuaValue, field: &str) -> LuaResult<Self> {
+        match value {
+            LuaValue::Nil => Ok(OutputMode::Pipe),
+            LuaValue::String(mode) => {
+                let mode = mode.to_string_lossy();
+                match mode.as_str() {
+                    "pipe" => Ok(OutputMode::Pipe),
+                    "inherit" => Ok(OutputMode::Inherit),
+                    "null" => Ok(OutputMode::Null),
+                    "" => wrap_err!("RunOptions.{} expected \"pipe\", \"inherit\", \"null\", or a file path, got an empty string", field),
+                    _ => Ok(OutputMode::File(mode)),
+                }
+            },
+            other => {
+                wrap_err!("RunOptions.{} expected \"pipe\", \"inherit\", \"null\", or a file path, got: {:#?}", field, other)
+            }
+        }
+    }
+
+    pub fn stdio(&self, field: &str) -> LuaResult<Stdio> {
+        match self {
+            OutputMode::Pipe => Ok(Stdio::piped()),
+            OutputMode::Inherit => Ok(Stdio::inherit()),
+            OutputMode::Null => Ok(Stdio::null()),
+            OutputMode::File(path) => match fs::File::create(path) {
+                Ok(file) => Ok(Stdio::from(file)),
+                Err(err) => wrap_err!("RunOptions.{}: unable to open file '{}' for writing: {}", field, path, err),
+            },
+        }
+    }
+}
+
+pub struct RunOptions {
+    pub program: String,
+    pub args: Vec<String>,
+    pub shell: Option<String>,
+    pub cwd: Option<String>,
+    /// added to (or replacing, with `clear_env`) the environment the child inherits from us
+    pub env: Vec<(String, String)>,
+    pub clear_env: bool,
+    pub stdin: Option<Vec<u8>>,
+    pub timeout: Option<Duration>,
+    pub stdout: OutputMode,
+    pub stderr: OutputMode,
+    pub capture: CaptureAs,
+    /// rows and columns of the pseudo-terminal to run the process in, instead of pipes
+    pub pty: Option<(u16, u16)>,
 }
 
 impl RunOptions {
@@ -18,12 +108,21 @@ impl RunOptions {
         RunOptions {
             program,
             args,
-            shell
+            shell,
+            cwd: None,
+            env: Vec::new(),
+            clear_env: false,
+            stdin: None,
+            timeout: None,
+            stdout: OutputMode::Pipe,
+            stderr: OutputMode::Pipe,
+            capture: CaptureAs::String,
+            pty: None,
         }
     }
 
-    fn from_table(luau: &Lua, run_options: LuaTable) -> LuaResult<Self> {
-        let program = match run_options.raw_get("program").unwrap() {
+    pub fn from_table(luau: &Lua, run_options: LuaTable) -> LuaResult<Self> {
+        let program = match run_options.raw_get("program")? {
             LuaValue::String(program) => {
                 program.to_string_lossy()
             },
@@ -35,14 +134,10 @@ impl RunOptions {
             }
         };
 
-        let args = match run_options.raw_get("args").unwrap() {
+        let args = match run_options.raw_get("args")? {
             LuaValue::Table(args) => {
-                let mut rust_vec: Vec<String> = Vec::from_lua(LuaValue::Table(args), luau)?;
-                // let's trim the whitespace just to make sure we pass valid args (untrimmed args might explode)
-                for s in rust_vec.iter_mut() {
-                    *s = s.trim().to_string();
-                };
-                rust_vec
+                // passed through untouched; a filename can legitimately start or end with a space
+                Vec::from_lua(LuaValue::Table(args), luau)?
             },
             LuaValue::Nil => {
                 Vec::new()
@@ -52,7 +147,7 @@ impl RunOptions {
             }
         };
 
-        let shell = match run_options.raw_get("shell").unwrap() {
+        let shell = match run_options.raw_get("shell")? {
             LuaValue::String(shell) => {
                 Some(shell.to_string_lossy())
             },
@@ -64,13 +159,267 @@ impl RunOptions {
             }
         };
 
+        let cwd = match run_options.raw_get("cwd")? {
+            LuaValue::String(cwd) => Some(cwd.to_string_lossy()),
+            LuaValue::Nil => None,
+            other => {
+                return wrap_err!("RunOptions.cwd expected to be a string (path) or nil, got: {:#?}", other);
+            }
+        };
+
+        let env = match run_options.raw_get("env")? {
+            LuaValue::Table(env) => {
+                let mut vars = Vec::new();
+                for pair in env.pairs::<LuaValue, LuaValue>() {
+                    match pair? {
+                        (LuaValue::String(key), LuaValue::String(value)) => {
+                            vars.push((key.to_string_lossy(), value.to_string_lossy()));
+                        },
+                        (LuaValue::String(key), LuaValue::Integer(value)) => {
+                            vars.push((key.to_string_lossy(), value.to_string()));
+                        },
+                        (LuaValue::String(key), LuaValue::Number(value)) => {
+                            vars.push((key.to_string_lossy(), value.to_string()));
+                        },
+                        (key, value) => {
+                            return wrap_err!("RunOptions.env expected {{ [string]: string }}, got {:?} = {:?}", key, value);
+                        }
+                    }
+                }
+                vars
+            },
+            LuaValue::Nil => Vec::new(),
+            other => {
+                return wrap_err!("RunOptions.env expected to be a table of environment variables ({{ [string]: string }}) or nil, got: {:#?}", other);
+            }
+        };
+
+        let clear_env = match run_options.raw_get("clear_env")? {
+            LuaValue::Boolean(clear_env) => clear_env,
+            LuaValue::Nil => false,
+            other => {
+                return wrap_err!("RunOptions.clear_env expected to be a boolean or nil, got: {:#?}", other);
+            }
+        };
+
+        let stdin = match run_options.raw_get("stdin")? {
+            LuaValue::String(stdin) => Some(stdin.as_bytes().to_vec()),
+            LuaValue::Buffer(stdin) => Some(stdin.to_vec()),
+            LuaValue::Nil => None,
+            other => {
+                return wrap_err!("RunOptions.stdin expected to be a string or buffer to write to the process' stdin, got: {:#?}", other);
+            }
+        };
+
+        let timeout = match run_options.raw_get("timeout")? {
+            LuaValue::Integer(seconds) if seconds > 0 => Some(Duration::from_secs(seconds as u64)),
+            LuaValue::Number(seconds) if seconds > 0.0 => Some(Duration::from_secs_f64(seconds)),
+            LuaValue::Nil => None,
+            other => {
+                return wrap_err!("RunOptions.timeout expected to be a positive number of seconds or nil, got: {:#?}", other);
+            }
+        };
+
+        let stdout = OutputMode::from_value(run_options.raw_get("stdout")?, "stdout")?;
+        let stderr = OutputMode::from_value(run_options.raw_get("stderr")?, "stderr")?;
+        let capture = CaptureAs::from_value(run_options.raw_get("capture")?, "RunOptions")?;
+
+        let pty = match run_options.raw_get("pty")? {
+            LuaValue::Nil | LuaValue::Boolean(false) => None,
+            LuaValue::Boolean(true) => Some((DEFAULT_PTY_ROWS, DEFAULT_PTY_COLS)),
+            LuaValue::Table(size) => Some((
+                pty_dimension(size.raw_get("rows")?, "rows", DEFAULT_PTY_ROWS)?,
+                pty_dimension(size.raw_get("cols")?, "cols", DEFAULT_PTY_COLS)?,
+            )),
+            other => {
+                return wrap_err!("RunOptions.pty expected to be {{ rows: number?, cols: number? }}, true, or nil, got: {:#?}", other);
+            }
+        };
+
         Ok(RunOptions {
             program,
             args,
-            shell
+            shell,
+            cwd,
+            env,
+            clear_env,
+            stdin,
+            timeout,
+            stdout,
+            stderr,
+            capture,
+            pty,
         })
         
     }
+
+    /// `program` is shell code (so pipes and `&&` work), but args are data, so each gets quoted
+    /// and appended as a single word of the command string
+    fn shell_command(&self, shell: &str) -> String {
+        let mut command = self.program.clone();
+        for arg in &self.args {
+            command.push(' ');
+            if is_powershell(shell) {
+                command.push_str(&std_process_shellwords::quote_powershell(arg));
+            } else {
+                command.push_str(&std_process_shellwords::quote(arg));
+            }
+        }
+        command
+    }
+
+    /// the program to start and its args, which run `program` through `shell` if there is one
+    pub fn argv(&self) -> (String, Vec<String>) {
+        match &self.shell {
+            Some(shell) => {
+                let flag = if is_powershell(shell) { "-Command" } else { "-c" };
+                (shell.clone(), vec![flag.to_string(), self.shell_command(shell)])
+            },
+            None => (self.program.clone(), self.args.clone()),
+        }
+    }
+
+    /// the command to run, in its working directory and environment; stdio's up to the caller
+    pub fn command(&self) -> Command {
+        let (program, args) = self.argv();
+        let mut command = Command::new(program);
+        command.args(args);
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+        if self.clear_env {
+            command.env_clear();
+        }
+        command.envs(self.env.iter().map(|(key, value)| (key, value)));
+        command
+    }
+}
+
+const DEFAULT_PTY_ROWS: u16 = 24;
+const DEFAULT_PTY_COLS: u16 = 80;
+
+pub fn pty_dimension(value: LuaValue, field: &str, default: u16) -> LuaResult<u16> {
+    match value {
+        LuaValue::Nil => Ok(default),
+        LuaValue::Integer(n) if n > 0 && n <= u16::MAX as i32 => Ok(n as u16),
+        LuaValue::Number(n) if n > 0.0 && n <= u16::MAX as f64 && n.trunc() == n => Ok(n as u16),
+        other => {
+            wrap_err!("pty {} expected to be a positive integer, got: {:#?}", field, other)
+        }
+    }
+}
+
+fn is_powershell(shell: &str) -> bool {
+    let name = std::path::Path::new(shell)
+        .file_stem()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    name == "pwsh" || name == "powershell"
+}
+
+/// waits for the child to exit, killing it if it outlives `timeout`; returns whether it timed out
+fn wait_with_timeout(child: &mut Child, timeout: Option<Duration>) -> io::Result<(ExitStatus, bool)> {
+    let Some(timeout) = timeout else {
+        return Ok((child.wait()?, false));
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok((status, false));
+        }
+        if Instant::now() >= deadline {
+            // it might've exited on its own right after we last checked
+            let _ = child.kill();
+            return Ok((child.wait()?, true));
+        }
+        thread::sleep(WAIT_POLL_INTERVAL);
+    }
+}
+
+/// why a program couldn't be started, with the `kind` scripts can check to fall back to something else
+pub struct SpawnError {
+    pub kind: &'static str,
+    pub program: String,
+    err: io::Error,
+}
+
+impl SpawnError {
+    pub fn new(options: &RunOptions, err: io::Error) -> Self {
+        let kind = match err.kind() {
+            io::ErrorKind::NotFound => "NotFound",
+            io::ErrorKind::PermissionDenied => "PermissionDenied",
+            _ => "Other",
+        };
+        // with a shell, the shell's what we actually tried to start
+        let program = options.shell.clone().unwrap_or_else(|| options.program.clone());
+        SpawnError { kind, program, err }
+    }
+}
+
+impl std::fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            "NotFound" => write!(f, "{}: program '{}' not found", self.kind, self.program),
+            "PermissionDenied" => write!(f, "{}: not allowed to run '{}'", self.kind, self.program),
+            _ => write!(f, "{}: unable to start '{}': {}", self.kind, self.program, self.err),
+        }
+    }
+}
+
+/// what `process.spawn` returns instead of a ChildProcess when the program can't be started, so scripts can
+/// check `kind` and fall back like they can with `process.run`
+pub fn spawn_failure(luau: &Lua, error: SpawnError) -> LuaValueResult {
+    let err = error.to_string();
+    Ok(LuaValue::Table(TableBuilder::create(luau)?
+        .with_value("ok", false)?
+        .with_value("err", err.clone())?
+        .with_value("kind", error.kind)?
+        .with_value("program", error.program)?
+        .with_function("unwrap", move |_luau: &Lua, mut multivalue: LuaMultiValue| -> LuaValueResult {
+            let _failure_table = multivalue.pop_front();
+            match multivalue.pop_front() {
+                Some(default) => Ok(default),
+                None => {
+                    wrap_err!("Attempt to ChildProcess:unwrap() a process.spawn that failed without a default value! process.spawn: {}", err)
+                }
+            }
+        })?
+        .build_readonly()?
+    ))
+}
+
+/// the parts of a failed RunResult every failure has; callers add what else they know
+pub fn failure_result<'lua>(luau: &'lua Lua, err: String, stdout: LuaValue, stderr: LuaValue) -> LuaResult<TableBuilder<'lua>> {
+    TableBuilder::create(luau)?
+        .with_value("ok", false)?
+        .with_value("err", err)?
+        .with_value("stdout", stdout)?
+        .with_value("stderr", stderr)?
+        .with_function("unwrap",
+            | _luau: &Lua, mut multivalue: LuaMultiValue | -> LuaValueResult {
+                let _failure_table = multivalue.pop_front();
+                let default_arg = match multivalue.pop_front() {
+                    Some(value) => value,
+                    None => {
+                        return wrap_err!("Attempt to ProcessRunResult:unwrap() an erred process.run without a default value!")
+                    }
+                };
+                Ok(default_arg)
+            }
+        )
+}
+
+/// the parts of a successful RunResult; `unwrap` returns stdout as-is
+pub fn success_result<'lua>(luau: &'lua Lua, stdout: LuaValue, stderr: LuaValue) -> LuaResult<TableBuilder<'lua>> {
+    TableBuilder::create(luau)?
+        .with_value("ok", true)?
+        .with_value("out", stdout.clone())?
+        .with_value("stdout", stdout.clone())?
+        .with_value("stderr", stderr)?
+        .with_value("timed_out", false)?
+        .with_function("unwrap", move | _luau: &Lua, _multivalue: LuaMultiValue | -> LuaValueResult {
+            Ok(stdout.clone())
+        })
 }
 
 fn process_run(luau: &Lua, run_options: LuaValue) -> LuaValueResult {
@@ -85,64 +434,72 @@ fn process_run(luau: &Lua, run_options: LuaValue) -> LuaValueResult {
             return wrap_err!("process.run expected RunOptions table of type {{ program: string, args: {{string}}?, shell: string? }}, got: {:#?}", other);
         }
     };
+    if options.pty.is_some() {
+        return wrap_err!("process.run: RunOptions.pty is only supported by process.spawn");
+    }
 
-    let output = {
-        if let Some(shell) = options.shell {
-            Command::new(shell.clone())
-                .arg(
-                    if shell.as_str() == "pwsh" || shell.as_str() == "powershell" {
-                        "-Command"
-                    } else {
-                        "-c"
-                    }
-                )
-                .arg(options.program)
-                .arg(options.args.join(" "))
-                .output()
-                .expect("process.run failed to execute process")
-        } else {
-            Command::new(options.program)
-                .args(options.args)
-                .output()
-                .expect("process.run failed to execute process")
+    let spawned = options.command()
+        .stdin(if options.stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(options.stdout.stdio("stdout")?)
+        .stderr(options.stderr.stdio("stderr")?)
+        .spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(err) => {
+            // a missing program is an ordinary failure scripts might want to fall back from, not a crash
+            let error = SpawnError::new(&options, err);
+            let nothing = options.capture.to_value(luau, Vec::new())?;
+            return Ok(LuaValue::Table(failure_result(luau, error.to_string(), nothing.clone(), nothing)?
+                .with_value("kind", error.kind)?
+                .with_value("program", error.program)?
+                .with_value("timed_out", false)?
+                .build_readonly()?
+            ));
         }
     };
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let stderr = String::from_utf8_lossy(&output.stderr);
-
-    if output.status.success() {
-        let success_table = TableBuilder::create(luau)?
-            .with_value("ok", true)?
-            .with_value("out", stdout.clone())?
-            .with_value("stdout", stdout.clone())?
-            .with_value("stderr", stderr.clone())?
-            .with_function("unwrap", {
-                let stdout = stdout.clone().to_string();
-                move | luau: &Lua, _multivalue: LuaMultiValue | -> LuaValueResult {
-                    Ok(LuaValue::String(luau.create_string(stdout.clone())?))
-                }
-            })?
-            .build_readonly()?;
-        Ok(LuaValue::Table(success_table))
+    if let (Some(input), Some(mut stdin)) = (options.stdin, child.stdin.take()) {
+        // stdin gets closed once it's all written, so programs reading to EOF can finish
+        thread::spawn(move || {
+            let _ = stdin.write_all(&input);
+        });
+    }
+    // drained on their own threads so a chatty process can't fill one pipe and stall while we wait on the other
+    let stdout = child.stdout.take().map(OutputPipe::start);
+    let stderr = child.stderr.take().map(OutputPipe::start);
+
+    let (status, timed_out) = match wait_with_timeout(&mut child, options.timeout) {
+        Ok(waited) => waited,
+        Err(err) => {
+            return wrap_err!("process.run: unable to wait on '{}': {}", options.program, err);
+        }
+    };
+    // whatever the process left running (like a shell's children) can keep the pipe open after
+    // we've killed it, so don't wait on it for long
+    let grace = if timed_out { Some(TIMED_OUT_OUTPUT_GRACE) } else { None };
+    let stdout = stdout.map(|pipe| pipe.read_to_end(grace)).unwrap_or_default();
+    let stderr = stderr.map(|pipe| pipe.read_to_end(grace)).unwrap_or_default();
+
+    let exit_code = status.code();
+    let signal = exit_signal(&status);
+
+    if status.success() && !timed_out {
+        Ok(LuaValue::Table(success_result(luau, options.capture.to_value(luau, stdout)?, options.capture.to_value(luau, stderr)?)?
+            .with_value("exit_code", exit_code)?
+            .build_readonly()?
+        ))
     } else {
-        let failure_table = TableBuilder::create(luau)?
-            .with_value("ok", false)?
-            .with_value("err", stderr.clone())?
-            .with_value("stdout", stdout.clone())?
-            .with_value("stderr", stderr.clone())?
-            .with_function("unwrap",
-                | _luau: &Lua, mut multivalue: LuaMultiValue | -> LuaValueResult {
-                    let _failure_table = multivalue.pop_front();
-                    let default_arg = match multivalue.pop_front() {
-                        Some(value) => value,
-                        None => {
-                            return wrap_err!("Attempt to ProcessRunResult:unwrap() an erred process.run without a default value!")
-                        }
-                    };
-                    Ok(default_arg)
-                }
-            )?
+        let err = if timed_out {
+            format!("process timed out after {:?} and was killed\n{}", options.timeout.unwrap_or_default(), String::from_utf8_lossy(&stderr))
+        } else {
+            String::from_utf8_lossy(&stderr).to_string()
+        };
+        let stdout = options.capture.to_value(luau, stdout)?;
+        let stderr = options.capture.to_value(luau, stderr)?;
+        let failure_table = failure_result(luau, err, stdout, stderr)?
+            .with_value("exit_code", exit_code)?
+            .with_value("signal", signal)?
+            .with_value("timed_out", timed_out)?
             .build_readonly()?;
         Ok(LuaValue::Table(failure_table))
     }
@@ -160,204 +517,87 @@ fn process_spawn(luau: &Lua, spawn_options: LuaValue) -> LuaValueResult {
             return wrap_err!("process.spawn expected RunOptions table of type {{ program: string, args: {{string}}?, shell: string? }}, got: {:#?}", other);
         }
     };
+    if let Some(size) = options.pty {
+        return std_process_pty::spawn_pty(luau, &options, size);
+    }
 
-    let mut child = {
-        if let Some(shell) = options.shell {
-            Command::new(shell.clone())
-                .arg(
-                    if shell.as_str() == "pwsh" || shell.as_str() == "powershell" {
-                        "-Command"
-                    } else {
-                        "-c"
-                    }
-                )
-                .arg(options.program)
-                .arg(options.args.join(" "))
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
-                .spawn()
-                .expect("process.spawn failed to execute process")
-        } else {
-            Command::new(options.program)
-                .args(options.args)
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
-                .spawn()
-                .expect("process.run failed to execute process")
+    let spawned = options.command()
+        .stdin(Stdio::piped())
+        .stdout(options.stdout.stdio("stdout")?)
+        .stderr(options.stderr.stdio("stderr")?)
+        .spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(err) => {
+            return spawn_failure(luau, SpawnError::new(&options, err));
         }
     };
 
-    let child_id = child.id();
-    let stdout = child.stdout.take().unwrap();
-    let stderr = child.stderr.take().unwrap();
-    let stdin = child.stdin.take().unwrap();
-
-    let arc_child = Arc::new(Mutex::new(child));
-    let arc_stdout = Arc::new(Mutex::new(stdout));
-    let arc_stderr = Arc::new(Mutex::new(stderr));
-    let arc_stdin = Arc::new(Mutex::new(stdin));
-
-    let stdout_handle = TableBuilder::create(luau)?
-        .with_function("read", {
-            let stdout = Arc::clone(&arc_stdout);
-            move | luau: &Lua, mut multivalue: LuaMultiValue | -> LuaValueResult {
-                let buffer_size = match multivalue.pop_back() {
-                    Some(LuaValue::Integer(i)) => i as usize,
-                    Some(LuaValue::Number(n)) => {
-                        if n.trunc() == n {
-                            n as usize
-                        } else {
-                            return wrap_err!("ChildProcess.stdout:read(buffer_size) expected buffer_size to be an integer, got a float: {}", n);
-                        }
-                    },
-                    _ => 32,
-                };
-                let mut stdout = stdout.lock().unwrap();
-                let mut buffy = vec![0; buffer_size];
-                match stdout.read_exact(&mut buffy) {
-                    Ok(_) => {
-                        let result_string = luau.create_string(buffy)?;
-                        Ok(LuaValue::String(result_string))
-                    },
-                    Err(_err) => Ok(LuaValue::Nil)
-                }
-            }
-        })?
-        .with_function("lines", {
-            let stdout = Arc::clone(&arc_stdout);
-            move | luau: &Lua, _multivalue: LuaMultiValue | -> LuaValueResult {
-                Ok(LuaValue::Function(luau.create_function({
-                    let stdout = Arc::clone(&stdout);
-                    move | luau: &Lua, _value: LuaValue | -> LuaValueResult {
-                        let mut stdout = stdout.lock().unwrap();
-                        let mut reader = BufReader::new(stdout.by_ref());
-                        let mut new_line = String::from("");
-                        match reader.read_line(&mut new_line) {
-                            Ok(0) => {
-                                Ok(LuaNil)
-                            },
-                            Ok(_other) => {
-                                Ok(LuaValue::String(luau.create_string(new_line.trim_end())?))
-                            },
-                            Err(err) => {
-                                wrap_err!("unable to read line: {:#?}", err)
-                            }
-                        }
-                    }
-                })?))
-                // let line = reader.read_line(buf)
-            }
-        })?
-        .build_readonly()?;
-
-    let stderr_handle = TableBuilder::create(luau)?
-        .with_function("read", {
-            let stderr = Arc::clone(&arc_stderr);
-            move | luau: &Lua, mut multivalue: LuaMultiValue | -> LuaValueResult {
-                let buffer_size = match multivalue.pop_back() {
-                    Some(LuaValue::Integer(i)) => i as usize,
-                    Some(LuaValue::Number(n)) => {
-                        if n.trunc() == n {
-                            n as usize
-                        } else {
-                            return wrap_err!("ChildProcess.stderr:read(buffer_size) expected buffer_size to be an integer, got a float: {}", n);
-                        }
-                    },
-                    _ => 32,
-                };
-                let mut stderr = stderr.lock().unwrap();
-                let mut buffy = vec![0; buffer_size];
-                match stderr.read_exact(&mut buffy) {
-                    Ok(_) => {
-                        let result_string = luau.create_string(buffy)?;
-                        Ok(LuaValue::String(result_string))
-                    },
-                    Err(_err) => Ok(LuaValue::Nil)
-                }
-            }
-        })?
-        .with_function("lines", {
-            let stderr = Arc::clone(&arc_stderr);
-            move | luau: &Lua, _multivalue: LuaMultiValue | -> LuaValueResult {
-                Ok(LuaValue::Function(luau.create_function({
-                    let stderr = Arc::clone(&stderr);
-                    move | luau: &Lua, _value: LuaValue | -> LuaValueResult {
-                        let mut stderr = stderr.lock().unwrap();
-                        let mut reader = BufReader::new(stderr.by_ref());
-                        let mut new_line = String::from("");
-                        match reader.read_line(&mut new_line) {
-                            Ok(0) => {
-                                Ok(LuaNil)
-                            },
-                            Ok(_other) => {
-                                Ok(LuaValue::String(luau.create_string(new_line.trim_end())?))
-                            },
-                            Err(err) => {
-                                wrap_err!("unable to read line: {:#?}", err)
-                            }
-                        }
-                    }
-                })?))
-                // let line = reader.read_line(buf)
-            }
-        })?
-        .build_readonly()?;
-
-    let stdin_handle = TableBuilder::create(luau)?
-        .with_function("write", {
-            let stdin = Arc::clone(&arc_stdin);
-            move | _luau: &Lua, mut multivalue: LuaMultiValue | -> LuaValueResult {
-                let _handle = multivalue.pop_front();
-                let stuff_to_write = match multivalue.pop_back() {
-                    Some(LuaValue::String(stuff)) => stuff.to_string_lossy(),
-                    Some(other) => {
-                        return wrap_err!("ChildProcess.stdin:write(data) expected data to be a string, got: {:?}", other);
-                    },
-                    None => {
-                        return wrap_err!("ChildProcess.stdin:write(data) was called without argument data");
-                    }
-                };
-                let mut stdin = stdin.lock().unwrap();
-                match stdin.write_all(stuff_to_write.as_bytes()) {
-                    Ok(_) => Ok(LuaNil),
-                    Err(err) => wrap_err!("ChildProcess.stdin:write: error writing to stdin: {:?}", err)
-                }
-            }
-        })?
-        .build_readonly()?;
-    
-    let child_handle = TableBuilder::create(luau)?
-        .with_value("id", child_id)?
-        .with_function("alive",{
-            let child = Arc::clone(&arc_child);
-            move | _luau: &Lua, _multivalue: LuaMultiValue | -> LuaValueResult {
-                let mut child = child.lock().unwrap();
-                match child.try_wait().unwrap() {
-                    Some(_status_code) => Ok(LuaValue::Boolean(false)),
-                    None => Ok(LuaValue::Boolean(true)),
-                }
-            }
-        })?
-        .with_function("kill",{
-            let child = Arc::clone(&arc_child);
-            move | _luau: &Lua, _multivalue: LuaMultiValue | -> LuaValueResult {
-                let mut child = child.lock().unwrap();
-                match child.kill() {
-                    Ok(_) => Ok(LuaValue::Nil),
-                    Err(err) => {
-                        wrap_err!("ChildProcess could not be killed: {:?}", err)
-                    }
-                }
-            }
-        })?
-        .with_value("stdout", LuaValue::Table(stdout_handle))?
-        .with_value("stderr", stderr_handle)?
-        .with_value("stdin", stdin_handle)?
-        .build_readonly()?;
+    let stdin = child.stdin.take();
+    // stdin given up front gets written and closed, rather than left open for ChildProcess.stdin:write
+    let stdin = match (options.stdin, stdin) {
+        (Some(input), Some(mut stdin)) => {
+            thread::spawn(move || {
+                let _ = stdin.write_all(&input);
+            });
+            None
+        },
+        (None, stdin) => stdin,
+        (Some(_), None) => None,
+    };
+    let stdout = child.stdout.take().map(OutputPipe::start_limited);
+    let stderr = child.stderr.take().map(OutputPipe::start_limited);
+
+    let spawned = SpawnedChild::new(child);
+    if let Some(timeout) = options.timeout {
+        spawned.kill_after(timeout);
+    }
+
+    Ok(LuaValue::Table(std_process_child::child_table(luau, spawned, stdout, stderr, stdin)?))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata().is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// finds a program the way a shell would: paths are checked directly, bare names are looked up on PATH
+/// (trying each of PATHEXT's extensions on Windows)
+pub fn which(name: &str) -> Option<PathBuf> {
+    let extensions: Vec<String> = if cfg!(windows) {
+        let pathext = env::var("PATHEXT").unwrap_or_else(|_| String::from(".COM;.EXE;.BAT;.CMD"));
+        std::iter::once(String::new()).chain(pathext.split(';').map(String::from)).collect()
+    } else {
+        vec![String::new()]
+    };
+    let candidates = |base: PathBuf| -> Option<PathBuf> {
+        extensions.iter()
+            .map(|extension| PathBuf::from(format!("{}{}", base.display(), extension)))
+            .find(|candidate| is_executable(candidate))
+    };
 
-    Ok(LuaValue::Table(child_handle))
+    if name.contains('/') || (cfg!(windows) && name.contains('\\')) {
+        return candidates(PathBuf::from(name));
+    }
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .find_map(|dir| candidates(dir.join(name)))
+}
+
+fn process_which(_luau: &Lua, name: LuaValue) -> LuaResult<Option<String>> {
+    match name {
+        LuaValue::String(name) => Ok(which(&name.to_string_lossy()).map(|path| path.display().to_string())),
+        other => {
+            wrap_err!("process.which(name: string) expected name to be a string, got: {:#?}", other)
+        }
+    }
 }
 
 fn process_shell(luau: &Lua, shell_command: LuaValue) -> LuaValueResult {
@@ -406,6 +646,8 @@ pub fn handle_exit_callback(luau: &Lua, exit_code: i32) -> LuaResult<()> {
             unreachable!("what did you put into _process_exit_callback_function???");
         }
     }
+    // supervised processes don't outlive the script that started them
+    std_process_supervise::stop_all();
     Ok(())
 }
 
@@ -431,6 +673,7 @@ fn exit(luau: &Lua, exit_code: Option<LuaValue>) -> LuaResult<()> {
             unreachable!("wtf is in _process_exit_callback_function other than a function or nil?: {:?}", other)
         }
     }
+    std_process_supervise::stop_all();
     process::exit(exit_code);
 }
 
@@ -439,8 +682,19 @@ pub fn create(luau: &Lua) -> LuaResult<LuaTable> {
     TableBuilder::create(luau)?
         .with_function("run", process_run)?
         .with_function("spawn", process_spawn)?
+        .with_function("pipeline", std_process_pipeline::process_pipeline)?
+        .with_function("supervise", std_process_supervise::process_supervise)?
         .with_function("shell", process_shell)?
+        .with_function("which", process_which)?
+        .with_function("quote", std_process_shellwords::process_quote)?
+        .with_function("split", std_process_shellwords::process_split)?
         .with_function("setexitcallback", set_exit_callback)?
+        .with_function("on_signal", std_process_signals::process_on_signal)?
+        .with_function("pid", std_process_info::process_pid)?
+        .with_function("ppid", std_process_info::process_ppid)?
+        .with_function("uptime", std_process_info::process_uptime)?
+        .with_function("memory", std_process_info::process_memory)?
+        .with_function("list", std_process_info::process_list)?
         .with_function("exit", exit)?
         .build_readonly()
 }
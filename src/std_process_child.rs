@@ -0,0 +1,583 @@
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::process::{Child, ChildStdin, ExitStatus};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mlua::prelude::*;
+use crate::{colors, std_process_signals, table_helpers::TableBuilder, wrap_err, LuaValueResult};
+
+/// how often we check on a running process while waiting for it to exit
+pub const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// the signal that killed a process, like "SIGKILL", if it was killed by one
+#[cfg(unix)]
+pub fn exit_signal(status: &ExitStatus) -> Option<String> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal().map(signal_name)
+}
+
+#[cfg(not(unix))]
+pub fn exit_signal(_status: &ExitStatus) -> Option<String> {
+    None
+}
+
+#[cfg(unix)]
+const SIGNALS: [(&str, i32); 15] = [
+    ("SIGHUP", libc::SIGHUP),
+    ("SIGINT", libc::SIGINT),
+    ("SIGQUIT", libc::SIGQUIT),
+    ("SIGILL", libc::SIGILL),
+    ("SIGABRT", libc::SIGABRT),
+    ("SIGFPE", libc::SIGFPE),
+    ("SIGKILL", libc::SIGKILL),
+    ("SIGSEGV", libc::SIGSEGV),
+    ("SIGPIPE", libc::SIGPIPE),
+    ("SIGALRM", libc::SIGALRM),
+    ("SIGTERM", libc::SIGTERM),
+    ("SIGUSR1", libc::SIGUSR1),
+    ("SIGUSR2", libc::SIGUSR2),
+    ("SIGCONT", libc::SIGCONT),
+    ("SIGSTOP", libc::SIGSTOP),
+];
+
+#[cfg(unix)]
+fn signal_name(signal: i32) -> String {
+    match SIGNALS.iter().find(|(_name, number)| *number == signal) {
+        Some((name, _number)) => name.to_string(),
+        None => format!("SIG{}", signal),
+    }
+}
+
+/// looks up a signal by name, with or without the SIG prefix ("SIGTERM" or "term")
+#[cfg(unix)]
+pub fn signal_number(name: &str) -> Option<i32> {
+    let name = name.to_uppercase();
+    let name = if name.starts_with("SIG") { name } else { format!("SIG{}", name) };
+    SIGNALS.iter().find(|(signal, _number)| *signal == name).map(|(_signal, number)| *number)
+}
+
+/// how much of a spawned child's output we'll hold onto before leaving the rest in the pipe,
+/// so a child nobody's reading from blocks on its writes instead of growing our memory without bound
+pub const SPAWNED_OUTPUT_LIMIT: usize = 1024 * 1024;
+
+#[derive(Default)]
+struct PipeState {
+    buffer: Vec<u8>,
+    /// the process closed its end (usually by exiting)
+    closed: bool,
+}
+
+pub struct ExpectMatch {
+    /// the output that came before the match
+    pub before: Vec<u8>,
+    /// the whole match, then each capture group (None for groups that didn't participate)
+    pub captures: Vec<Option<Vec<u8>>>,
+}
+
+/// a child's stdout or stderr, drained on its own thread so reads can return whatever's arrived so far
+/// instead of blocking; with a limit, the drain thread stops reading once that much is buffered and
+/// waits for a reader to take some, so the child stalls on a full pipe just like it would without us
+#[derive(Clone)]
+pub struct OutputPipe {
+    state: Arc<(Mutex<PipeState>, Condvar)>,
+    limit: Option<usize>,
+}
+
+impl OutputPipe {
+    /// buffers everything the child writes; for callers that collect all the output anyway
+    pub fn start(pipe: impl Read + Send + 'static) -> Self {
+        Self::drain(pipe, None)
+    }
+
+    /// buffers at most `SPAWNED_OUTPUT_LIMIT` bytes until a reader catches up
+    pub fn start_limited(pipe: impl Read + Send + 'static) -> Self {
+        Self::drain(pipe, Some(SPAWNED_OUTPUT_LIMIT))
+    }
+
+    fn drain(mut pipe: impl Read + Send + 'static, limit: Option<usize>) -> Self {
+        let output = OutputPipe { state: Arc::default(), limit };
+        thread::spawn({
+            let output = output.clone();
+            move || {
+                let mut chunk = [0u8; 8192];
+                loop {
+                    let read = pipe.read(&mut chunk);
+                    let (state, ready) = &*output.state;
+                    let mut state = lock(state);
+                    match read {
+                        Ok(0) | Err(_) => {
+                            state.closed = true;
+                            ready.notify_all();
+                            break;
+                        },
+                        Ok(n) => {
+                            while output.is_full(&state) {
+                                state = ready.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner());
+                            }
+                            state.buffer.extend_from_slice(&chunk[..n]);
+                            ready.notify_all();
+                        }
+                    }
+                }
+            }
+        });
+        output
+    }
+
+    fn is_full(&self, state: &PipeState) -> bool {
+        self.limit.is_some_and(|limit| state.buffer.len() >= limit)
+    }
+
+    /// wakes the drain thread if it's waiting on a full buffer; call after taking anything out of it
+    fn drained(&self) {
+        self.state.1.notify_all();
+    }
+
+    /// blocks until `done` is satisfied or the deadline passes (None waits as long as it takes)
+    fn wait_until(&self, deadline: Option<Instant>, done: impl Fn(&PipeState) -> bool) -> MutexGuard<'_, PipeState> {
+        let (state, ready) = &*self.state;
+        let mut state = lock(state);
+        while !done(&state) {
+            match deadline {
+                None => {
+                    state = ready.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner());
+                },
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    state = ready.wait_timeout(state, deadline - now).unwrap_or_else(|poisoned| poisoned.into_inner()).0;
+                }
+            }
+        }
+        state
+    }
+
+    /// takes up to `max` bytes of whatever's arrived, waiting up to `timeout` for something to arrive;
+    /// None if nothing did
+    pub fn read(&self, max: usize, timeout: Duration) -> Option<Vec<u8>> {
+        let mut state = self.wait_until(Some(Instant::now() + timeout), |state| !state.buffer.is_empty() || state.closed);
+        if state.buffer.is_empty() {
+            return None;
+        }
+        let n = max.min(state.buffer.len());
+        let read = state.buffer.drain(..n).collect();
+        self.drained();
+        Some(read)
+    }
+
+    /// waits for the next full line (without its line ending), or whatever's left once the pipe closes;
+    /// a line longer than the buffer limit comes back in limit-sized pieces
+    pub fn read_line(&self) -> Option<Vec<u8>> {
+        let mut state = self.wait_until(None, |state| state.buffer.contains(&b'\n') || state.closed || self.is_full(state));
+        let mut line: Vec<u8> = match state.buffer.iter().position(|byte| *byte == b'\n') {
+            Some(end) => state.buffer.drain(..=end).collect(),
+            None if !state.buffer.is_empty() => std::mem::take(&mut state.buffer),
+            None => return None,
+        };
+        self.drained();
+        if line.ends_with(b"\n") {
+            line.pop();
+        }
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+        Some(line)
+    }
+
+    /// waits for the pipe to close (or `timeout` to pass) and takes everything that's left,
+    /// emptying the buffer as it fills so a limited pipe keeps draining
+    pub fn read_to_end(&self, timeout: Option<Duration>) -> Vec<u8> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut output = Vec::new();
+        loop {
+            let mut state = self.wait_until(deadline, |state| state.closed || self.is_full(state));
+            output.append(&mut state.buffer);
+            self.drained();
+            if state.closed || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return output;
+            }
+        }
+    }
+
+    /// waits for output matching `regex` (or `timeout` to pass) and takes everything up to the end of the
+    /// match; None if the pipe closes, the buffer fills up, or we time out first, leaving the output unread
+    pub fn expect(&self, regex: &regex::bytes::Regex, timeout: Option<Duration>) -> Option<ExpectMatch> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.wait_until(deadline, |state| state.closed || self.is_full(state) || regex.is_match(&state.buffer));
+        let (start, end, captures) = {
+            let found = regex.captures(&state.buffer)?;
+            let whole = found.get(0)?;
+            let captures = found.iter().map(|capture| capture.map(|capture| capture.as_bytes().to_vec())).collect();
+            (whole.start(), whole.end(), captures)
+        };
+        let mut before: Vec<u8> = state.buffer.drain(..end).collect();
+        before.truncate(start);
+        self.drained();
+        Some(ExpectMatch { before, captures })
+    }
+
+    /// the pipe's closed and there's nothing left to read
+    pub fn at_end(&self) -> bool {
+        let state = lock(&self.state.0);
+        state.closed && state.buffer.is_empty()
+    }
+}
+
+fn optional_timeout(value: Option<LuaValue>, function_name: &str) -> LuaResult<Option<Duration>> {
+    match value {
+        None | Some(LuaNil) => Ok(None),
+        Some(LuaValue::Integer(seconds)) if seconds >= 0 => Ok(Some(Duration::from_secs(seconds as u64))),
+        Some(LuaValue::Number(seconds)) if seconds >= 0.0 => Ok(Some(Duration::from_secs_f64(seconds))),
+        Some(other) => {
+            wrap_err!("{} expected timeout to be a non-negative number of seconds or nil, got: {:#?}", function_name, other)
+        }
+    }
+}
+
+pub fn output_table(luau: &Lua, pipe: OutputPipe, name: &'static str) -> LuaResult<LuaTable> {
+    TableBuilder::create(luau)?
+        .with_function("read", {
+            let pipe = pipe.clone();
+            move | luau: &Lua, mut multivalue: LuaMultiValue | -> LuaValueResult {
+                let _handle = multivalue.pop_front();
+                let max = match multivalue.pop_front() {
+                    None | Some(LuaNil) => usize::MAX,
+                    Some(LuaValue::Integer(i)) if i > 0 => i as usize,
+                    Some(LuaValue::Number(n)) if n > 0.0 && n.trunc() == n => n as usize,
+                    Some(other) => {
+                        return wrap_err!("ChildProcess.{}:read(size: number?, timeout: number?) expected size to be a positive integer or nil, got: {:#?}", name, other);
+                    }
+                };
+                let timeout = optional_timeout(multivalue.pop_front(), "ChildProcess.stdout:read(size: number?, timeout: number?)")?;
+                match pipe.read(max, timeout.unwrap_or_default()) {
+                    Some(data) => Ok(LuaValue::String(luau.create_string(data)?)),
+                    None => Ok(LuaNil),
+                }
+            }
+        })?
+        .with_function("read_to_end", {
+            let pipe = pipe.clone();
+            move | luau: &Lua, _multivalue: LuaMultiValue | -> LuaValueResult {
+                Ok(LuaValue::String(luau.create_string(pipe.read_to_end(None))?))
+            }
+        })?
+        .with_function("lines", {
+            let pipe = pipe.clone();
+            move | luau: &Lua, _multivalue: LuaMultiValue | -> LuaValueResult {
+                Ok(LuaValue::Function(luau.create_function({
+                    let pipe = pipe.clone();
+                    move | luau: &Lua, _value: LuaValue | -> LuaValueResult {
+                        match pipe.read_line() {
+                            Some(line) => Ok(LuaValue::String(luau.create_string(line)?)),
+                            None => Ok(LuaNil),
+                        }
+                    }
+                })?))
+            }
+        })?
+        .with_function("at_end", move | _luau: &Lua, _multivalue: LuaMultiValue | -> LuaResult<bool> {
+            Ok(pipe.at_end())
+        })?
+        .build_readonly()
+}
+
+pub fn stdin_table(luau: &Lua, stdin: Box<dyn Write>) -> LuaResult<LuaTable> {
+    let stdin = Rc::new(RefCell::new(Some(stdin)));
+    TableBuilder::create(luau)?
+        .with_function("write", {
+            let stdin = Rc::clone(&stdin);
+            move | _luau: &Lua, mut multivalue: LuaMultiValue | -> LuaValueResult {
+                let _handle = multivalue.pop_front();
+                let data = match multivalue.pop_front() {
+                    Some(LuaValue::String(data)) => data.as_bytes().to_vec(),
+                    Some(LuaValue::Buffer(data)) => data.to_vec(),
+                    Some(other) => {
+                        return wrap_err!("ChildProcess.stdin:write(data) expected data to be a string or buffer, got: {:?}", other);
+                    },
+                    None => {
+                        return wrap_err!("ChildProcess.stdin:write(data) was called without argument data");
+                    }
+                };
+                let mut stdin = stdin.borrow_mut();
+                let Some(stdin) = stdin.as_mut() else {
+                    return wrap_err!("ChildProcess.stdin:write: stdin has already been closed");
+                };
+                match stdin.write_all(&data).and_then(|_| stdin.flush()) {
+                    Ok(_) => Ok(LuaNil),
+                    Err(err) => wrap_err!("ChildProcess.stdin:write: error writing to stdin: {}", err)
+                }
+            }
+        })?
+        .with_function("close", move | _luau: &Lua, _multivalue: LuaMultiValue | -> LuaValueResult {
+            // dropping our end is what lets a program reading stdin to EOF finish
+            stdin.borrow_mut().take();
+            Ok(LuaNil)
+        })?
+        .build_readonly()
+}
+
+/// a spawned process, shared with the thread that enforces its timeout
+pub struct SpawnedChild {
+    child: Mutex<Child>,
+    timed_out: AtomicBool,
+}
+
+impl SpawnedChild {
+    pub fn new(child: Child) -> Arc<Self> {
+        Arc::new(SpawnedChild { child: Mutex::new(child), timed_out: AtomicBool::new(false) })
+    }
+
+    pub fn timed_out(&self) -> bool {
+        self.timed_out.load(Ordering::SeqCst)
+    }
+
+    pub fn try_wait(&self) -> std::io::Result<Option<ExitStatus>> {
+        lock(&self.child).try_wait()
+    }
+
+    /// waits for the process to exit, without holding the lock the whole time so it can still be killed
+    pub fn wait(&self) -> std::io::Result<ExitStatus> {
+        loop {
+            if let Some(status) = self.try_wait()? {
+                return Ok(status);
+            }
+            thread::sleep(WAIT_POLL_INTERVAL);
+        }
+    }
+
+    pub fn kill(&self) -> std::io::Result<()> {
+        lock(&self.child).kill()
+    }
+
+    /// kills the process if it's still running once `timeout` passes
+    pub fn kill_after(self: &Arc<Self>, timeout: Duration) {
+        let spawned = Arc::clone(self);
+        let deadline = Instant::now() + timeout;
+        thread::spawn(move || {
+            while Instant::now() < deadline {
+                if !matches!(spawned.try_wait(), Ok(None)) {
+                    return;
+                }
+                thread::sleep(WAIT_POLL_INTERVAL);
+            }
+            let mut child = lock(&spawned.child);
+            if matches!(child.try_wait(), Ok(None)) {
+                spawned.timed_out.store(true, Ordering::SeqCst);
+                let _ = child.kill();
+            }
+        });
+    }
+
+    pub fn id(&self) -> u32 {
+        lock(&self.child).id()
+    }
+
+    /// sends `signal` if the process is still running; returns whether it was
+    #[cfg(unix)]
+    fn send_signal(&self, signal: i32) -> std::io::Result<bool> {
+        // hold the lock so the process can't be reaped (and its pid reused) between checking and signalling
+        let mut child = lock(&self.child);
+        if !matches!(child.try_wait(), Ok(None)) {
+            return Ok(false);
+        }
+        // SAFETY: kill has no memory safety requirements; the pid belongs to our unreaped child
+        if unsafe { libc::kill(child.id() as libc::pid_t, signal) } == 0 {
+            Ok(true)
+        } else {
+            Err(std::io::Error::last_os_error())
+        }
+    }
+
+    /// asks the process to exit with SIGTERM, or kills it on platforms without signals
+    #[cfg(unix)]
+    pub fn terminate(&self) -> std::io::Result<()> {
+        self.send_signal(libc::SIGTERM).map(|_sent| ())
+    }
+
+    #[cfg(not(unix))]
+    pub fn terminate(&self) -> std::io::Result<()> {
+        self.kill()
+    }
+
+    #[cfg(unix)]
+    fn signal(&self, name: &str) -> LuaResult<bool> {
+        let Some(signal) = signal_number(name) else {
+            return wrap_err!("ChildProcess:signal: unknown signal '{}'", name);
+        };
+        match self.send_signal(signal) {
+            Ok(sent) => Ok(sent),
+            Err(err) => wrap_err!("ChildProcess:signal: unable to send {} to process {}: {}", name, self.id(), err),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn signal(&self, name: &str) -> LuaResult<bool> {
+        match name.to_uppercase().trim_start_matches("SIG") {
+            "KILL" | "TERM" => {
+                let mut child = lock(&self.child);
+                if !matches!(child.try_wait(), Ok(None)) {
+                    return Ok(false);
+                }
+                match child.kill() {
+                    Ok(()) => Ok(true),
+                    Err(err) => wrap_err!("ChildProcess:signal: unable to stop process: {}", err),
+                }
+            },
+            _ => wrap_err!("ChildProcess:signal: only SIGKILL and SIGTERM are supported on this platform, got '{}'", name),
+        }
+    }
+}
+
+/// `{ ok, exit_code, signal, timed_out }` for a process that's exited, for callers to add to
+pub fn exit_status_fields<'lua>(luau: &'lua Lua, status: &ExitStatus, timed_out: bool) -> LuaResult<TableBuilder<'lua>> {
+    TableBuilder::create(luau)?
+        .with_value("ok", status.success() && !timed_out)?
+        .with_value("exit_code", status.code())?
+        .with_value("signal", exit_signal(status))?
+        .with_value("timed_out", timed_out)
+}
+
+pub fn exit_status_table(luau: &Lua, status: &ExitStatus, timed_out: bool) -> LuaResult<LuaTable> {
+    exit_status_fields(luau, status, timed_out)?.build_readonly()
+}
+
+pub fn child_table(luau: &Lua, spawned: Arc<SpawnedChild>, stdout: Option<OutputPipe>, stderr: Option<OutputPipe>, stdin: Option<ChildStdin>) -> LuaResult<LuaTable> {
+    let stdin = stdin.map(|stdin| Box::new(stdin) as Box<dyn Write>);
+    child_fields(luau, spawned, stdout, stderr, stdin)?.build_readonly()
+}
+
+/// `ChildProcess:expect(pattern: string, timeout: number?)`
+fn child_expect(luau: &Lua, stdout: &OutputPipe, mut multivalue: LuaMultiValue) -> LuaValueResult {
+    let _handle = multivalue.pop_front();
+    let pattern = match multivalue.pop_front() {
+        Some(LuaValue::String(pattern)) => pattern.to_string_lossy(),
+        other => {
+            return wrap_err!("ChildProcess:expect(pattern: string, timeout: number?) expected pattern to be a regex string, got: {:#?}", other);
+        }
+    };
+    let regex = match regex::bytes::Regex::new(&pattern) {
+        Ok(regex) => regex,
+        Err(err) => {
+            return wrap_err!("ChildProcess:expect: invalid pattern {:?}: {}", pattern, err);
+        }
+    };
+    let timeout = optional_timeout(multivalue.pop_front(), "ChildProcess:expect(pattern: string, timeout: number?)")?;
+    let Some(ExpectMatch { before, captures }) = stdout.expect(&regex, timeout) else {
+        return Ok(LuaNil);
+    };
+    let mut captures = captures.into_iter();
+    let matched = captures.next().flatten().unwrap_or_default();
+    let groups = luau.create_table()?;
+    for capture in captures {
+        groups.raw_push(luau.create_string(capture.unwrap_or_default())?)?;
+    }
+    Ok(LuaValue::Table(TableBuilder::create(luau)?
+        .with_value("matched", luau.create_string(matched)?)?
+        .with_value("before", luau.create_string(before)?)?
+        .with_value("captures", groups)?
+        .build_readonly()?
+    ))
+}
+
+/// the ChildProcess table's fields, for callers (like pty spawning) that add their own before building it
+pub fn child_fields<'lua>(luau: &'lua Lua, spawned: Arc<SpawnedChild>, stdout: Option<OutputPipe>, stderr: Option<OutputPipe>, stdin: Option<Box<dyn Write>>) -> LuaResult<TableBuilder<'lua>> {
+    let pid = spawned.id();
+    let expect = match &stdout {
+        Some(stdout) => {
+            let stdout = stdout.clone();
+            Some(luau.create_function(move |luau: &Lua, multivalue: LuaMultiValue| child_expect(luau, &stdout, multivalue))?)
+        },
+        None => None,
+    };
+    let stdout_handle = match stdout {
+        Some(stdout) => Some(output_table(luau, stdout, "stdout")?),
+        None => None,
+    };
+    let stderr_handle = match stderr {
+        Some(stderr) => Some(output_table(luau, stderr, "stderr")?),
+        None => None,
+    };
+    let stdin_handle = match stdin {
+        Some(stdin) => Some(stdin_table(luau, stdin)?),
+        None => None,
+    };
+
+    TableBuilder::create(luau)?
+        .with_value("ok", true)?
+        .with_value("pid", pid)?
+        .with_value("id", pid)?
+        // so `process.spawn { ... }:unwrap()` errors if the program couldn't be started, like RunResult:unwrap
+        .with_function("unwrap", |_luau: &Lua, mut multivalue: LuaMultiValue| -> LuaValueResult {
+            Ok(multivalue.pop_front().unwrap_or(LuaNil))
+        })?
+        .with_function("wait", {
+            let spawned = Arc::clone(&spawned);
+            move | luau: &Lua, _multivalue: LuaMultiValue | -> LuaValueResult {
+                // polled here rather than with SpawnedChild::wait so the script's signal handlers still run
+                loop {
+                    match spawned.try_wait() {
+                        Ok(Some(status)) => {
+                            return Ok(LuaValue::Table(exit_status_table(luau, &status, spawned.timed_out())?));
+                        },
+                        Ok(None) => std_process_signals::sleep(luau, WAIT_POLL_INTERVAL)?,
+                        Err(err) => {
+                            return wrap_err!("ChildProcess:wait: {}", err);
+                        }
+                    }
+                }
+            }
+        })?
+        .with_function("try_wait", {
+            let spawned = Arc::clone(&spawned);
+            move | luau: &Lua, _multivalue: LuaMultiValue | -> LuaValueResult {
+                match spawned.try_wait() {
+                    Ok(Some(status)) => Ok(LuaValue::Table(exit_status_table(luau, &status, spawned.timed_out())?)),
+                    Ok(None) => Ok(LuaNil),
+                    Err(err) => wrap_err!("ChildProcess:try_wait: {}", err),
+                }
+            }
+        })?
+        .with_function("alive", {
+            let spawned = Arc::clone(&spawned);
+            move | _luau: &Lua, _multivalue: LuaMultiValue | -> LuaResult<bool> {
+                Ok(matches!(spawned.try_wait(), Ok(None)))
+            }
+        })?
+        .with_function("kill", {
+            let spawned = Arc::clone(&spawned);
+            move | _luau: &Lua, _multivalue: LuaMultiValue | -> LuaValueResult {
+                match spawned.kill() {
+                    Ok(_) => Ok(LuaValue::Nil),
+                    Err(err) => {
+                        wrap_err!("ChildProcess could not be killed: {:?}", err)
+                    }
+                }
+            }
+        })?
+        .with_function("signal", {
+            let spawned = Arc::clone(&spawned);
+            move | _luau: &Lua, mut multivalue: LuaMultiValue | -> LuaResult<bool> {
+                let _handle = multivalue.pop_front();
+                match multivalue.pop_front() {
+                    Some(LuaValue::String(name)) => spawned.signal(&name.to_string_lossy()),
+                    other => {
+                        wrap_err!("ChildProcess:signal(signal: string) expected a signal name like \"SIGTERM\", got: {:#?}", other)
+                    }
+                }
+            }
+        })?
+        .with_value("stdout", stdout_handle)?
+        .with_value("stderr", stderr_handle)?
+        .with_value("stdin", stdin_handle)?
+        .with_value("expect", expect)
+}
@@ -0,0 +1,147 @@
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use mlua::prelude::*;
+use crate::{colors, wrap_err, LuaValueResult};
+#[cfg(target_os = "linux")]
+use crate::table_helpers::TableBuilder;
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+/// called first thing in main, so `process.uptime` counts from when seal started rather than
+/// from when something first required @std/process
+pub fn record_start() {
+    STARTED.get_or_init(Instant::now);
+}
+
+pub fn process_pid(_luau: &Lua, _value: LuaValue) -> LuaResult<u32> {
+    Ok(std::process::id())
+}
+
+#[cfg(unix)]
+pub fn process_ppid(_luau: &Lua, _value: LuaValue) -> LuaResult<Option<u32>> {
+    Ok(Some(std::os::unix::process::parent_id()))
+}
+
+#[cfg(not(unix))]
+pub fn process_ppid(_luau: &Lua, _value: LuaValue) -> LuaResult<Option<u32>> {
+    Ok(None)
+}
+
+/// seconds since seal started
+pub fn process_uptime(_luau: &Lua, _value: LuaValue) -> LuaResult<f64> {
+    Ok(STARTED.get_or_init(Instant::now).elapsed().as_secs_f64())
+}
+
+#[cfg(target_os = "linux")]
+fn page_size() -> u64 {
+    // SAFETY: sysconf has no memory safety requirements
+    let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    if size > 0 { size as u64 } else { 4096 }
+}
+
+/// a `Name:   1234 kB` field from /proc/*/status, in bytes
+#[cfg(target_os = "linux")]
+fn status_field_bytes(status: &str, field: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with(field) && line[field.len()..].starts_with(':'))?;
+    let kibibytes: u64 = line[field.len() + 1..].trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kibibytes * 1024)
+}
+
+/// `process.memory()`: how much memory seal is using, in bytes
+#[cfg(target_os = "linux")]
+pub fn process_memory(luau: &Lua, _value: LuaValue) -> LuaValueResult {
+    let status = match std::fs::read_to_string("/proc/self/status") {
+        Ok(status) => status,
+        Err(err) => {
+            return wrap_err!("process.memory: unable to read /proc/self/status: {}", err);
+        }
+    };
+    Ok(LuaValue::Table(TableBuilder::create(luau)?
+        .with_value("rss", status_field_bytes(&status, "VmRSS"))?
+        .with_value("peak_rss", status_field_bytes(&status, "VmHWM"))?
+        .with_value("virtual", status_field_bytes(&status, "VmSize"))?
+        .build_readonly()?
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn process_memory(_luau: &Lua, _value: LuaValue) -> LuaValueResult {
+    wrap_err!("process.memory: only supported on Linux for now")
+}
+
+/// the name, state, and parent pid out of /proc/*/stat, whose name is wrapped in parentheses and
+/// can contain spaces and parentheses itself
+#[cfg(target_os = "linux")]
+fn parse_stat(stat: &str) -> Option<(String, &'static str, u32)> {
+    let name_start = stat.find('(')? + 1;
+    let name_end = stat.rfind(')')?;
+    let name = stat.get(name_start..name_end)?.to_string();
+    let mut fields = stat.get(name_end + 1..)?.split_whitespace();
+    let state = match fields.next()? {
+        "R" => "running",
+        "S" => "sleeping",
+        "D" => "disk_sleep",
+        "Z" => "zombie",
+        "T" => "stopped",
+        "t" => "tracing_stop",
+        "X" | "x" => "dead",
+        "I" => "idle",
+        _ => "unknown",
+    };
+    let ppid = fields.next()?.parse().ok()?;
+    Some((name, state, ppid))
+}
+
+/// `process.list()`: every process we can see in /proc
+#[cfg(target_os = "linux")]
+pub fn process_list(luau: &Lua, _value: LuaValue) -> LuaValueResult {
+    let entries = match std::fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(err) => {
+            return wrap_err!("process.list: unable to read /proc: {}", err);
+        }
+    };
+    let mut pids: Vec<u32> = entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    pids.sort_unstable();
+
+    let page_size = page_size();
+    let processes = luau.create_table()?;
+    for pid in pids {
+        // processes can exit while we're looking at them, so skip any that have disappeared
+        let Ok(stat) = std::fs::read_to_string(format!("/proc/{}/stat", pid)) else {
+            continue;
+        };
+        let Some((name, state, ppid)) = parse_stat(&stat) else {
+            continue;
+        };
+        let cmdline: Vec<String> = std::fs::read(format!("/proc/{}/cmdline", pid))
+            .unwrap_or_default()
+            .split(|byte| *byte == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).to_string())
+            .collect();
+        let rss = std::fs::read_to_string(format!("/proc/{}/statm", pid))
+            .ok()
+            .and_then(|statm| statm.split_whitespace().nth(1)?.parse::<u64>().ok())
+            .map(|pages| pages * page_size);
+
+        processes.raw_push(TableBuilder::create(luau)?
+            .with_value("pid", pid)?
+            .with_value("ppid", ppid)?
+            .with_value("name", name)?
+            .with_value("state", state)?
+            .with_value("cmdline", luau.create_sequence_from(cmdline)?)?
+            .with_value("rss", rss)?
+            .build_readonly()?
+        )?;
+    }
+    Ok(LuaValue::Table(processes))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn process_list(_luau: &Lua, _value: LuaValue) -> LuaValueResult {
+    wrap_err!("process.list: only supported on Linux for now")
+}
@@ -0,0 +1,231 @@
+use std::io::Write;
+use std::process::{ChildStdout, ExitStatus, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use mlua::prelude::*;
+use crate::{colors, wrap_err, LuaValueResult};
+use crate::std_process::{self, CaptureAs, OutputMode, RunOptions, SpawnError};
+use crate::std_process_child::{self, exit_signal, OutputPipe, SpawnedChild};
+
+/// how long we keep reading a timed out pipeline's output after killing it
+const TIMED_OUT_OUTPUT_GRACE: Duration = Duration::from_millis(100);
+
+struct PipelineOptions {
+    stdin: Option<Vec<u8>>,
+    timeout: Option<Duration>,
+    capture: CaptureAs,
+}
+
+impl PipelineOptions {
+    fn from_value(value: Option<LuaValue>) -> LuaResult<Self> {
+        let options = match value {
+            None | Some(LuaNil) => {
+                return Ok(PipelineOptions { stdin: None, timeout: None, capture: CaptureAs::String });
+            },
+            Some(LuaValue::Table(options)) => options,
+            Some(other) => {
+                return wrap_err!("process.pipeline(stages, options: PipelineOptions?) expected options to be a table or nil, got: {:#?}", other);
+            }
+        };
+        let stdin = match options.raw_get("stdin")? {
+            LuaValue::String(stdin) => Some(stdin.as_bytes().to_vec()),
+            LuaValue::Buffer(stdin) => Some(stdin.to_vec()),
+            LuaNil => None,
+            other => {
+                return wrap_err!("PipelineOptions.stdin expected to be a string or buffer to write to the first stage's stdin, got: {:#?}", other);
+            }
+        };
+        let timeout = match options.raw_get("timeout")? {
+            LuaValue::Integer(seconds) if seconds > 0 => Some(Duration::from_secs(seconds as u64)),
+            LuaValue::Number(seconds) if seconds > 0.0 => Some(Duration::from_secs_f64(seconds)),
+            LuaNil => None,
+            other => {
+                return wrap_err!("PipelineOptions.timeout expected to be a positive number of seconds or nil, got: {:#?}", other);
+            }
+        };
+        let capture = CaptureAs::from_value(options.raw_get("capture")?, "PipelineOptions")?;
+        Ok(PipelineOptions { stdin, timeout, capture })
+    }
+}
+
+fn parse_stages(luau: &Lua, stages: LuaValue) -> LuaResult<Vec<RunOptions>> {
+    let stages = match stages {
+        LuaValue::Table(stages) => stages,
+        other => {
+            return wrap_err!("process.pipeline(stages: {{ RunOptions }}) expected stages to be an array of RunOptions, got: {:#?}", other);
+        }
+    };
+    let mut parsed = Vec::new();
+    for stage in stages.sequence_values::<LuaValue>() {
+        let stage = match stage? {
+            LuaValue::Table(stage) => RunOptions::from_table(luau, stage)?,
+            other => {
+                return wrap_err!("process.pipeline: stage {} expected to be a RunOptions table, got: {:#?}", parsed.len() + 1, other);
+            }
+        };
+        if stage.pty.is_some() {
+            return wrap_err!("process.pipeline: stage {} ('{}') can't run in a pty; RunOptions.pty is only supported by process.spawn", parsed.len() + 1, stage.program);
+        }
+        if stage.stdin.is_some() {
+            return wrap_err!("process.pipeline: stage {} ('{}') can't have its own stdin; pass PipelineOptions.stdin to feed the first stage instead", parsed.len() + 1, stage.program);
+        }
+        parsed.push(stage);
+    }
+    if parsed.is_empty() {
+        return wrap_err!("process.pipeline(stages: {{ RunOptions }}) expected at least one stage, got an empty table");
+    }
+    let last = parsed.len() - 1;
+    if let Some(index) = parsed[..last].iter().position(|stage| !matches!(stage.stdout, OutputMode::Pipe)) {
+        return wrap_err!("process.pipeline: stage {} ('{}') can't redirect stdout, since it's piped into the next stage", index + 1, parsed[index].program);
+    }
+    Ok(parsed)
+}
+
+struct Stage {
+    program: String,
+    spawned: Arc<SpawnedChild>,
+    stderr: Option<OutputPipe>,
+}
+
+/// whether a stage's exit counts against the pipeline; earlier stages killed by SIGPIPE just had
+/// a later stage stop reading early (like `yes | head -n 1`), which isn't a failure
+fn stage_failed(status: &ExitStatus, timed_out: bool, is_last: bool) -> bool {
+    if timed_out {
+        return true;
+    }
+    if !is_last && exit_signal(status).as_deref() == Some("SIGPIPE") {
+        return false;
+    }
+    !status.success()
+}
+
+fn describe_failure(status: &ExitStatus, timed_out: bool) -> String {
+    if timed_out {
+        String::from("timed out and was killed")
+    } else if let Some(signal) = exit_signal(status) {
+        format!("was killed by {}", signal)
+    } else {
+        format!("exited with code {}", status.code().unwrap_or(-1))
+    }
+}
+
+/// `process.pipeline(stages: { RunOptions }, options: PipelineOptions?)`
+pub fn process_pipeline(luau: &Lua, (stages, options): (LuaValue, Option<LuaValue>)) -> LuaValueResult {
+    let stage_options = parse_stages(luau, stages)?;
+    let options = PipelineOptions::from_value(options)?;
+    let last = stage_options.len() - 1;
+
+    let mut stages: Vec<Stage> = Vec::new();
+    let mut previous_stdout: Option<ChildStdout> = None;
+    let mut final_stdout: Option<OutputPipe> = None;
+
+    for (index, stage) in stage_options.iter().enumerate() {
+        let stdin = match previous_stdout.take() {
+            Some(stdout) => Stdio::from(stdout),
+            None if options.stdin.is_some() => Stdio::piped(),
+            None => Stdio::null(),
+        };
+        let stdout = if index == last { stage.stdout.stdio("stdout")? } else { Stdio::piped() };
+        // the command holds its stdio until it's dropped, which would keep the pipe from the
+        // previous stage open, so it only lives for this statement
+        let spawned = stage.command()
+            .stdin(stdin)
+            .stdout(stdout)
+            .stderr(stage.stderr.stdio("stderr")?)
+            .spawn();
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(err) => {
+                for started in &stages {
+                    let _ = started.spawned.kill();
+                    let _ = started.spawned.wait();
+                }
+                let error = SpawnError::new(stage, err);
+                let nothing = options.capture.to_value(luau, Vec::new())?;
+                return Ok(LuaValue::Table(std_process::failure_result(luau, format!("stage {}: {}", index + 1, error), nothing.clone(), nothing)?
+                    .with_value("kind", error.kind)?
+                    .with_value("program", error.program)?
+                    .with_value("failed_stage", index + 1)?
+                    .with_value("timed_out", false)?
+                    .build_readonly()?
+                ));
+            }
+        };
+
+        if let (Some(input), Some(mut stdin)) = (options.stdin.clone(), child.stdin.take()) {
+            thread::spawn(move || {
+                let _ = stdin.write_all(&input);
+            });
+        }
+        if index == last {
+            final_stdout = child.stdout.take().map(OutputPipe::start);
+        } else {
+            previous_stdout = child.stdout.take();
+        }
+        let stderr = child.stderr.take().map(OutputPipe::start);
+
+        let spawned = SpawnedChild::new(child);
+        for timeout in [stage.timeout, options.timeout].into_iter().flatten() {
+            spawned.kill_after(timeout);
+        }
+        stages.push(Stage { program: stage.program.clone(), spawned, stderr });
+    }
+
+    let mut statuses = Vec::new();
+    for stage in &stages {
+        match stage.spawned.wait() {
+            Ok(status) => statuses.push((status, stage.spawned.timed_out())),
+            Err(err) => {
+                return wrap_err!("process.pipeline: unable to wait on '{}': {}", stage.program, err);
+            }
+        }
+    }
+    let timed_out = statuses.iter().any(|(_status, timed_out)| *timed_out);
+    let grace = if timed_out { Some(TIMED_OUT_OUTPUT_GRACE) } else { None };
+
+    let stdout = final_stdout.map(|pipe| pipe.read_to_end(grace)).unwrap_or_default();
+    let mut stderr = Vec::new();
+    let stage_results = luau.create_table()?;
+    for (stage, (status, stage_timed_out)) in stages.iter().zip(&statuses) {
+        let stage_stderr = stage.stderr.as_ref().map(|pipe| pipe.read_to_end(grace)).unwrap_or_default();
+        stderr.extend_from_slice(&stage_stderr);
+        stage_results.raw_push(std_process_child::exit_status_fields(luau, status, *stage_timed_out)?
+            .with_value("program", stage.program.as_str())?
+            .with_value("stderr", options.capture.to_value(luau, stage_stderr)?)?
+            .build_readonly()?
+        )?;
+    }
+
+    let failed = statuses.iter()
+        .enumerate()
+        .find(|(index, (status, timed_out))| stage_failed(status, *timed_out, *index == last));
+
+    match failed {
+        None => {
+            let (status, _timed_out) = &statuses[last];
+            Ok(LuaValue::Table(std_process::success_result(luau, options.capture.to_value(luau, stdout)?, options.capture.to_value(luau, stderr)?)?
+                .with_value("exit_code", status.code())?
+                .with_value("stages", stage_results)?
+                .build_readonly()?
+            ))
+        },
+        Some((index, (status, stage_timed_out))) => {
+            let err = format!(
+                "stage {} ('{}') {}\n{}",
+                index + 1, stages[index].program, describe_failure(status, *stage_timed_out), String::from_utf8_lossy(&stderr)
+            );
+            let stdout = options.capture.to_value(luau, stdout)?;
+            let stderr = options.capture.to_value(luau, stderr)?;
+            Ok(LuaValue::Table(std_process::failure_result(luau, err, stdout, stderr)?
+                .with_value("exit_code", status.code())?
+                .with_value("signal", exit_signal(status))?
+                .with_value("timed_out", timed_out)?
+                .with_value("failed_stage", index + 1)?
+                .with_value("stages", stage_results)?
+                .build_readonly()?
+            ))
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use mlua::prelude::*;
+use crate::{colors, wrap_err, LuaValueResult};
+use crate::std_process::RunOptions;
+
+#[cfg(unix)]
+use std::{env, io, io::Write, path::PathBuf, rc::Rc, thread};
+#[cfg(unix)]
+use portable_pty::{native_pty_system, CommandBuilder, MasterPty, PtySize};
+#[cfg(unix)]
+use crate::std_process::{self, OutputMode, SpawnError};
+#[cfg(unix)]
+use crate::std_process_child::{self, OutputPipe, SpawnedChild};
+
+#[cfg(unix)]
+fn pty_size(rows: u16, cols: u16) -> PtySize {
+    PtySize { rows, cols, pixel_width: 0, pixel_height: 0 }
+}
+
+#[cfg(unix)]
+fn pty_command(options: &RunOptions, program: String, args: Vec<String>) -> LuaResult<CommandBuilder> {
+    let mut command = CommandBuilder::new(program);
+    command.args(args);
+    // portable-pty starts processes in the home directory (or quietly falls back to it) unless told otherwise
+    let cwd = match &options.cwd {
+        Some(cwd) => PathBuf::from(cwd),
+        None => match env::current_dir() {
+            Ok(cwd) => cwd,
+            Err(err) => {
+                return wrap_err!("process.spawn: unable to get the current directory: {}", err);
+            }
+        },
+    };
+    if !cwd.is_dir() {
+        return wrap_err!("process.spawn: RunOptions.cwd '{}' isn't a directory", cwd.display());
+    }
+    command.cwd(cwd);
+    if options.clear_env {
+        command.env_clear();
+    }
+    for (key, value) in &options.env {
+        command.env(key, value);
+    }
+    if command.get_env("TERM").is_none() {
+        command.env("TERM", "xterm-256color");
+    }
+    Ok(command)
+}
+
+#[cfg(unix)]
+fn resize(master: &dyn MasterPty, mut multivalue: LuaMultiValue) -> LuaValueResult {
+    let _handle = multivalue.pop_front();
+    let rows = std_process::pty_dimension(multivalue.pop_front().unwrap_or(LuaNil), "rows", 0)?;
+    let cols = std_process::pty_dimension(multivalue.pop_front().unwrap_or(LuaNil), "cols", 0)?;
+    if rows == 0 || cols == 0 {
+        return wrap_err!("ChildProcess:resize(rows: number, cols: number) expected both rows and cols");
+    }
+    match master.resize(pty_size(rows, cols)) {
+        Ok(()) => Ok(LuaNil),
+        Err(err) => wrap_err!("ChildProcess:resize: unable to resize the pty: {}", err),
+    }
+}
+
+/// `process.spawn` with `pty`: runs the process with a pseudo-terminal as its stdin, stdout and stderr,
+/// so programs that behave differently (or refuse to prompt) without a terminal can be scripted
+#[cfg(unix)]
+pub fn spawn_pty(luau: &Lua, options: &RunOptions, (rows, cols): (u16, u16)) -> LuaValueResult {
+    if !matches!(options.stdout, OutputMode::Pipe) || !matches!(options.stderr, OutputMode::Pipe) {
+        return wrap_err!("process.spawn: RunOptions.stdout and stderr can't be redirected with a pty, since the pty is the process' terminal");
+    }
+    let (program, args) = options.argv();
+    // portable-pty only reports a missing program as an opaque error, so look for it ourselves
+    if std_process::which(&program).is_none() {
+        return std_process::spawn_failure(luau, SpawnError::new(options, io::Error::from(io::ErrorKind::NotFound)));
+    }
+    let command = pty_command(options, program, args)?;
+
+    let pair = match native_pty_system().openpty(pty_size(rows, cols)) {
+        Ok(pair) => pair,
+        Err(err) => {
+            return wrap_err!("process.spawn: unable to open a pty: {}", err);
+        }
+    };
+    let child = match pair.slave.spawn_command(command) {
+        Ok(child) => child,
+        Err(err) => {
+            return std_process::spawn_failure(luau, SpawnError::new(options, io::Error::other(err.to_string())));
+        }
+    };
+    // the child has its own copy of the terminal; ours has to close so reads end once the child exits
+    drop(pair.slave);
+    // on unix, pty children are plain std children, so they can be waited on and signalled like any other
+    let child = match child.into_any().downcast::<std::process::Child>() {
+        Ok(child) => *child,
+        Err(_) => {
+            return wrap_err!("process.spawn: unexpected pty child process type");
+        }
+    };
+
+    let (reader, mut writer) = match (pair.master.try_clone_reader(), pair.master.take_writer()) {
+        (Ok(reader), Ok(writer)) => (reader, writer),
+        (Err(err), _) | (_, Err(err)) => {
+            return wrap_err!("process.spawn: unable to open the pty for reading and writing: {}", err);
+        }
+    };
+    // stdin given up front gets written and closed, rather than left open for ChildProcess.stdin:write
+    let stdin: Option<Box<dyn Write>> = match options.stdin.clone() {
+        Some(input) => {
+            thread::spawn(move || {
+                let _ = writer.write_all(&input);
+            });
+            None
+        },
+        None => Some(Box::new(writer)),
+    };
+
+    let spawned = SpawnedChild::new(child);
+    if let Some(timeout) = options.timeout {
+        spawned.kill_after(timeout);
+    }
+    let master = Rc::new(pair.master);
+    Ok(LuaValue::Table(std_process_child::child_fields(luau, spawned, Some(OutputPipe::start_limited(reader)), None, stdin)?
+        .with_function("resize", move |_luau: &Lua, multivalue: LuaMultiValue| resize(master.as_ref().as_ref(), multivalue))?
+        .build_readonly()?
+    ))
+}
+
+#[cfg(not(unix))]
+pub fn spawn_pty(_luau: &Lua, _options: &RunOptions, _size: (u16, u16)) -> LuaValueResult {
+    wrap_err!("process.spawn: RunOptions.pty isn't supported on this platform yet")
+}
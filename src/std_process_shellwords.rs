@@ -0,0 +1,131 @@
+use crate::{colors, wrap_err, LuaValueResult};
+use mlua::prelude::*;
+
+/// characters that never need quoting in a POSIX shell word
+fn is_shell_safe(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c)
+}
+
+/// quotes `arg` so a POSIX shell reads it back as exactly one word, leaving it alone when it's already safe
+pub fn quote(arg: &str) -> String {
+    if !arg.is_empty() && arg.chars().all(is_shell_safe) {
+        return arg.to_string();
+    }
+    // nothing is special inside single quotes, so the only thing to handle is a single quote itself:
+    // close the quotes, add an escaped quote, and reopen them
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// quotes `arg` for PowerShell, where single-quoted strings are literal except for doubled single quotes
+pub fn quote_powershell(arg: &str) -> String {
+    if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "_./-:\\".contains(c)) {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', "''"))
+}
+
+/// splits a command line into words the way a POSIX shell would (quotes, backslash escapes,
+/// line continuations), without doing any expansion
+pub fn split(cmdline: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    // whether we're in a word, since '' is a word even though it's empty
+    let mut in_word = false;
+    let mut chars = cmdline.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            },
+            '\\' => match chars.next() {
+                // a backslash before a newline continues the line
+                Some('\n') => {},
+                Some(escaped) => {
+                    word.push(escaped);
+                    in_word = true;
+                },
+                None => return Err(String::from("trailing backslash with nothing to escape")),
+            },
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(String::from("unterminated single quote")),
+                    }
+                }
+            },
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        // inside double quotes, backslash only escapes the characters that are special there
+                        Some('\\') => match chars.next() {
+                            Some('\n') => {},
+                            Some(escaped @ ('$' | '`' | '"' | '\\')) => word.push(escaped),
+                            Some(other) => {
+                                word.push('\\');
+                                word.push(other);
+                            },
+                            None => return Err(String::from("unterminated double quote")),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(String::from("unterminated double quote")),
+                    }
+                }
+            },
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// `process.quote(args: string | { string })`
+pub fn process_quote(_luau: &Lua, args: LuaValue) -> LuaResult<String> {
+    match args {
+        LuaValue::String(arg) => Ok(quote(&arg.to_string_lossy())),
+        LuaValue::Table(args) => {
+            let mut quoted = Vec::new();
+            for arg in args.sequence_values::<LuaValue>() {
+                match arg? {
+                    LuaValue::String(arg) => quoted.push(quote(&arg.to_string_lossy())),
+                    LuaValue::Integer(n) => quoted.push(n.to_string()),
+                    LuaValue::Number(n) => quoted.push(quote(&n.to_string())),
+                    other => {
+                        return wrap_err!("process.quote(args: {{ string }}) expected every arg to be a string, got: {:#?}", other);
+                    }
+                }
+            }
+            Ok(quoted.join(" "))
+        },
+        other => {
+            wrap_err!("process.quote(args: string | {{ string }}) expected a string or array of strings, got: {:#?}", other)
+        }
+    }
+}
+
+/// `process.split(cmdline: string)`
+pub fn process_split(luau: &Lua, cmdline: LuaValue) -> LuaValueResult {
+    let cmdline = match cmdline {
+        LuaValue::String(cmdline) => cmdline.to_string_lossy(),
+        other => {
+            return wrap_err!("process.split(cmdline: string) expected cmdline to be a string, got: {:#?}", other);
+        }
+    };
+    match split(&cmdline) {
+        Ok(words) => Ok(LuaValue::Table(luau.create_sequence_from(words)?)),
+        Err(err) => wrap_err!("process.split: unable to split {:?}: {}", cmdline, err),
+    }
+}
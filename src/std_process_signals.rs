@@ -0,0 +1,210 @@
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mlua::prelude::*;
+use crate::{colors, wrap_err, LuaValueResult};
+
+/// how often blocking waits check for signals once a handler's registered
+const SIGNAL_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// the signals scripts can handle, in the same order as `PENDING` and `INSTALLED`
+#[cfg(unix)]
+const HANDLEABLE_SIGNALS: [(&str, i32); 3] = [
+    ("SIGINT", libc::SIGINT),
+    ("SIGTERM", libc::SIGTERM),
+    ("SIGHUP", libc::SIGHUP),
+];
+
+/// set from the OS signal handler, which can't safely do anything else, and cleared once the Luau handlers have run
+#[cfg(unix)]
+static PENDING: [AtomicBool; 3] = [AtomicBool::new(false), AtomicBool::new(false), AtomicBool::new(false)];
+/// whether we've replaced the default behavior (usually exiting) for each signal
+#[cfg(unix)]
+static INSTALLED: [AtomicBool; 3] = [AtomicBool::new(false), AtomicBool::new(false), AtomicBool::new(false)];
+/// whether the script has its own handlers for each signal
+#[cfg(unix)]
+static SCRIPT_HANDLES: [AtomicBool; 3] = [AtomicBool::new(false), AtomicBool::new(false), AtomicBool::new(false)];
+/// the last SIGINT or SIGTERM received, for Rust-side cleanup (like stopping supervised processes)
+/// that has to happen whether or not the script handles it
+#[cfg(unix)]
+static SHUTDOWN_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+/// marks the Luau state that registered signal handlers; thread states have their own globals and
+/// shouldn't take signals meant for the main script
+struct HandlesSignals;
+
+fn handles_signals(luau: &Lua) -> bool {
+    luau.app_data_ref::<HandlesSignals>().is_some()
+}
+
+#[cfg(unix)]
+extern "C" fn record_signal(signal: libc::c_int) {
+    if let Some(index) = HANDLEABLE_SIGNALS.iter().position(|(_name, number)| *number == signal) {
+        PENDING[index].store(true, Ordering::SeqCst);
+    }
+    if signal == libc::SIGINT || signal == libc::SIGTERM {
+        SHUTDOWN_SIGNAL.store(signal, Ordering::SeqCst);
+    }
+}
+
+#[cfg(unix)]
+fn ensure_installed(index: usize) -> std::io::Result<()> {
+    if !INSTALLED[index].swap(true, Ordering::SeqCst) {
+        if let Err(err) = install_handler(HANDLEABLE_SIGNALS[index].1) {
+            INSTALLED[index].store(false, Ordering::SeqCst);
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// starts catching SIGINT and SIGTERM (if the script isn't already) so `take_shutdown_signal` sees them;
+/// until then they still exit seal like normal
+#[cfg(unix)]
+pub fn watch_shutdown_signals() -> std::io::Result<()> {
+    for (index, (_name, number)) in HANDLEABLE_SIGNALS.iter().enumerate() {
+        if *number == libc::SIGINT || *number == libc::SIGTERM {
+            ensure_installed(index)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn watch_shutdown_signals() -> std::io::Result<()> {
+    Ok(())
+}
+
+/// the SIGINT or SIGTERM received since we last checked, if any: its number, and whether the script
+/// handles it (otherwise it's on us to exit)
+#[cfg(unix)]
+pub fn take_shutdown_signal() -> Option<(i32, bool)> {
+    let signal = SHUTDOWN_SIGNAL.swap(0, Ordering::SeqCst);
+    let index = HANDLEABLE_SIGNALS.iter().position(|(_name, number)| *number == signal)?;
+    Some((signal, SCRIPT_HANDLES[index].load(Ordering::SeqCst)))
+}
+
+#[cfg(not(unix))]
+pub fn take_shutdown_signal() -> Option<(i32, bool)> {
+    None
+}
+
+#[cfg(unix)]
+fn install_handler(signal: i32) -> std::io::Result<()> {
+    // SAFETY: record_signal only touches atomics, which is async-signal-safe
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = record_signal as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// runs the Luau handlers for any signals received since we last checked
+#[cfg(unix)]
+pub fn dispatch_pending(luau: &Lua) -> LuaResult<()> {
+    if !handles_signals(luau) {
+        return Ok(());
+    }
+    for (index, (name, _number)) in HANDLEABLE_SIGNALS.iter().enumerate() {
+        if !PENDING[index].swap(false, Ordering::SeqCst) {
+            continue;
+        }
+        let LuaValue::Table(all_handlers) = luau.globals().raw_get("_process_signal_handlers")? else {
+            continue;
+        };
+        if let LuaValue::Table(handlers) = all_handlers.raw_get(*name)? {
+            for handler in handlers.sequence_values::<LuaFunction>() {
+                handler?.call::<()>(*name)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn dispatch_pending(_luau: &Lua) -> LuaResult<()> {
+    Ok(())
+}
+
+/// sleeps like `thread::sleep`, but still runs signal handlers on time if the script has any
+pub fn sleep(luau: &Lua, duration: Duration) -> LuaResult<()> {
+    if !handles_signals(luau) {
+        thread::sleep(duration);
+        return Ok(());
+    }
+    let deadline = Instant::now() + duration;
+    loop {
+        dispatch_pending(luau)?;
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(());
+        }
+        thread::sleep(SIGNAL_POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+/// `process.on_signal(signal: "SIGINT" | "SIGTERM" | "SIGHUP", handler: (signal: string) -> ())`
+#[cfg(unix)]
+pub fn process_on_signal(luau: &Lua, (signal, handler): (LuaValue, LuaValue)) -> LuaValueResult {
+    let name = match signal {
+        LuaValue::String(name) => name.to_string_lossy(),
+        other => {
+            return wrap_err!("process.on_signal(signal: string, handler) expected signal to be \"SIGINT\", \"SIGTERM\", or \"SIGHUP\", got: {:#?}", other);
+        }
+    };
+    let Some(index) = HANDLEABLE_SIGNALS.iter().position(|(signal, _number)| *signal == name) else {
+        return wrap_err!("process.on_signal: can't handle signal '{}'; expected \"SIGINT\", \"SIGTERM\", or \"SIGHUP\"", name);
+    };
+    let handler = match handler {
+        LuaValue::Function(handler) => handler,
+        other => {
+            return wrap_err!("process.on_signal(signal, handler: (signal: string) -> ()) expected handler to be a function, got: {:#?}", other);
+        }
+    };
+
+    let globals = luau.globals();
+    let all_handlers = match globals.raw_get("_process_signal_handlers")? {
+        LuaValue::Table(all_handlers) => all_handlers,
+        _ => {
+            let all_handlers = luau.create_table()?;
+            globals.raw_set("_process_signal_handlers", all_handlers.clone())?;
+            all_handlers
+        }
+    };
+    let handlers = match all_handlers.raw_get(name.as_str())? {
+        LuaValue::Table(handlers) => handlers,
+        _ => {
+            let handlers = luau.create_table()?;
+            all_handlers.raw_set(name.as_str(), handlers.clone())?;
+            handlers
+        }
+    };
+    handlers.raw_push(handler)?;
+
+    if let Err(err) = ensure_installed(index) {
+        return wrap_err!("process.on_signal: unable to handle {}: {}", name, err);
+    }
+    SCRIPT_HANDLES[index].store(true, Ordering::SeqCst);
+    if !handles_signals(luau) {
+        luau.set_app_data(HandlesSignals);
+        // luau checks in at function calls and loop iterations, so handlers run promptly while
+        // the script's busy; blocking waits (like time.wait) check for themselves
+        luau.set_interrupt(|luau: &Lua| {
+            dispatch_pending(luau)?;
+            Ok(LuaVmState::Continue)
+        });
+    }
+    Ok(LuaNil)
+}
+
+#[cfg(not(unix))]
+pub fn process_on_signal(_luau: &Lua, _args: (LuaValue, LuaValue)) -> LuaValueResult {
+    wrap_err!("process.on_signal: signal handling isn't supported on this platform yet")
+}
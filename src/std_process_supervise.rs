@@ -0,0 +1,445 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process::{self, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Once};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use mlua::prelude::*;
+use crate::{colors, std_process_signals, table_helpers::TableBuilder, wrap_err, LuaValueResult};
+use crate::std_process::{OutputMode, RunOptions, SpawnError};
+use crate::std_process_child::{exit_signal, SpawnedChild};
+
+/// how long stopped services get to exit after SIGTERM before they're killed
+const STOP_GRACE: Duration = Duration::from_secs(5);
+/// backoff doubles after each restart, up to this; a service that stays up this long gets its backoff reset
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+/// how often we check whether a service's been stopped, while waiting to restart it or for it to exit
+const SUPERVISE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// how often we check for SIGINT/SIGTERM while anything's supervised
+const SIGNAL_WATCH_INTERVAL: Duration = Duration::from_millis(50);
+
+/// service name colors, handed out in order so services next to each other are easy to tell apart
+const PREFIX_COLORS: [&str; 10] = [
+    colors::CYAN, colors::MAGENTA, colors::YELLOW, colors::GREEN, colors::BLUE,
+    colors::BRIGHT_CYAN, colors::BRIGHT_MAGENTA, colors::BRIGHT_YELLOW, colors::BRIGHT_GREEN, colors::BRIGHT_BLUE,
+];
+static NEXT_COLOR: AtomicUsize = AtomicUsize::new(0);
+
+/// every service we've started, so they can all be stopped when seal exits
+static SERVICES: Mutex<Vec<Arc<Service>>> = Mutex::new(Vec::new());
+static WATCH_SIGNALS: Once = Once::new();
+/// set once we're stopping everything to exit on a signal the script doesn't handle, so Supervisor:wait
+/// doesn't return (and let the script finish with exit code 0) in the meantime
+static EXITING_ON_SIGNAL: AtomicBool = AtomicBool::new(false);
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Restart {
+    Always,
+    OnFailure,
+    Never,
+}
+
+struct ServiceState {
+    child: Option<Arc<SpawnedChild>>,
+    status: &'static str,
+    restarts: u32,
+    exit_code: Option<i32>,
+    signal: Option<String>,
+}
+
+struct Service {
+    name: String,
+    /// the colored `[name]` its output lines start with, padded to line up with the other services
+    prefix: String,
+    options: RunOptions,
+    restart: Restart,
+    max_restarts: Option<u32>,
+    backoff: Duration,
+    stopping: AtomicBool,
+    finished: AtomicBool,
+    state: Mutex<ServiceState>,
+}
+
+impl Service {
+    fn from_table(luau: &Lua, service: LuaTable) -> LuaResult<Self> {
+        let name = match service.raw_get("name")? {
+            LuaValue::String(name) => name.to_string_lossy(),
+            other => {
+                return wrap_err!("ServiceOptions.name expected to be a string, got: {:#?}", other);
+            }
+        };
+        let restart = match service.raw_get("restart")? {
+            LuaNil => Restart::OnFailure,
+            LuaValue::String(restart) => match restart.to_string_lossy().as_str() {
+                "always" => Restart::Always,
+                "on-failure" => Restart::OnFailure,
+                "never" => Restart::Never,
+                other => {
+                    return wrap_err!("ServiceOptions.restart expected \"always\", \"on-failure\", or \"never\", got: {:?}", other);
+                }
+            },
+            other => {
+                return wrap_err!("ServiceOptions.restart expected \"always\", \"on-failure\", or \"never\", got: {:#?}", other);
+            }
+        };
+        let max_restarts = match service.raw_get("max_restarts")? {
+            LuaNil => None,
+            LuaValue::Integer(n) if n >= 0 => Some(n as u32),
+            LuaValue::Number(n) if n >= 0.0 && n.trunc() == n => Some(n as u32),
+            other => {
+                return wrap_err!("ServiceOptions.max_restarts expected to be a non-negative integer or nil, got: {:#?}", other);
+            }
+        };
+        let backoff = match service.raw_get("backoff")? {
+            LuaNil => DEFAULT_BACKOFF,
+            LuaValue::Integer(seconds) if seconds >= 0 => Duration::from_secs(seconds as u64),
+            LuaValue::Number(seconds) if seconds >= 0.0 => Duration::from_secs_f64(seconds),
+            other => {
+                return wrap_err!("ServiceOptions.backoff expected to be a non-negative number of seconds or nil, got: {:#?}", other);
+            }
+        };
+        let options = RunOptions::from_table(luau, service)?;
+        if options.pty.is_some() {
+            return wrap_err!("process.supervise: service '{}' can't run in a pty; RunOptions.pty is only supported by process.spawn", name);
+        }
+
+        Ok(Service {
+            name,
+            prefix: String::new(),
+            options,
+            restart,
+            max_restarts,
+            backoff,
+            stopping: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+            state: Mutex::new(ServiceState { child: None, status: "starting", restarts: 0, exit_code: None, signal: None }),
+        })
+    }
+
+    /// a message from the supervisor (rather than the service), dimmed so it stands out from the service's output
+    fn announce(&self, message: &str) {
+        eprintln!("{} {}{}{}", self.prefix, colors::BRIGHT_BLACK, message, colors::RESET);
+    }
+
+    fn set_status(&self, status: &'static str) {
+        lock(&self.state).status = status;
+    }
+
+    fn output_stdio(mode: &OutputMode, field: &str) -> LuaResult<Stdio> {
+        match mode {
+            // piped output gets prefixed with the service name instead of returned
+            OutputMode::Pipe => Ok(Stdio::piped()),
+            other => other.stdio(field),
+        }
+    }
+
+    /// copies a service's output to ours a line at a time, each prefixed with the service's name
+    fn forward_lines(&self, pipe: impl Read + Send + 'static, to_stderr: bool) -> JoinHandle<()> {
+        let prefix = self.prefix.clone();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(pipe);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                match reader.read_until(b'\n', &mut line) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {
+                        while line.ends_with(b"\n") || line.ends_with(b"\r") {
+                            line.pop();
+                        }
+                        let write = |out: &mut dyn Write| -> io::Result<()> {
+                            out.write_all(prefix.as_bytes())?;
+                            out.write_all(b" ")?;
+                            out.write_all(&line)?;
+                            out.write_all(b"\n")?;
+                            out.flush()
+                        };
+                        let _ = if to_stderr { write(&mut io::stderr().lock()) } else { write(&mut io::stdout().lock()) };
+                    }
+                }
+            }
+        })
+    }
+
+    /// starts the service once; None if it couldn't be started
+    fn run_once(&self) -> Option<(ExitStatus, bool)> {
+        let stdio = (
+            Self::output_stdio(&self.options.stdout, "stdout"),
+            Self::output_stdio(&self.options.stderr, "stderr"),
+        );
+        let (Ok(stdout), Ok(stderr)) = stdio else {
+            self.announce("unable to open the file its output is redirected to");
+            return None;
+        };
+        let stdin = if self.options.stdin.is_some() { Stdio::piped() } else { Stdio::null() };
+        let mut child = match self.options.command().stdin(stdin).stdout(stdout).stderr(stderr).spawn() {
+            Ok(child) => child,
+            Err(err) => {
+                self.announce(&format!("failed to start: {}", SpawnError::new(&self.options, err)));
+                return None;
+            }
+        };
+
+        if let (Some(input), Some(mut stdin)) = (self.options.stdin.clone(), child.stdin.take()) {
+            thread::spawn(move || {
+                let _ = stdin.write_all(&input);
+            });
+        }
+        let forwarders: Vec<JoinHandle<()>> = [
+            child.stdout.take().map(|stdout| self.forward_lines(stdout, false)),
+            child.stderr.take().map(|stderr| self.forward_lines(stderr, true)),
+        ].into_iter().flatten().collect();
+
+        let spawned = SpawnedChild::new(child);
+        if let Some(timeout) = self.options.timeout {
+            spawned.kill_after(timeout);
+        }
+        {
+            let mut state = lock(&self.state);
+            state.child = Some(Arc::clone(&spawned));
+            state.status = "running";
+        }
+        // stop might've been called while we were starting it, before there was a child to stop
+        if self.stopping.load(Ordering::SeqCst) {
+            let _ = spawned.terminate();
+        }
+
+        let status = spawned.wait();
+        // let its last lines print before we say it exited
+        for forwarder in forwarders {
+            let _ = forwarder.join();
+        }
+        let mut state = lock(&self.state);
+        state.child = None;
+        match status {
+            Ok(status) => {
+                state.exit_code = status.code();
+                state.signal = exit_signal(&status);
+                Some((status, spawned.timed_out()))
+            },
+            Err(_err) => None,
+        }
+    }
+
+    /// sleeps before a restart; returns false if the service was stopped in the meantime
+    fn wait_to_restart(&self, delay: Duration) -> bool {
+        let deadline = Instant::now() + delay;
+        while Instant::now() < deadline {
+            if self.stopping.load(Ordering::SeqCst) {
+                return false;
+            }
+            thread::sleep(SUPERVISE_POLL_INTERVAL);
+        }
+        !self.stopping.load(Ordering::SeqCst)
+    }
+
+    fn supervise(&self) {
+        let mut backoff = self.backoff;
+        loop {
+            let started = Instant::now();
+            let exited = self.run_once();
+            if self.stopping.load(Ordering::SeqCst) {
+                self.set_status("stopped");
+                break;
+            }
+
+            let (failed, description) = match &exited {
+                Some((_status, true)) => (true, format!("timed out after {:?} and was killed", self.options.timeout.unwrap_or_default())),
+                Some((status, false)) => match (status.code(), exit_signal(status)) {
+                    (_, Some(signal)) => (true, format!("was killed by {}", signal)),
+                    (Some(code), None) => (code != 0, format!("exited with code {}", code)),
+                    (None, None) => (true, String::from("exited")),
+                },
+                None => (true, String::from("couldn't be started")),
+            };
+            let restart = match self.restart {
+                Restart::Always => true,
+                Restart::OnFailure => failed,
+                Restart::Never => false,
+            };
+            if !restart {
+                self.announce(&description);
+                self.set_status(if failed { "failed" } else { "exited" });
+                break;
+            }
+            let restarts = lock(&self.state).restarts;
+            if self.max_restarts.is_some_and(|max_restarts| restarts >= max_restarts) {
+                self.announce(&format!("{}; giving up after {} restarts", description, restarts));
+                self.set_status("failed");
+                break;
+            }
+
+            // something that stayed up a good while before exiting is probably not crash looping
+            if started.elapsed() >= MAX_BACKOFF {
+                backoff = self.backoff;
+            }
+            self.announce(&format!("{}; restarting in {:?}", description, backoff));
+            self.set_status("restarting");
+            if !self.wait_to_restart(backoff) {
+                self.set_status("stopped");
+                break;
+            }
+            lock(&self.state).restarts += 1;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+        self.finished.store(true, Ordering::SeqCst);
+    }
+
+    /// asks the service to exit, without waiting for it
+    fn begin_stop(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        if let Some(child) = &lock(&self.state).child {
+            let _ = child.terminate();
+        }
+    }
+
+    fn kill(&self) {
+        if let Some(child) = &lock(&self.state).child {
+            let _ = child.kill();
+        }
+    }
+}
+
+/// stops services with SIGTERM, killing whatever's still running after STOP_GRACE
+fn stop_services(services: &[Arc<Service>]) {
+    for service in services {
+        service.begin_stop();
+    }
+    let deadline = Instant::now() + STOP_GRACE;
+    while services.iter().any(|service| !service.finished.load(Ordering::SeqCst)) {
+        if Instant::now() >= deadline {
+            for service in services {
+                service.kill();
+            }
+        }
+        thread::sleep(SUPERVISE_POLL_INTERVAL);
+    }
+}
+
+/// stops every supervised service; called when seal exits
+pub fn stop_all() {
+    let services = lock(&SERVICES).clone();
+    stop_services(&services);
+}
+
+/// stops supervised services on SIGINT/SIGTERM, then exits like the signal would've unless the script handles it
+fn watch_signals() -> io::Result<()> {
+    std_process_signals::watch_shutdown_signals()?;
+    thread::spawn(|| loop {
+        if let Some((signal, script_handles)) = std_process_signals::take_shutdown_signal() {
+            EXITING_ON_SIGNAL.store(!script_handles, Ordering::SeqCst);
+            stop_all();
+            if !script_handles {
+                process::exit(128 + signal);
+            }
+        }
+        thread::sleep(SIGNAL_WATCH_INTERVAL);
+    });
+    Ok(())
+}
+
+fn supervisor_status(luau: &Lua, services: &[Arc<Service>]) -> LuaValueResult {
+    let statuses = luau.create_table()?;
+    for service in services {
+        let state = lock(&service.state);
+        statuses.raw_push(TableBuilder::create(luau)?
+            .with_value("name", service.name.as_str())?
+            .with_value("status", state.status)?
+            .with_value("pid", state.child.as_ref().map(|child| child.id()))?
+            .with_value("restarts", state.restarts)?
+            .with_value("exit_code", state.exit_code)?
+            .with_value("signal", state.signal.clone())?
+            .build_readonly()?
+        )?;
+    }
+    Ok(LuaValue::Table(statuses))
+}
+
+fn supervisor_wait(luau: &Lua, services: &[Arc<Service>], mut multivalue: LuaMultiValue) -> LuaResult<bool> {
+    let _handle = multivalue.pop_front();
+    let deadline = match multivalue.pop_front() {
+        None | Some(LuaNil) => None,
+        Some(LuaValue::Integer(seconds)) if seconds >= 0 => Some(Instant::now() + Duration::from_secs(seconds as u64)),
+        Some(LuaValue::Number(seconds)) if seconds >= 0.0 => Some(Instant::now() + Duration::from_secs_f64(seconds)),
+        Some(other) => {
+            return wrap_err!("Supervisor:wait(timeout: number?) expected timeout to be a non-negative number of seconds or nil, got: {:#?}", other);
+        }
+    };
+    loop {
+        let finished = services.iter().all(|service| service.finished.load(Ordering::SeqCst));
+        if finished && !EXITING_ON_SIGNAL.load(Ordering::SeqCst) {
+            return Ok(true);
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Ok(false);
+        }
+        std_process_signals::sleep(luau, SUPERVISE_POLL_INTERVAL)?;
+    }
+}
+
+/// `process.supervise(services: ServiceOptions | { ServiceOptions })`
+pub fn process_supervise(luau: &Lua, services: LuaValue) -> LuaValueResult {
+    let services = match services {
+        // a single service
+        LuaValue::Table(service) if service.raw_get::<LuaValue>("program")? != LuaNil => vec![Service::from_table(luau, service)?],
+        LuaValue::Table(services) => {
+            let mut parsed = Vec::new();
+            for service in services.sequence_values::<LuaValue>() {
+                match service? {
+                    LuaValue::Table(service) => parsed.push(Service::from_table(luau, service)?),
+                    other => {
+                        return wrap_err!("process.supervise(services: {{ ServiceOptions }}) expected every service to be a ServiceOptions table, got: {:#?}", other);
+                    }
+                }
+            }
+            parsed
+        },
+        other => {
+            return wrap_err!("process.supervise(services: ServiceOptions | {{ ServiceOptions }}) expected a ServiceOptions table or an array of them, got: {:#?}", other);
+        }
+    };
+    if services.is_empty() {
+        return wrap_err!("process.supervise expected at least one service, got an empty table");
+    }
+
+    let mut watch_result = Ok(());
+    WATCH_SIGNALS.call_once(|| watch_result = watch_signals());
+    if let Err(err) = watch_result {
+        return wrap_err!("process.supervise: unable to watch for SIGINT and SIGTERM: {}", err);
+    }
+
+    let width = services.iter().map(|service| service.name.chars().count()).max().unwrap_or(0);
+    let services: Vec<Arc<Service>> = services.into_iter().map(|mut service| {
+        let color = PREFIX_COLORS[NEXT_COLOR.fetch_add(1, Ordering::SeqCst) % PREFIX_COLORS.len()];
+        service.prefix = format!("{}[{:<width$}]{}", color, service.name, colors::RESET, width = width);
+        Arc::new(service)
+    }).collect();
+    for service in &services {
+        lock(&SERVICES).push(Arc::clone(service));
+        let service = Arc::clone(service);
+        thread::spawn(move || service.supervise());
+    }
+
+    let services = Arc::new(services);
+    Ok(LuaValue::Table(TableBuilder::create(luau)?
+        .with_function("stop", {
+            let services = Arc::clone(&services);
+            move |_luau: &Lua, _multivalue: LuaMultiValue| -> LuaValueResult {
+                stop_services(&services);
+                Ok(LuaNil)
+            }
+        })?
+        .with_function("wait", {
+            let services = Arc::clone(&services);
+            move |luau: &Lua, multivalue: LuaMultiValue| supervisor_wait(luau, &services, multivalue)
+        })?
+        .with_function("status", move |luau: &Lua, _multivalue: LuaMultiValue| supervisor_status(luau, &services))?
+        .build_readonly()?
+    ))
+}
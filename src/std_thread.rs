@@ -1,110 +1,270 @@
 use std::{fs, time::Duration};
-use std::sync::Mutex;
-use std::thread;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
 
 use regex::Regex;
-use crossbeam_channel::{bounded, Sender, Receiver};
+use crossbeam_channel::{bounded, Sender, Receiver, RecvTimeoutError};
 
-use crate::{table_helpers::TableBuilder, LuaValueResult, colors, globals, std_json};
+use crate::{table_helpers::TableBuilder, LuaValueResult, colors, globals, std_process_signals, std_thread_channel, std_thread_pool, std_thread_serialize, std_thread_sync};
+use crate::std_thread_serialize::ThreadMessage;
 use mlua::prelude::*;
 
-fn thread_sleep(_luau: &Lua, duration: LuaNumber) -> LuaValueResult {
+const DEFAULT_CHANNEL_CAPACITY: usize = 12;
+const DEFAULT_BYTES_CHANNEL_CAPACITY: usize = 24;
+
+/// the code a thread runs, and where it came from
+#[derive(Clone)]
+pub struct ThreadSource {
+    pub src: String,
+    /// the file `src` was read from, or the spawning script's path for inline `src`
+    pub path: String,
+    /// the script that spawned the thread
+    pub called_from: String,
+}
+
+impl ThreadSource {
+    /// reads `path` relative to the calling script's directory; call this straight from the
+    /// function Luau called so we can tell which script that was
+    pub fn from_path(luau: &Lua, path: &str, function_name: &str) -> LuaResult<Self> {
+        let called_from = globals::get_script_path(luau, LuaMultiValue::new())?.to_string()?;
+        let parent = Path::new(&called_from).parent().unwrap_or(Path::new(""));
+        let path = parent.join(path.strip_prefix("./").unwrap_or(path)).to_string_lossy().to_string();
+        match fs::read_to_string(&path) {
+            Ok(src) => Ok(ThreadSource { src, path, called_from }),
+            Err(err) => {
+                wrap_err!("{}: unable to read '{}': {}", function_name, path, err)
+            }
+        }
+    }
+
+    pub fn from_src(luau: &Lua, src: String) -> LuaResult<Self> {
+        let called_from = globals::get_script_path(luau, LuaMultiValue::new())?.to_string()?;
+        Ok(ThreadSource { src, path: called_from.clone(), called_from })
+    }
+
+    /// from the `src` or `path` field of `options`
+    pub fn from_options(luau: &Lua, options: &LuaTable, function_name: &str) -> LuaResult<Self> {
+        if let LuaValue::String(src) = options.raw_get("src")? {
+            Self::from_src(luau, src.to_str()?.to_string())
+        } else if let LuaValue::String(path) = options.raw_get("path")? {
+            Self::from_path(luau, &path.to_str()?, function_name)
+        } else {
+            wrap_err!("{} expected table with fields src or path, got neither", function_name)
+        }
+    }
+
+    /// a fresh Luau state with seal's globals, ready to run `src`
+    pub fn create_luau(&self) -> LuaResult<Lua> {
+        let new_luau = mlua::Lua::new();
+        globals::set_globals(&new_luau)?;
+        new_luau.globals().raw_set("script", TableBuilder::create(&new_luau)?
+            .with_value("entry_path", self.path.to_owned())?
+            .with_value("current_path", self.path.to_owned())?
+            .with_value("thread_parent_path", self.called_from.to_owned())?
+            .with_value("src", self.src.to_owned())?
+            .with_function("path", globals::get_script_path)?
+            .with_function("parent", globals::get_script_parent)?
+            .build()?
+        )?;
+        Ok(new_luau)
+    }
+
+    /// loads `src` into `luau`, named after its path so errors and relative paths point to the right file
+    pub fn load(&self, luau: &Lua) -> LuaChunk<'_> {
+        luau.load(self.src.as_str()).set_name(&self.path)
+    }
+}
+
+/// `err` from a thread's Luau state, with chunk names like `[string "..."]` swapped for the thread script's path
+fn thread_error_text(luau: &Lua, err: &LuaError) -> String {
+    let replace_main_re = Regex::new(r#"\[string \"[^\"]+\"\]"#).unwrap();
+    let current_path: Option<String> = luau.globals().get::<LuaTable>("script")
+        .and_then(|script| script.get("current_path"))
+        .ok();
+    replace_main_re
+        .replace_all(&err.to_string(), format!("[\"{}\"]", current_path.unwrap_or_default()))
+        .replace("_G.error", "error")
+}
+
+/// formats an error from a thread's Luau state like the main script's errors, noting which script spawned it
+pub fn thread_error_message(luau: &Lua, err: &LuaError) -> String {
+    let script: Option<LuaTable> = luau.globals().get("script").ok();
+    let field = |key: &str| -> Option<String> {
+        script.as_ref().and_then(|script| script.get(key).ok())
+    };
+    let thread_parent_path = field("thread_parent_path").unwrap_or_default();
+    let err_message = thread_error_text(luau, err);
+    if let Some(context) = field("context") {
+        let context = format!("{}[CONTEXT] {}{}{}\n", colors::BOLD_RED, context, colors::RESET, colors::RED);
+        context + &err_message + &format!("\n THREAD CALLED FROM: {}", thread_parent_path)
+    } else {
+        err_message + &format!("\n{}THREAD CALLED FROM:{} [\"{}\"]", colors::BOLD_RED, colors::RESET, thread_parent_path)
+    }
+}
+
+/// how a thread's script failed: its error message and the traceback, split apart
+#[derive(Clone)]
+pub struct ThreadFailure {
+    pub message: String,
+    pub traceback: String,
+}
+
+impl ThreadFailure {
+    pub fn from_error(luau: &Lua, err: &LuaError) -> Self {
+        let err_message = thread_error_text(luau, err);
+        match err_message.split_once("stack traceback:") {
+            Some((message, traceback)) => ThreadFailure {
+                message: message.trim_end().to_string(),
+                traceback: traceback.trim().to_string(),
+            },
+            None => ThreadFailure::new(err_message.trim_end()),
+        }
+    }
+
+    /// a failure that didn't come with a traceback, like a script returning the wrong type
+    pub fn new(message: impl Into<String>) -> Self {
+        ThreadFailure { message: message.into(), traceback: String::new() }
+    }
+}
+
+impl std::fmt::Display for ThreadFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.traceback.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}\nstack traceback:\n{}", self.message, self.traceback)
+        }
+    }
+}
+
+/// the serialized value a spawned thread's script returned, or how it failed
+type ThreadOutcome = Result<ThreadMessage, ThreadFailure>;
+
+struct SpawnedThread {
+    handle: Option<JoinHandle<ThreadOutcome>>,
+    outcome: Option<ThreadOutcome>,
+}
+
+impl SpawnedThread {
+    fn is_finished(&self) -> bool {
+        self.handle.as_ref().is_none_or(|handle| handle.is_finished())
+    }
+
+    /// waits for the thread if it's still running; later calls return the same outcome
+    fn join(&mut self) -> &ThreadOutcome {
+        let handle = &mut self.handle;
+        self.outcome.get_or_insert_with(|| {
+            let panicked = || Err(ThreadFailure::new("the thread panicked"));
+            match handle.take() {
+                Some(handle) => handle.join().unwrap_or_else(|_panic| panicked()),
+                None => panicked(),
+            }
+        })
+    }
+}
+
+fn thread_result(luau: &Lua, outcome: &ThreadOutcome) -> LuaValueResult {
+    Ok(LuaValue::Table(match outcome {
+        Ok(value) => {
+            let value = std_thread_serialize::deserialize(luau, value)?;
+            TableBuilder::create(luau)?
+                .with_value("ok", true)?
+                .with_value("value", value.clone())?
+                .with_function("unwrap", move |_luau: &Lua, _multivalue: LuaMultiValue| -> LuaValueResult {
+                    Ok(value.clone())
+                })?
+                .build_readonly()?
+        },
+        Err(failure) => {
+            let message = failure.message.clone();
+            TableBuilder::create(luau)?
+                .with_value("ok", false)?
+                .with_value("err", failure.message.as_str())?
+                .with_value("traceback", failure.traceback.as_str())?
+                .with_function("unwrap", move |_luau: &Lua, mut multivalue: LuaMultiValue| -> LuaValueResult {
+                    let _result = multivalue.pop_front();
+                    match multivalue.pop_front() {
+                        Some(default) => Ok(default),
+                        None => {
+                            wrap_err!("Attempt to ThreadResult:unwrap() a failed thread without a default value! The thread failed with: {}", message)
+                        }
+                    }
+                })?
+                .build_readonly()?
+        },
+    }))
+}
+
+/// `timeout` seconds for the `*_await` functions
+pub fn parse_timeout(timeout: Option<LuaValue>, function_name: &str) -> LuaResult<Option<Duration>> {
+    match timeout {
+        None | Some(LuaNil) => Ok(None),
+        Some(LuaValue::Integer(seconds)) if seconds >= 0 => Ok(Some(Duration::from_secs(seconds as u64))),
+        Some(LuaValue::Number(seconds)) if seconds >= 0.0 => Ok(Some(Duration::from_secs_f64(seconds))),
+        Some(other) => {
+            wrap_err!("{}(timeout: number?) expected timeout to be a non-negative number of seconds or nil, got: {:#?}", function_name, other)
+        }
+    }
+}
+
+pub fn receive<T>(receiver: &Receiver<T>, timeout: Option<Duration>) -> Result<T, RecvTimeoutError> {
+    match timeout {
+        Some(timeout) => receiver.recv_timeout(timeout),
+        None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+    }
+}
+
+/// a channel capacity from ThreadSpawnOptions
+fn parse_capacity(options: &LuaTable, field: &str, default: usize) -> LuaResult<usize> {
+    match options.raw_get(field)? {
+        LuaNil => Ok(default),
+        LuaValue::Integer(capacity) if capacity >= 0 => Ok(capacity as usize),
+        other => {
+            wrap_err!("ThreadSpawnOptions.{} expected to be a non-negative integer or nil, got: {:#?}", field, other)
+        }
+    }
+}
+
+fn thread_sleep(luau: &Lua, duration: LuaNumber) -> LuaValueResult {
     let dur = Duration::from_millis(duration as u64);
-    thread::sleep(dur);
+    std_process_signals::sleep(luau, dur)?;
     Ok(LuaValue::Boolean(true)) // ensure while thread.sleep(n) do end works
 }
 
 fn thread_spawn(luau: &Lua, spawn_options: LuaValue) -> LuaValueResult {
     match spawn_options {
         LuaValue::Table(options) => {
-            let mut thread_src_path = String::from("");
-            let mut thread_called_from_path = String::from("");
-            let spawn_src = {
-                if let LuaValue::String(src) = options.get("src")? {
-                    let src = src.to_str()?.to_string();
-                    Ok(src)
-                } else if let LuaValue::String(path) = options.get("path")? {
-                    let extract_path_re = Regex::new(r"^(.*[/\\])[^/\\]+\.luau$").unwrap();
-                    let script: LuaTable = luau.globals().get("script")?;
-                    let current_path: String = script.get("current_path")?;
-                    thread_called_from_path = current_path.to_owned();
-                    let captures = extract_path_re.captures(&current_path).unwrap();
-                    let new_path = &captures[1];
-
-                    let path = path.to_str()?.to_string();
-                    let path = path.replace("./", "");
-                    let path = format!("{new_path}{path}");
-                    thread_src_path = path.to_owned();
-                    Ok(fs::read_to_string(path).unwrap())
-                } else {
-                    wrap_err!("thread.spawn expected table with fields src or path, got neither")
-                }
-            }?;
-
-            let spawn_data = {
-                if let LuaValue::Table(data) = options.raw_get("data")? {
-                    Some(std_json::json_encode(luau, LuaValue::Table(data).into_lua_multi(luau)?)?)
-                } else {
-                    None
-                }
-            };
-
-            let spawn_data_buffer: Option<Vec<u8>> = {
-                if let LuaValue::Buffer(data) = options.raw_get("data")? {
-                    Some(data.to_vec())
-                } else {
-                    None
-                }
-            };
+            let source = ThreadSource::from_options(luau, &options, "thread.spawn")?;
+
+            let spawn_data = std_thread_serialize::serialize(&options.raw_get("data")?, "thread.spawn")?;
+            let capacity = parse_capacity(&options, "capacity", DEFAULT_CHANNEL_CAPACITY)?;
+            let bytes_capacity = parse_capacity(&options, "bytes_capacity", DEFAULT_BYTES_CHANNEL_CAPACITY)?;
 
             let (parent_to_child_sender, parent_to_child_receiver): 
-                (Sender<String>, Receiver<String>) = bounded(12);
+                (Sender<ThreadMessage>, Receiver<ThreadMessage>) = bounded(capacity);
             let parent_to_child_receiver_readawait_clone = parent_to_child_receiver.clone();
 
             let (parent_to_child_buffer_sender, parent_to_child_buffer_receiver): 
-                (Sender<Vec<u8>>, Receiver<Vec<u8>>) = bounded(24);
+                (Sender<Vec<u8>>, Receiver<Vec<u8>>) = bounded(bytes_capacity);
             let parent_to_child_buffer_receiver_readawait_clone = parent_to_child_buffer_receiver.clone();
 
             let (child_to_parent_sender, child_to_parent_receiver): 
-                (Sender<String>, Receiver<String>) = bounded(12);
+                (Sender<ThreadMessage>, Receiver<ThreadMessage>) = bounded(capacity);
             let child_to_parent_receiver_readawait_clone = child_to_parent_receiver.clone();
 
             let (child_to_parent_buffer_sender, child_to_parent_buffer_receiver): 
-                (Sender<Vec<u8>>, Receiver<Vec<u8>>) = bounded(24);
+                (Sender<Vec<u8>>, Receiver<Vec<u8>>) = bounded(bytes_capacity);
             let child_to_parent_buffer_receiver_readawait_clone = child_to_parent_buffer_receiver.clone();
 
             let handle = thread::spawn(move || {
-                let new_luau = mlua::Lua::new();
-
-                globals::set_globals(&new_luau).unwrap();
-
-                new_luau.globals().raw_set("script",
-                    TableBuilder::create(&new_luau).unwrap()
-                        .with_value("current_path", thread_src_path).unwrap()
-                        .with_value("thread_parent_path", thread_called_from_path).unwrap()
-                        .with_value("src", spawn_src.to_owned()).unwrap()
-                        .build().unwrap()
-                ).unwrap();
+                let new_luau = source.create_luau().unwrap();
 
                 new_luau.globals().raw_set("channel",
                     TableBuilder::create(&new_luau).unwrap()
                         .with_function("read", move |new_luau: &Lua, _multivalue: LuaMultiValue| -> LuaValueResult {
                             match parent_to_child_receiver.try_recv() {
-                                Ok(data) => {
-                                    if data.starts_with("{") {
-                                        // it's some json we have to decode and return
-                                        let json_result = match std_json::json_decode(new_luau, data) {
-                                            Ok(value) => value,
-                                            Err(err) => {
-                                                return wrap_err!("channel:read(): error decoding json: {}", err);
-                                            }
-                                        };
-                                        Ok(json_result)
-                                        // let json_result = std_json::json_decode(new_luau, data)?;
-                                    } else {
-                                        data.into_lua(new_luau)
-                                    }
-                                },
+                                Ok(data) => std_thread_serialize::deserialize(new_luau, &data),
                                 Err(_) => Ok(LuaNil)
                             }
                         }).unwrap()
@@ -118,8 +278,10 @@ fn thread_spawn(luau: &Lua, spawn_options: LuaValue) -> LuaValueResult {
                                 Err(_) => Ok(LuaNil)
                             }
                         }).unwrap()
-                        .with_function("readbytes_await", move |luau: &Lua, _multivalue: LuaMultiValue| -> LuaValueResult {
-                            match parent_to_child_buffer_receiver_readawait_clone.recv() {
+                        .with_function("readbytes_await", move |luau: &Lua, mut multivalue: LuaMultiValue| -> LuaValueResult {
+                            let _self = multivalue.pop_front();
+                            let timeout = parse_timeout(multivalue.pop_front(), "channel:readbytes_await")?;
+                            match receive(&parent_to_child_buffer_receiver_readawait_clone, timeout) {
                                 Ok(data) => {
                                     Ok(LuaValue::Buffer(
                                         luau.create_buffer(data).unwrap()
@@ -128,23 +290,14 @@ fn thread_spawn(luau: &Lua, spawn_options: LuaValue) -> LuaValueResult {
                                 Err(_) => Ok(LuaNil)
                             }
                         }).unwrap()
-                        .with_function("read_await", move |luau: &Lua, _multivalue: LuaMultiValue| -> LuaValueResult {
-                            match parent_to_child_receiver_readawait_clone.recv() {
-                                Ok(data) => {
-                                    if data.starts_with("{") {
-                                        let json_result = match std_json::json_decode(luau, data) {
-                                            Ok(value) => value,
-                                            Err(err) => {
-                                                return wrap_err!("channel:read_await(): error decoding json: {}", err);
-                                            }
-                                        };
-                                        Ok(json_result)
-                                    } else {
-                                        data.into_lua(luau)
-                                    }
-                                },
-                                Err(err) => {
-                                    wrap_err!("channel:read_await: RecvError: {}", err)
+                        .with_function("read_await", move |luau: &Lua, mut multivalue: LuaMultiValue| -> LuaValueResult {
+                            let _self = multivalue.pop_front();
+                            let timeout = parse_timeout(multivalue.pop_front(), "channel:read_await")?;
+                            match receive(&parent_to_child_receiver_readawait_clone, timeout) {
+                                Ok(data) => std_thread_serialize::deserialize(luau, &data),
+                                Err(RecvTimeoutError::Timeout) => Ok(LuaNil),
+                                Err(RecvTimeoutError::Disconnected) => {
+                                    wrap_err!("channel:read_await: the parent thread dropped its handle, so nothing else will be sent")
                                 }
                             }
                         }).unwrap()
@@ -177,23 +330,12 @@ fn thread_spawn(luau: &Lua, spawn_options: LuaValue) -> LuaValueResult {
                                 }
                             }
                         }).unwrap()
-                        .with_function("send", move |new_luau: &Lua, mut multivalue: LuaMultiValue| -> LuaValueResult {
-                            let send_data = match multivalue.pop_back() {
-                                Some(data) => {
-                                    match data {
-                                        LuaValue::Table(data) => {
-                                            std_json::json_encode(new_luau, LuaValue::Table(data).into_lua_multi(new_luau)?)?
-                                        },
-                                        LuaValue::String(data) => {
-                                            data.to_str()?.to_string()
-                                        },
-                                        other => {
-                                            return wrap_err!("channel:send() (in thread) expected string or json-serializable data, got: {:?}", other);
-                                        }
-                                    }
-                                },
+                        .with_function("send", move |_luau: &Lua, mut multivalue: LuaMultiValue| -> LuaValueResult {
+                            let _channel = multivalue.pop_front();
+                            let send_data = match multivalue.pop_front() {
+                                Some(data) => std_thread_serialize::serialize(&data, "channel:send")?,
                                 None => {
-                                    return wrap_err!("channel:send() (in thread) expected some json-serializable data, got nothing");
+                                    return wrap_err!("channel:send(data) (in thread) expected some data to send, got nothing");
                                 }
                             };
                             match child_to_parent_sender.send(send_data) {
@@ -203,74 +345,30 @@ fn thread_spawn(luau: &Lua, spawn_options: LuaValue) -> LuaValueResult {
                                 }
                             }
                         }).unwrap()
-                        .with_value("data", {
-                            if let Some(data) = spawn_data {
-                                std_json::json_decode(&new_luau, data).unwrap()
-                            } else if let Some(buffy_data) = spawn_data_buffer {
-                                LuaValue::Buffer(new_luau.create_buffer(buffy_data).unwrap())
-                            } else {
-                                LuaNil
-                            }
-                        }).unwrap()
+                        .with_value("data", std_thread_serialize::deserialize(&new_luau, &spawn_data).unwrap()).unwrap()
                         .build().unwrap()
                 ).unwrap();
 
-                match new_luau.load(spawn_src).exec() {
-                    Ok(_) => {},
-                    Err(err) => {
-                        let replace_main_re = Regex::new(r#"\[string \"[^\"]+\"\]"#).unwrap();
-                        let globals = new_luau.globals();
-                        let script: LuaTable = globals.get("script").unwrap();
-                        let current_path: String = script.get("current_path").unwrap();
-                        let thread_parent_path: String = script.get("thread_parent_path").unwrap();
-                        let err_context: Option<String> = script.get("context").unwrap();
-                        let err_message = {
-                            let err_message = replace_main_re
-                                .replace_all(&err.to_string(), format!("[\"{}\"]", current_path))
-                                .replace("_G.error", "error")
-                                .to_string();
-                            if let Some(context) = err_context {
-                                let context = format!("{}[CONTEXT] {}{}{}\n", colors::BOLD_RED, context, colors::RESET, colors::RED);
-                                context + &err_message + &format!("\n THREAD CALLED FROM: {}", thread_parent_path)
-                            } else {
-                                err_message + &format!("\n{}THREAD CALLED FROM:{} [\"{}\"]", colors::BOLD_RED, colors::RESET, thread_parent_path)
-                            }
-                        };
-                        panic!("{}", err_message);
-                    }
-                }
+                let result = source.load(&new_luau).eval::<LuaValue>()
+                    .and_then(|value| std_thread_serialize::serialize(&value, "thread.spawn: the thread's return value"));
+                result.map_err(|err| {
+                    // report it right away like the main script's errors, since nobody might ever join this thread
+                    eprintln!("{}[ERR]{}{} {}{}", colors::BOLD_RED, colors::RESET, colors::RED, thread_error_message(&new_luau, &err), colors::RESET);
+                    ThreadFailure::from_error(&new_luau, &err)
+                })
             });
-            // hmm wonder why this works, got it off copilot but yay fearful concurrency :p 
-            // let arc_handle = Arc::new(Mutex::new(Some(handle)));
-            let arc_handle = Mutex::new(Some(handle));
+            let spawned = Arc::new(Mutex::new(SpawnedThread { handle: Some(handle), outcome: None }));
 
             Ok(LuaValue::Table(
                 TableBuilder::create(luau)?
-                    .with_function("send", move |luau: &Lua, mut multivalue: LuaMultiValue| -> LuaValueResult {
-                        let value = match multivalue.pop_back() {
-                            Some(value) => value,
+                    .with_function("send", move |_luau: &Lua, mut multivalue: LuaMultiValue| -> LuaValueResult {
+                        let _handle = multivalue.pop_front();
+                        let send_data = match multivalue.pop_front() {
+                            Some(value) => std_thread_serialize::serialize(&value, "thread.send")?,
                             None => {
                                 return wrap_err!("thread.send expected value, got nothing");
                             }
                         };
-                        let send_data: String = {
-                            match value {
-                                LuaValue::String(s) => {
-                                    s.to_str()?.to_string()
-                                },
-                                LuaValue::Table(data) => {
-                                    match std_json::json_encode(luau, LuaValue::Table(data).into_lua_multi(luau)?) {
-                                        Ok(json) => json,
-                                        Err(err) => {
-                                            return wrap_err!("thread.send unable to encode data to json: {}", err)
-                                        }
-                                    }
-                                },
-                                other => {
-                                    return wrap_err!("thread.send expected string or table (to stringify as json), got {:?}", other);
-                                }
-                            }
-                        };
                         match parent_to_child_sender.send(send_data) {
                             Ok(()) => {},
                             Err(err) => {
@@ -310,40 +408,18 @@ fn thread_spawn(luau: &Lua, spawn_options: LuaValue) -> LuaValueResult {
                     })?
                     .with_function("read", move |luau: &Lua, _multivalue: LuaMultiValue| -> LuaValueResult {
                         match child_to_parent_receiver.try_recv() {
-                            Ok(data) => {
-                                if data.starts_with("{") {
-                                    // it's some json we have to decode and return
-                                    let json_result = match std_json::json_decode(luau, data) {
-                                        Ok(value) => value,
-                                        Err(err) => {
-                                            return wrap_err!("channel:read(): error decoding json: {}", err);
-                                        }
-                                    };
-                                    Ok(json_result)
-                                } else {
-                                    data.into_lua(luau)
-                                }
-                            },
+                            Ok(data) => std_thread_serialize::deserialize(luau, &data),
                             Err(_) => Ok(LuaNil)
                         }
                     })?
-                    .with_function("read_await", move |luau: &Lua, _multivalue: LuaMultiValue| -> LuaValueResult {
-                        match child_to_parent_receiver_readawait_clone.recv() {
-                            Ok(data) => {
-                                if data.starts_with("{") {
-                                    let json_result = match std_json::json_decode(luau, data) {
-                                        Ok(value) => value,
-                                        Err(err) => {
-                                            return wrap_err!("channel:read_await(): error decoding json: {}", err);
-                                        }
-                                    };
-                                    Ok(json_result)
-                                } else {
-                                    data.into_lua(luau)
-                                }
-                            },
-                            Err(err) => {
-                                wrap_err!("channel:read_await: RecvError: {}", err)
+                    .with_function("read_await", move |luau: &Lua, mut multivalue: LuaMultiValue| -> LuaValueResult {
+                        let _self = multivalue.pop_front();
+                        let timeout = parse_timeout(multivalue.pop_front(), "ThreadHandle:read_await")?;
+                        match receive(&child_to_parent_receiver_readawait_clone, timeout) {
+                            Ok(data) => std_thread_serialize::deserialize(luau, &data),
+                            Err(RecvTimeoutError::Timeout) => Ok(LuaNil),
+                            Err(RecvTimeoutError::Disconnected) => {
+                                wrap_err!("ThreadHandle:read_await: the thread has finished, so nothing else will be sent; use ThreadHandle:join to see how it went")
                             }
                         }
                     })?
@@ -357,8 +433,10 @@ fn thread_spawn(luau: &Lua, spawn_options: LuaValue) -> LuaValueResult {
                             Err(_) => Ok(LuaNil)
                         }
                     })?
-                    .with_function("readbytes_await", move |luau: &Lua, _multivalue: LuaMultiValue| -> LuaValueResult {
-                        match child_to_parent_buffer_receiver_readawait_clone.recv() {
+                    .with_function("readbytes_await", move |luau: &Lua, mut multivalue: LuaMultiValue| -> LuaValueResult {
+                        let _self = multivalue.pop_front();
+                        let timeout = parse_timeout(multivalue.pop_front(), "ThreadHandle:readbytes_await")?;
+                        match receive(&child_to_parent_buffer_receiver_readawait_clone, timeout) {
                             Ok(data) => {
                                 Ok(LuaValue::Buffer(
                                     luau.create_buffer(data)?
@@ -367,19 +445,14 @@ fn thread_spawn(luau: &Lua, spawn_options: LuaValue) -> LuaValueResult {
                             Err(_) => Ok(LuaNil)
                         }
                     })?
-                    .with_function("join", move |_luau: &Lua, _value: LuaValue| -> LuaValueResult {
-                        let mut handle = arc_handle.lock().unwrap();
-                        if let Some(handle) = handle.take() {
-                            match handle.join() {
-                                Ok(_) => {
-                                    return Ok(LuaNil);
-                                },
-                                Err(_) => {
-                                    return wrap_err!("error in called thread.spawn");
-                                }
-                            }
+                    .with_function("is_finished", {
+                        let spawned = Arc::clone(&spawned);
+                        move |_luau: &Lua, _value: LuaValue| -> LuaResult<bool> {
+                            Ok(spawned.lock().unwrap().is_finished())
                         }
-                        Ok(LuaNil)
+                    })?
+                    .with_function("join", move |luau: &Lua, _value: LuaValue| -> LuaValueResult {
+                        thread_result(luau, spawned.lock().unwrap().join())
                     })?
                     .build_readonly()?
             ))
@@ -393,6 +466,15 @@ fn thread_spawn(luau: &Lua, spawn_options: LuaValue) -> LuaValueResult {
 pub fn create(luau: &Lua) -> LuaResult<LuaTable> {
     TableBuilder::create(luau)?
         .with_function("spawn", thread_spawn)?
+        .with_function("pool", std_thread_pool::thread_pool)?
+        .with_function("parallel_map", std_thread_pool::thread_parallel_map)?
+        .with_function("channel", std_thread_channel::thread_channel)?
+        .with_function("select", std_thread_channel::thread_select)?
+        .with_function("atomic", std_thread_sync::thread_atomic)?
+        .with_function("shared_table", std_thread_sync::thread_shared_table)?
+        .with_function("once", std_thread_sync::thread_once)?
+        .with_function("barrier", std_thread_sync::thread_barrier)?
+        .with_function("semaphore", std_thread_sync::thread_semaphore)?
         .with_function("sleep", thread_sleep)?
         .build_readonly()
 }
\ No newline at end of file
@@ -0,0 +1,154 @@
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crossbeam_channel::{bounded, unbounded, Receiver, RecvTimeoutError, Select, Sender};
+use mlua::prelude::*;
+
+use crate::{colors, LuaValueResult, std_thread_serialize};
+use crate::std_thread::{parse_timeout, receive};
+use crate::std_thread_serialize::ThreadMessage;
+
+/// a channel any number of threads can send to and read from; sending it to another thread
+/// (in spawn data or a message) shares the same channel rather than copying it
+#[derive(Clone)]
+pub struct ThreadChannel {
+    /// every copy of the channel shares this one sender, so closing it from anywhere disconnects the channel
+    sender: Arc<Mutex<Option<Sender<ThreadMessage>>>>,
+    receiver: Receiver<ThreadMessage>,
+    capacity: Option<usize>,
+}
+
+impl ThreadChannel {
+    /// a copy of the sender so a blocking send doesn't stop other threads from closing the channel
+    fn sender(&self) -> Option<Sender<ThreadMessage>> {
+        self.sender.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    fn close(&self) {
+        self.sender.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+    }
+}
+
+impl LuaUserData for ThreadChannel {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("capacity", |_luau, this| Ok(this.capacity));
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("send", |_luau, this, data: LuaValue| -> LuaValueResult {
+            let message = std_thread_serialize::serialize(&data, "ThreadChannel:send")?;
+            let Some(sender) = this.sender() else {
+                return wrap_err!("ThreadChannel:send: can't send to a closed channel");
+            };
+            match sender.send(message) {
+                Ok(()) => Ok(LuaNil),
+                Err(err) => {
+                    wrap_err!("ThreadChannel:send: unable to send data: {}", err)
+                }
+            }
+        });
+        // whatever's already been sent can still be read; after that, reads return nil right away
+        methods.add_method("close", |_luau, this, ()| {
+            this.close();
+            Ok(())
+        });
+        methods.add_method("is_closed", |_luau, this, ()| Ok(this.sender().is_none()));
+        methods.add_method("read", |luau, this, ()| -> LuaValueResult {
+            match this.receiver.try_recv() {
+                Ok(message) => std_thread_serialize::deserialize(luau, &message),
+                Err(_) => Ok(LuaNil),
+            }
+        });
+        methods.add_method("read_await", |luau, this, timeout: Option<LuaValue>| -> LuaValueResult {
+            let timeout = parse_timeout(timeout, "ThreadChannel:read_await")?;
+            match receive(&this.receiver, timeout) {
+                Ok(message) => std_thread_serialize::deserialize(luau, &message),
+                // disconnected means it's been closed and there's nothing left to read
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => Ok(LuaNil),
+            }
+        });
+        methods.add_method("len", |_luau, this, ()| Ok(this.receiver.len()));
+        methods.add_meta_method(LuaMetaMethod::ToString, |_luau, this, ()| {
+            let closed = if this.sender().is_none() { ", closed" } else { "" };
+            Ok(match this.capacity {
+                Some(capacity) => format!("ThreadChannel(capacity: {}{})", capacity, closed),
+                None => format!("ThreadChannel(unbounded{})", closed),
+            })
+        });
+    }
+}
+
+/// `thread.channel(capacity: number?)`
+pub fn thread_channel(luau: &Lua, capacity: Option<LuaValue>) -> LuaValueResult {
+    let capacity = match capacity {
+        None | Some(LuaNil) => None,
+        Some(LuaValue::Integer(capacity)) if capacity >= 0 => Some(capacity as usize),
+        Some(other) => {
+            return wrap_err!("thread.channel(capacity: number?) expected capacity to be a non-negative integer or nil (for an unbounded channel), got: {:#?}", other);
+        }
+    };
+    let (sender, receiver) = match capacity {
+        Some(capacity) => bounded(capacity),
+        None => unbounded(),
+    };
+    Ok(LuaValue::UserData(luau.create_userdata(ThreadChannel { sender: Arc::new(Mutex::new(Some(sender))), receiver, capacity })?))
+}
+
+/// `thread.select(channels: { ThreadChannel }, timeout: number?)`: waits for a message on any of `channels`,
+/// returning the channel it came in on and the message, or nothing if `timeout` passes first or every channel's
+/// been closed and drained
+pub fn thread_select(luau: &Lua, (channels, timeout): (LuaValue, Option<LuaValue>)) -> LuaResult<LuaMultiValue> {
+    let channels = match channels {
+        LuaValue::Table(channels) => channels,
+        other => {
+            return wrap_err!("thread.select(channels: {{ ThreadChannel }}, timeout: number?) expected channels to be an array of ThreadChannels, got: {:#?}", other);
+        }
+    };
+    let timeout = parse_timeout(timeout, "thread.select")?;
+
+    let mut userdatas = Vec::new();
+    let mut receivers = Vec::new();
+    for channel in channels.sequence_values::<LuaValue>() {
+        match channel? {
+            LuaValue::UserData(userdata) if userdata.is::<ThreadChannel>() => {
+                receivers.push(userdata.borrow::<ThreadChannel>()?.receiver.clone());
+                userdatas.push(userdata);
+            },
+            other => {
+                return wrap_err!("thread.select: expected every channel to be a ThreadChannel (from thread.channel), got: {:#?}", other);
+            }
+        }
+    }
+    if receivers.is_empty() {
+        return wrap_err!("thread.select expected at least one channel to wait on, got an empty table");
+    }
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut select = Select::new();
+    for receiver in &receivers {
+        select.recv(receiver);
+    }
+    let mut open = receivers.len();
+    while open > 0 {
+        let operation = match deadline {
+            Some(deadline) => match select.select_deadline(deadline) {
+                Ok(operation) => operation,
+                Err(_) => break,
+            },
+            None => select.select(),
+        };
+        let index = operation.index();
+        match operation.recv(&receivers[index]) {
+            Ok(message) => {
+                let data = std_thread_serialize::deserialize(luau, &message)?;
+                return Ok(LuaMultiValue::from_vec(vec![LuaValue::UserData(userdatas[index].clone()), data]));
+            },
+            // closed and drained, so it'd be picked every time from now on
+            Err(_) => {
+                select.remove(index);
+                open -= 1;
+            }
+        }
+    }
+    Ok(LuaMultiValue::new())
+}
@@ -0,0 +1,253 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
+use mlua::prelude::*;
+
+use crate::{colors, table_helpers::TableBuilder, LuaValueResult, std_thread_serialize};
+use crate::std_thread::{ThreadFailure, ThreadSource};
+use crate::std_thread_serialize::ThreadMessage;
+
+/// a serialized return value, or how the worker failed
+type JobResult = Result<ThreadMessage, ThreadFailure>;
+
+struct Job {
+    data: ThreadMessage,
+    result: Sender<JobResult>,
+}
+
+fn default_workers() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(4)
+}
+
+/// each worker loads the pool's script into its own Luau state once, then calls the function it
+/// returns with every job it picks up, until the pool's closed or cancelled
+fn run_worker(source: ThreadSource, jobs: Receiver<Job>, cancelled: Arc<AtomicBool>) {
+    let handler: Result<(Lua, LuaFunction), ThreadFailure> = (|| {
+        let luau = match source.create_luau() {
+            Ok(luau) => luau,
+            Err(err) => return Err(ThreadFailure::new(err.to_string())),
+        };
+        match source.load(&luau).eval::<LuaValue>() {
+            Ok(LuaValue::Function(handler)) => Ok((luau, handler)),
+            Ok(other) => Err(ThreadFailure::new(format!(
+                "thread.pool: worker script '{}' should return a function to call with each job's data, got: {}",
+                source.path, other.type_name()
+            ))),
+            Err(err) => Err(ThreadFailure::from_error(&luau, &err)),
+        }
+    })();
+
+    for job in jobs.iter() {
+        if cancelled.load(Ordering::Relaxed) {
+            break;
+        }
+        let result = match &handler {
+            Ok((luau, handler)) => run_job(luau, handler, &job.data),
+            Err(err) => Err(err.clone()),
+        };
+        // nobody's waiting on this future anymore
+        let _ = job.result.send(result);
+    }
+}
+
+fn run_job(luau: &Lua, handler: &LuaFunction, data: &ThreadMessage) -> JobResult {
+    let data = std_thread_serialize::deserialize(luau, data).map_err(|err| ThreadFailure::from_error(luau, &err))?;
+    handler.call::<LuaValue>(data)
+        .and_then(|result| std_thread_serialize::serialize(&result, "thread.pool: worker result"))
+        .map_err(|err| ThreadFailure::from_error(luau, &err))
+}
+
+struct Pool {
+    jobs: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Pool {
+    fn start(source: ThreadSource, size: usize) -> Self {
+        let (jobs, receiver) = unbounded::<Job>();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let workers = (0..size).map(|_| {
+            let source = source.clone();
+            let receiver = receiver.clone();
+            let cancelled = Arc::clone(&cancelled);
+            thread::spawn(move || run_worker(source, receiver, cancelled))
+        }).collect();
+        Pool { jobs: Some(jobs), workers, cancelled }
+    }
+
+    fn submit(&self, data: ThreadMessage) -> Option<Receiver<JobResult>> {
+        let (result, receiver) = bounded(1);
+        self.jobs.as_ref()?.send(Job { data, result }).ok()?;
+        Some(receiver)
+    }
+
+    /// lets the workers finish what's queued, then waits for them to exit
+    fn close(&mut self) {
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+
+    /// stops the workers from picking up anything else in the queue, without waiting on the jobs they're
+    /// already running; the queued jobs get dropped along with the channel once the workers exit
+    fn cancel(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        self.jobs = None;
+        self.workers.clear();
+    }
+}
+
+/// waits for a job's result, or an error if its worker died without sending one
+fn await_result(receiver: &Receiver<JobResult>) -> JobResult {
+    match receiver.recv() {
+        Ok(result) => result,
+        Err(_) => Err(ThreadFailure::new("the worker running this job stopped without returning a result")),
+    }
+}
+
+fn future_table(luau: &Lua, receiver: Receiver<JobResult>) -> LuaValueResult {
+    let received: Rc<RefCell<Option<JobResult>>> = Rc::new(RefCell::new(None));
+    Ok(LuaValue::Table(TableBuilder::create(luau)?
+        .with_function("await", {
+            let received = Rc::clone(&received);
+            let receiver = receiver.clone();
+            move |luau: &Lua, _multivalue: LuaMultiValue| -> LuaValueResult {
+                let mut received = received.borrow_mut();
+                match received.get_or_insert_with(|| await_result(&receiver)) {
+                    Ok(result) => std_thread_serialize::deserialize(luau, result),
+                    Err(failure) => {
+                        wrap_err!("ThreadFuture:await: job failed: {}", failure)
+                    }
+                }
+            }
+        })?
+        .with_function("is_ready", move |_luau: &Lua, _multivalue: LuaMultiValue| -> LuaResult<bool> {
+            let mut received = received.borrow_mut();
+            if received.is_none() {
+                if let Ok(result) = receiver.try_recv() {
+                    *received = Some(result);
+                }
+            }
+            Ok(received.is_some())
+        })?
+        .build_readonly()?
+    ))
+}
+
+/// `thread.pool(options: { size: number?, src: string?, path: string? })`
+pub fn thread_pool(luau: &Lua, options: LuaValue) -> LuaValueResult {
+    let options = match options {
+        LuaValue::Table(options) => options,
+        other => {
+            return wrap_err!("thread.pool(options: ThreadPoolOptions) expected options to be a table with src or path, got: {:#?}", other);
+        }
+    };
+    let source = ThreadSource::from_options(luau, &options, "thread.pool")?;
+    let size = match options.raw_get("size")? {
+        LuaNil => default_workers(),
+        LuaValue::Integer(size) if size > 0 => size as usize,
+        other => {
+            return wrap_err!("ThreadPoolOptions.size expected to be a positive integer or nil, got: {:#?}", other);
+        }
+    };
+
+    let pool = Rc::new(RefCell::new(Pool::start(source, size)));
+    Ok(LuaValue::Table(TableBuilder::create(luau)?
+        .with_value("size", size)?
+        .with_function("submit", {
+            let pool = Rc::clone(&pool);
+            move |luau: &Lua, mut multivalue: LuaMultiValue| -> LuaValueResult {
+                let _pool = multivalue.pop_front();
+                let data = std_thread_serialize::serialize(&multivalue.pop_front().unwrap_or(LuaNil), "ThreadPool:submit")?;
+                match pool.borrow().submit(data) {
+                    Some(receiver) => future_table(luau, receiver),
+                    None => {
+                        wrap_err!("ThreadPool:submit: can't submit jobs to a closed pool")
+                    }
+                }
+            }
+        })?
+        .with_function("close", move |_luau: &Lua, _multivalue: LuaMultiValue| -> LuaValueResult {
+            pool.borrow_mut().close();
+            Ok(LuaNil)
+        })?
+        .build_readonly()?
+    ))
+}
+
+/// `thread.parallel_map(items: { any }, path_or_src: string, options: { workers: number? }?)`
+pub fn thread_parallel_map(luau: &Lua, (items, path_or_src, options): (LuaValue, LuaValue, Option<LuaValue>)) -> LuaValueResult {
+    let items = match items {
+        LuaValue::Table(items) => items,
+        other => {
+            return wrap_err!("thread.parallel_map(items: {{ any }}, path_or_src, options?) expected items to be an array, got: {:#?}", other);
+        }
+    };
+    let source = match path_or_src {
+        // a single line ending in .luau is a path to a script; anything else is the script itself
+        LuaValue::String(path_or_src) => {
+            let path_or_src = path_or_src.to_str()?.to_string();
+            if path_or_src.ends_with(".luau") && !path_or_src.contains('\n') {
+                ThreadSource::from_path(luau, &path_or_src, "thread.parallel_map")?
+            } else {
+                ThreadSource::from_src(luau, path_or_src)?
+            }
+        },
+        other => {
+            return wrap_err!("thread.parallel_map(items, path_or_src: string, options?) expected path_or_src to be a path to a .luau file or Luau source code, got: {:#?}", other);
+        }
+    };
+    let workers = match options {
+        None | Some(LuaNil) => None,
+        Some(LuaValue::Table(options)) => match options.raw_get("workers")? {
+            LuaNil => None,
+            LuaValue::Integer(workers) if workers > 0 => Some(workers as usize),
+            other => {
+                return wrap_err!("ParallelMapOptions.workers expected to be a positive integer or nil, got: {:#?}", other);
+            }
+        },
+        Some(other) => {
+            return wrap_err!("thread.parallel_map(items, path_or_src, options: ParallelMapOptions?) expected options to be a table or nil, got: {:#?}", other);
+        }
+    };
+
+    let len = items.raw_len();
+    if len == 0 {
+        return Ok(LuaValue::Table(luau.create_table()?));
+    }
+    let workers = workers.unwrap_or_else(default_workers).min(len);
+    let mut pool = Pool::start(source, workers);
+
+    let mut pending = Vec::with_capacity(len);
+    for index in 1..=len {
+        let item: LuaValue = items.raw_get(index)?;
+        let data = std_thread_serialize::serialize(&item, "thread.parallel_map")?;
+        match pool.submit(data) {
+            Some(receiver) => pending.push(receiver),
+            None => {
+                return wrap_err!("thread.parallel_map: workers stopped before every item was submitted");
+            }
+        }
+    }
+
+    // results keep their items' order, whichever worker finishes first
+    let results = luau.create_table_with_capacity(len, 0)?;
+    for (index, receiver) in pending.iter().enumerate() {
+        match await_result(receiver) {
+            Ok(result) => results.raw_set(index + 1, std_thread_serialize::deserialize(luau, &result)?)?,
+            Err(failure) => {
+                // the other items' results don't matter anymore, so skip whatever's still queued
+                pool.cancel();
+                return wrap_err!("thread.parallel_map: item {} failed: {}", index + 1, failure);
+            }
+        }
+    }
+    pool.close();
+    Ok(LuaValue::Table(results))
+}
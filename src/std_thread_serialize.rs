@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::ffi::c_void;
+
+use mlua::prelude::*;
+use crate::{colors, LuaValueResult};
+use crate::std_thread_channel::ThreadChannel;
+use crate::std_thread_sync::{SharedTable, ThreadAtomic, ThreadBarrier, ThreadOnce, ThreadSemaphore};
+
+// each value starts with one of these tags
+const TAG_NIL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_INTEGER: u8 = 3;
+const TAG_NUMBER: u8 = 4;
+const TAG_VECTOR: u8 = 5;
+const TAG_STRING: u8 = 6;
+const TAG_BUFFER: u8 = 7;
+const TAG_TABLE: u8 = 8;
+/// a table we've already written, by the order it was first seen in; keeps cycles and shared tables intact
+const TAG_TABLE_REF: u8 = 9;
+/// a channel or other object threads share instead of copying, by its index in `ThreadMessage.shared`
+const TAG_SHARED: u8 = 10;
+
+/// tables nested deeper than this are almost certainly a mistake, and would overflow the stack when decoding
+const MAX_DEPTH: usize = 256;
+
+/// the userdata threads share between them rather than copying
+#[derive(Clone)]
+enum SharedValue {
+    Channel(ThreadChannel),
+    Atomic(ThreadAtomic),
+    Table(SharedTable),
+    Once(ThreadOnce),
+    Barrier(ThreadBarrier),
+    Semaphore(ThreadSemaphore),
+}
+
+impl SharedValue {
+    fn from_userdata(userdata: &LuaAnyUserData) -> LuaResult<Option<Self>> {
+        Ok(Some(if let Ok(channel) = userdata.borrow::<ThreadChannel>() {
+            SharedValue::Channel(channel.clone())
+        } else if let Ok(atomic) = userdata.borrow::<ThreadAtomic>() {
+            SharedValue::Atomic(atomic.clone())
+        } else if let Ok(table) = userdata.borrow::<SharedTable>() {
+            SharedValue::Table(table.clone())
+        } else if let Ok(once) = userdata.borrow::<ThreadOnce>() {
+            SharedValue::Once(once.clone())
+        } else if let Ok(barrier) = userdata.borrow::<ThreadBarrier>() {
+            SharedValue::Barrier(barrier.clone())
+        } else if let Ok(semaphore) = userdata.borrow::<ThreadSemaphore>() {
+            SharedValue::Semaphore(semaphore.clone())
+        } else {
+            return Ok(None);
+        }))
+    }
+
+    fn to_userdata(&self, luau: &Lua) -> LuaResult<LuaAnyUserData> {
+        match self {
+            SharedValue::Channel(channel) => luau.create_userdata(channel.clone()),
+            SharedValue::Atomic(atomic) => luau.create_userdata(atomic.clone()),
+            SharedValue::Table(table) => luau.create_userdata(table.clone()),
+            SharedValue::Once(once) => luau.create_userdata(once.clone()),
+            SharedValue::Barrier(barrier) => luau.create_userdata(barrier.clone()),
+            SharedValue::Semaphore(semaphore) => luau.create_userdata(semaphore.clone()),
+        }
+    }
+}
+
+/// a value serialized to send to another thread; shared userdata can't be turned into bytes, so it travels alongside them
+pub struct ThreadMessage {
+    bytes: Vec<u8>,
+    shared: Vec<SharedValue>,
+}
+
+struct Serializer<'a> {
+    /// prefixes error messages, like "thread.send"
+    function_name: &'a str,
+    bytes: Vec<u8>,
+    shared: Vec<SharedValue>,
+    seen_tables: HashMap<*const c_void, u32>,
+}
+
+impl Serializer<'_> {
+    fn write_len(&mut self, len: usize) {
+        self.bytes.extend_from_slice(&(len as u32).to_le_bytes());
+    }
+
+    fn write_bytes(&mut self, tag: u8, bytes: &[u8]) {
+        self.bytes.push(tag);
+        self.write_len(bytes.len());
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn write_value(&mut self, value: &LuaValue, path: &str, depth: usize) -> LuaResult<()> {
+        match value {
+            LuaNil => self.bytes.push(TAG_NIL),
+            LuaValue::Boolean(false) => self.bytes.push(TAG_FALSE),
+            LuaValue::Boolean(true) => self.bytes.push(TAG_TRUE),
+            LuaValue::Integer(integer) => {
+                self.bytes.push(TAG_INTEGER);
+                self.bytes.extend_from_slice(&integer.to_le_bytes());
+            },
+            LuaValue::Number(number) => {
+                self.bytes.push(TAG_NUMBER);
+                self.bytes.extend_from_slice(&number.to_le_bytes());
+            },
+            LuaValue::Vector(vector) => {
+                self.bytes.push(TAG_VECTOR);
+                for component in [vector.x(), vector.y(), vector.z()] {
+                    self.bytes.extend_from_slice(&component.to_le_bytes());
+                }
+            },
+            LuaValue::String(string) => self.write_bytes(TAG_STRING, &string.as_bytes()),
+            LuaValue::Buffer(buffer) => self.write_bytes(TAG_BUFFER, &buffer.to_vec()),
+            LuaValue::Table(table) => self.write_table(table, path, depth)?,
+            LuaValue::UserData(userdata) => match SharedValue::from_userdata(userdata)? {
+                Some(shared) => {
+                    self.bytes.push(TAG_SHARED);
+                    self.bytes.extend_from_slice(&(self.shared.len() as u32).to_le_bytes());
+                    self.shared.push(shared);
+                },
+                None => {
+                    return wrap_err!("{}: can't send userdata between threads (found at {}) unless it's from @std/thread (like a channel or atomic)", self.function_name, path);
+                }
+            },
+            other => {
+                return wrap_err!(
+                    "{}: can't send {} values between threads (found at {}); only nil, booleans, numbers, vectors, strings, buffers, @std/thread objects (like channels), and tables of them can be sent",
+                    self.function_name, other.type_name(), path
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn write_table(&mut self, table: &LuaTable, path: &str, depth: usize) -> LuaResult<()> {
+        if let Some(index) = self.seen_tables.get(&table.to_pointer()) {
+            self.bytes.push(TAG_TABLE_REF);
+            self.bytes.extend_from_slice(&index.to_le_bytes());
+            return Ok(());
+        }
+        if depth >= MAX_DEPTH {
+            return wrap_err!("{}: can't send tables nested more than {} levels deep (at {})", self.function_name, MAX_DEPTH, path);
+        }
+        let index = self.seen_tables.len() as u32;
+        self.seen_tables.insert(table.to_pointer(), index);
+
+        // the array part goes first so it's rebuilt in order on the other side
+        let array_len = table.raw_len();
+        self.bytes.push(TAG_TABLE);
+        self.write_len(array_len);
+        for index in 1..=array_len {
+            let value: LuaValue = table.raw_get(index)?;
+            self.write_value(&value, &format!("{}[{}]", path, index), depth + 1)?;
+        }
+
+        let mut entries = Vec::new();
+        for pair in table.clone().pairs::<LuaValue, LuaValue>() {
+            let (key, value) = pair?;
+            let in_array = match key {
+                LuaValue::Integer(key) => key >= 1 && (key as usize) <= array_len,
+                _ => false,
+            };
+            if !in_array {
+                entries.push((key, value));
+            }
+        }
+        self.write_len(entries.len());
+        for (key, value) in entries {
+            let entry_path = match &key {
+                LuaValue::String(key) => format!("{}.{}", path, key.to_string_lossy()),
+                LuaValue::Integer(key) => format!("{}[{}]", path, key),
+                other => format!("{}[<{}>]", path, other.type_name()),
+            };
+            self.write_value(&key, &format!("a key in {}", path), depth + 1)?;
+            self.write_value(&value, &entry_path, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// serializes `value` to send to another thread's Luau state; `function_name` prefixes any errors
+pub fn serialize(value: &LuaValue, function_name: &str) -> LuaResult<ThreadMessage> {
+    let mut serializer = Serializer {
+        function_name,
+        bytes: Vec::new(),
+        shared: Vec::new(),
+        seen_tables: HashMap::new(),
+    };
+    serializer.write_value(value, "data", 0)?;
+    Ok(ThreadMessage { bytes: serializer.bytes, shared: serializer.shared })
+}
+
+struct Deserializer<'a> {
+    luau: &'a Lua,
+    bytes: &'a [u8],
+    shared: &'a [SharedValue],
+    position: usize,
+    tables: Vec<LuaTable>,
+}
+
+impl Deserializer<'_> {
+    fn take(&mut self, len: usize) -> LuaResult<&[u8]> {
+        match self.bytes.get(self.position..self.position + len) {
+            Some(taken) => {
+                self.position += len;
+                Ok(taken)
+            },
+            None => wrap_err!("thread message ended unexpectedly at byte {}", self.position),
+        }
+    }
+
+    fn take_array<const N: usize>(&mut self) -> LuaResult<[u8; N]> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn read_len(&mut self) -> LuaResult<usize> {
+        Ok(u32::from_le_bytes(self.take_array()?) as usize)
+    }
+
+    fn read_value(&mut self) -> LuaValueResult {
+        let tag = self.take_array::<1>()?[0];
+        Ok(match tag {
+            TAG_NIL => LuaNil,
+            TAG_FALSE => LuaValue::Boolean(false),
+            TAG_TRUE => LuaValue::Boolean(true),
+            TAG_INTEGER => LuaValue::Integer(LuaInteger::from_le_bytes(self.take_array()?)),
+            TAG_NUMBER => LuaValue::Number(f64::from_le_bytes(self.take_array()?)),
+            TAG_VECTOR => {
+                let x = f32::from_le_bytes(self.take_array()?);
+                let y = f32::from_le_bytes(self.take_array()?);
+                let z = f32::from_le_bytes(self.take_array()?);
+                LuaValue::Vector(mlua::Vector::new(x, y, z))
+            },
+            TAG_STRING => {
+                let len = self.read_len()?;
+                let luau = self.luau;
+                LuaValue::String(luau.create_string(self.take(len)?)?)
+            },
+            TAG_BUFFER => {
+                let len = self.read_len()?;
+                let luau = self.luau;
+                LuaValue::Buffer(luau.create_buffer(self.take(len)?)?)
+            },
+            TAG_TABLE => {
+                let array_len = self.read_len()?;
+                let table = self.luau.create_table()?;
+                self.tables.push(table.clone());
+                // arrays can have holes, so they're set by index
+                for index in 1..=array_len {
+                    let value = self.read_value()?;
+                    table.raw_set(index, value)?;
+                }
+                let entries = self.read_len()?;
+                for _ in 0..entries {
+                    let key = self.read_value()?;
+                    let value = self.read_value()?;
+                    table.raw_set(key, value)?;
+                }
+                LuaValue::Table(table)
+            },
+            TAG_TABLE_REF => {
+                let index = u32::from_le_bytes(self.take_array()?) as usize;
+                match self.tables.get(index) {
+                    Some(table) => LuaValue::Table(table.clone()),
+                    None => {
+                        return wrap_err!("thread message refers to table {} before it was sent", index);
+                    }
+                }
+            },
+            TAG_SHARED => {
+                let index = u32::from_le_bytes(self.take_array()?) as usize;
+                match self.shared.get(index) {
+                    Some(shared) => LuaValue::UserData(shared.to_userdata(self.luau)?),
+                    None => {
+                        return wrap_err!("thread message refers to shared value {}, but only has {}", index, self.shared.len());
+                    }
+                }
+            },
+            other => {
+                return wrap_err!("thread message has unknown tag {} at byte {}", other, self.position - 1);
+            }
+        })
+    }
+}
+
+/// rebuilds a value serialized by `serialize` in another Luau state
+pub fn deserialize(luau: &Lua, message: &ThreadMessage) -> LuaValueResult {
+    let mut deserializer = Deserializer {
+        luau,
+        bytes: &message.bytes,
+        shared: &message.shared,
+        position: 0,
+        tables: Vec::new(),
+    };
+    deserializer.read_value()
+}
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Barrier, Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread::{self, ThreadId};
+use std::time::Instant;
+
+use mlua::prelude::*;
+
+use crate::{colors, LuaValueResult, std_thread_serialize};
+use crate::std_thread::parse_timeout;
+use crate::std_thread_serialize::ThreadMessage;
+
+/// the largest integer a Luau number (an f64) can hold exactly
+const MAX_SAFE_INTEGER: f64 = 9007199254740992.0;
+
+// nothing behind these locks can be left half-updated by a panicking thread, so poisoned locks are still safe to use
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn read<T>(rwlock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    rwlock.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn write<T>(rwlock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    rwlock.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn integer(value: LuaValue, function_name: &str) -> LuaResult<i64> {
+    match value {
+        LuaValue::Integer(n) => Ok(n as i64),
+        LuaValue::Number(n) if n.fract() == 0.0 && n.abs() <= MAX_SAFE_INTEGER => Ok(n as i64),
+        other => {
+            wrap_err!("{} expected an integer, got: {:#?}", function_name, other)
+        }
+    }
+}
+
+/// an integer shared between threads; sending it to another thread shares it rather than copying it
+#[derive(Clone)]
+pub struct ThreadAtomic(Arc<AtomicI64>);
+
+impl LuaUserData for ThreadAtomic {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("get", |_luau, this, ()| Ok(this.0.load(Ordering::SeqCst)));
+        methods.add_method("set", |_luau, this, value: LuaValue| {
+            this.0.store(integer(value, "ThreadAtomic:set(value: number)")?, Ordering::SeqCst);
+            Ok(())
+        });
+        // returns the new value, since that's usually what you want to check (like whether you were the last to finish)
+        methods.add_method("add", |_luau, this, value: LuaValue| {
+            let value = integer(value, "ThreadAtomic:add(value: number)")?;
+            Ok(this.0.fetch_add(value, Ordering::SeqCst).wrapping_add(value))
+        });
+        methods.add_method("compare_and_swap", |_luau, this, (expected, new): (LuaValue, LuaValue)| {
+            let expected = integer(expected, "ThreadAtomic:compare_and_swap(expected: number, new)")?;
+            let new = integer(new, "ThreadAtomic:compare_and_swap(expected, new: number)")?;
+            Ok(this.0.compare_exchange(expected, new, Ordering::SeqCst, Ordering::SeqCst).is_ok())
+        });
+        methods.add_meta_method(LuaMetaMethod::ToString, |_luau, this, ()| {
+            Ok(format!("ThreadAtomic({})", this.0.load(Ordering::SeqCst)))
+        });
+    }
+}
+
+/// `thread.atomic(initial: number?)`
+pub fn thread_atomic(luau: &Lua, initial: Option<LuaValue>) -> LuaValueResult {
+    let initial = match initial {
+        None | Some(LuaNil) => 0,
+        Some(initial) => integer(initial, "thread.atomic(initial: number?)")?,
+    };
+    Ok(LuaValue::UserData(luau.create_userdata(ThreadAtomic(Arc::new(AtomicI64::new(initial))))?))
+}
+
+/// keys shared tables can use; floats are stored by their bits, with -0 folded into 0 like Luau does
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum SharedKey {
+    String(Vec<u8>),
+    Number(u64),
+    Boolean(bool),
+}
+
+impl SharedKey {
+    fn from_value(value: LuaValue, function_name: &str) -> LuaResult<Self> {
+        let number = |n: f64| if n == 0.0 { 0.0_f64.to_bits() } else { n.to_bits() };
+        match value {
+            LuaValue::String(key) => Ok(SharedKey::String(key.as_bytes().to_vec())),
+            LuaValue::Integer(key) => Ok(SharedKey::Number(number(key as f64))),
+            LuaValue::Number(key) if !key.is_nan() => Ok(SharedKey::Number(number(key))),
+            LuaValue::Boolean(key) => Ok(SharedKey::Boolean(key)),
+            other => {
+                wrap_err!("{} expected key to be a string, number, or boolean, got: {:#?}", function_name, other)
+            }
+        }
+    }
+
+    fn to_value(&self, luau: &Lua) -> LuaValueResult {
+        Ok(match self {
+            SharedKey::String(key) => LuaValue::String(luau.create_string(key)?),
+            SharedKey::Number(bits) => LuaValue::Number(f64::from_bits(*bits)),
+            SharedKey::Boolean(key) => LuaValue::Boolean(*key),
+        })
+    }
+}
+
+/// a table shared between threads; values are copied in and out like thread messages
+#[derive(Clone)]
+pub struct SharedTable {
+    entries: Arc<RwLock<HashMap<SharedKey, ThreadMessage>>>,
+    /// the thread running an `update` function, which holds the write lock until it returns
+    updating: Arc<Mutex<Option<ThreadId>>>,
+}
+
+impl SharedTable {
+    /// using the table from inside its own `update` function would wait on a lock we're holding forever
+    fn check_not_updating(&self, function_name: &str) -> LuaResult<()> {
+        if *lock(&self.updating) == Some(thread::current().id()) {
+            return wrap_err!("{}: can't use a SharedTable from inside its own update function", function_name);
+        }
+        Ok(())
+    }
+}
+
+impl LuaUserData for SharedTable {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("get", |luau, this, key: LuaValue| -> LuaValueResult {
+            let key = SharedKey::from_value(key, "SharedTable:get")?;
+            this.check_not_updating("SharedTable:get")?;
+            match read(&this.entries).get(&key) {
+                Some(message) => std_thread_serialize::deserialize(luau, message),
+                None => Ok(LuaNil),
+            }
+        });
+        methods.add_method("set", |_luau, this, (key, value): (LuaValue, LuaValue)| {
+            let key = SharedKey::from_value(key, "SharedTable:set")?;
+            this.check_not_updating("SharedTable:set")?;
+            if value.is_nil() {
+                write(&this.entries).remove(&key);
+            } else {
+                let message = std_thread_serialize::serialize(&value, "SharedTable:set")?;
+                write(&this.entries).insert(key, message);
+            }
+            Ok(())
+        });
+        // holds the table's lock while `updater` runs so no other thread can change the key in the meantime,
+        // which also means `updater` can't use this table itself
+        methods.add_method("update", |luau, this, (key, updater): (LuaValue, LuaFunction)| -> LuaValueResult {
+            let key = SharedKey::from_value(key, "SharedTable:update")?;
+            this.check_not_updating("SharedTable:update")?;
+            let mut table = write(&this.entries);
+            let current = match table.get(&key) {
+                Some(message) => std_thread_serialize::deserialize(luau, message)?,
+                None => LuaNil,
+            };
+            *lock(&this.updating) = Some(thread::current().id());
+            let updated = updater.call::<LuaValue>(current);
+            *lock(&this.updating) = None;
+            let updated = updated?;
+            if updated.is_nil() {
+                table.remove(&key);
+            } else {
+                table.insert(key, std_thread_serialize::serialize(&updated, "SharedTable:update")?);
+            }
+            Ok(updated)
+        });
+        methods.add_method("keys", |luau, this, ()| {
+            let keys = luau.create_table()?;
+            this.check_not_updating("SharedTable:keys")?;
+            for key in read(&this.entries).keys() {
+                keys.raw_push(key.to_value(luau)?)?;
+            }
+            Ok(keys)
+        });
+        methods.add_meta_method(LuaMetaMethod::ToString, |_luau, this, ()| {
+            this.check_not_updating("SharedTable:__tostring")?;
+            Ok(format!("SharedTable({} keys)", read(&this.entries).len()))
+        });
+    }
+}
+
+/// `thread.shared_table()`
+pub fn thread_shared_table(luau: &Lua, _value: LuaValue) -> LuaValueResult {
+    Ok(LuaValue::UserData(luau.create_userdata(SharedTable {
+        entries: Arc::new(RwLock::new(HashMap::new())),
+        updating: Arc::new(Mutex::new(None)),
+    })?))
+}
+
+enum OnceState {
+    Empty,
+    /// the thread running `get_or_init`'s initializer; other threads wait for it
+    Initializing(ThreadId),
+    Set(ThreadMessage),
+}
+
+/// a value shared between threads that can only be set once, like a config every worker loads lazily
+#[derive(Clone)]
+pub struct ThreadOnce(Arc<(Mutex<OnceState>, Condvar)>);
+
+impl LuaUserData for ThreadOnce {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("get", |luau, this, ()| -> LuaValueResult {
+            match &*lock(&this.0.0) {
+                OnceState::Set(message) => std_thread_serialize::deserialize(luau, message),
+                OnceState::Empty | OnceState::Initializing(_) => Ok(LuaNil),
+            }
+        });
+        // returns whether `value` was set, so only one of the threads racing to set it gets true
+        methods.add_method("set", |_luau, this, value: LuaValue| -> LuaResult<bool> {
+            let message = std_thread_serialize::serialize(&value, "ThreadOnce:set")?;
+            let (state, changed) = &*this.0;
+            let mut state = lock(state);
+            if !matches!(*state, OnceState::Empty) {
+                return Ok(false);
+            }
+            *state = OnceState::Set(message);
+            changed.notify_all();
+            Ok(true)
+        });
+        // only one thread runs `initializer`; the rest wait for its value. if it errors, the next caller tries again
+        methods.add_method("get_or_init", |luau, this, initializer: LuaFunction| -> LuaValueResult {
+            let (state, changed) = &*this.0;
+            let mut guard = lock(state);
+            loop {
+                match &*guard {
+                    OnceState::Set(message) => return std_thread_serialize::deserialize(luau, message),
+                    OnceState::Initializing(id) if *id == thread::current().id() => {
+                        return wrap_err!("ThreadOnce:get_or_init: can't use a ThreadOnce from inside its own initializer");
+                    },
+                    OnceState::Initializing(_) => guard = changed.wait(guard).unwrap_or_else(|poisoned| poisoned.into_inner()),
+                    OnceState::Empty => break,
+                }
+            }
+            *guard = OnceState::Initializing(thread::current().id());
+            drop(guard);
+
+            let initialized = initializer.call::<LuaValue>(())
+                .and_then(|value| Ok((std_thread_serialize::serialize(&value, "ThreadOnce:get_or_init")?, value)));
+            let mut guard = lock(state);
+            let result = match initialized {
+                Ok((message, value)) => {
+                    *guard = OnceState::Set(message);
+                    Ok(value)
+                },
+                Err(err) => {
+                    *guard = OnceState::Empty;
+                    Err(err)
+                }
+            };
+            changed.notify_all();
+            result
+        });
+        methods.add_method("is_set", |_luau, this, ()| Ok(matches!(*lock(&this.0.0), OnceState::Set(_))));
+    }
+}
+
+/// `thread.once()`
+pub fn thread_once(luau: &Lua, _value: LuaValue) -> LuaValueResult {
+    Ok(LuaValue::UserData(luau.create_userdata(ThreadOnce(Arc::new((Mutex::new(OnceState::Empty), Condvar::new()))))?))
+}
+
+/// makes `count` threads wait for each other
+#[derive(Clone)]
+pub struct ThreadBarrier {
+    barrier: Arc<Barrier>,
+    count: usize,
+}
+
+impl LuaUserData for ThreadBarrier {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("count", |_luau, this| Ok(this.count));
+    }
+
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        // true for exactly one of the threads released together, so it can do any once-per-round work
+        methods.add_method("wait", |_luau, this, ()| Ok(this.barrier.wait().is_leader()));
+    }
+}
+
+/// `thread.barrier(count: number)`
+pub fn thread_barrier(luau: &Lua, count: LuaValue) -> LuaValueResult {
+    let count = match count {
+        LuaValue::Integer(count) if count > 0 => count as usize,
+        other => {
+            return wrap_err!("thread.barrier(count: number) expected count to be a positive integer, got: {:#?}", other);
+        }
+    };
+    Ok(LuaValue::UserData(luau.create_userdata(ThreadBarrier { barrier: Arc::new(Barrier::new(count)), count })?))
+}
+
+/// limits how many threads can hold one of its permits at once
+#[derive(Clone)]
+pub struct ThreadSemaphore(Arc<(Mutex<usize>, Condvar)>);
+
+impl LuaUserData for ThreadSemaphore {
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("acquire", |_luau, this, timeout: Option<LuaValue>| -> LuaResult<bool> {
+            let deadline = parse_timeout(timeout, "ThreadSemaphore:acquire")?.map(|timeout| Instant::now() + timeout);
+            let (permits, available) = &*this.0;
+            let mut permits = lock(permits);
+            while *permits == 0 {
+                match deadline {
+                    Some(deadline) => {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            return Ok(false);
+                        }
+                        permits = available.wait_timeout(permits, deadline - now).unwrap_or_else(|poisoned| poisoned.into_inner()).0;
+                    },
+                    None => permits = available.wait(permits).unwrap_or_else(|poisoned| poisoned.into_inner()),
+                }
+            }
+            *permits -= 1;
+            Ok(true)
+        });
+        methods.add_method("release", |_luau, this, ()| {
+            let (permits, available) = &*this.0;
+            *lock(permits) += 1;
+            available.notify_one();
+            Ok(())
+        });
+        methods.add_method("available", |_luau, this, ()| Ok(*lock(&this.0.0)));
+    }
+}
+
+/// `thread.semaphore(permits: number)`
+pub fn thread_semaphore(luau: &Lua, permits: LuaValue) -> LuaValueResult {
+    let permits = match permits {
+        LuaValue::Integer(permits) if permits >= 0 => permits as usize,
+        other => {
+            return wrap_err!("thread.semaphore(permits: number) expected permits to be a non-negative integer, got: {:#?}", other);
+        }
+    };
+    Ok(LuaValue::UserData(luau.create_userdata(ThreadSemaphore(Arc::new((Mutex::new(permits), Condvar::new()))))?))
+}
@@ -1,14 +1,14 @@
-use crate::{table_helpers::TableBuilder, wrap_err, colors};
+use crate::{table_helpers::TableBuilder, std_process_signals, wrap_err, colors};
 use crate::LuaValueResult;
-use std::{thread, time::Duration};
+use std::time::Duration;
 
 use chrono::Local;
 use mlua::prelude::*;
 
-fn time_wait(_luau: &Lua, seconds: LuaNumber) -> LuaValueResult {
+fn time_wait(luau: &Lua, seconds: LuaNumber) -> LuaValueResult {
     let millis = (seconds * 1000.0) as u64;
     let dur = Duration::from_millis(millis);
-    thread::sleep(dur);
+    std_process_signals::sleep(luau, dur)?;
     Ok(LuaValue::Boolean(true)) // return true to ensure while time.wait(n) works
 }
 